@@ -0,0 +1,124 @@
+//! Batch generation of several prompts with shared parameters
+
+use crate::inference::EventSink;
+use crate::{default_model, generate_text, load_model, GenerateArgs};
+use serde_json::{json, Value};
+use std::time::Instant;
+
+/// Split batch arguments into one `GenerateArgs` per prompt.
+///
+/// All fields other than `prompts` are shared by every prompt.
+pub fn parse_batch_args(args: &str) -> Result<Vec<GenerateArgs>, String> {
+    let mut args: Value =
+        serde_json::from_str(args).map_err(|e| format!("Invalid arguments: {}", e))?;
+    let shared = args
+        .as_object_mut()
+        .ok_or_else(|| "Invalid arguments: expected an object".to_string())?;
+
+    let prompts = match shared.remove("prompts") {
+        Some(Value::Array(prompts)) => prompts,
+        Some(_) => return Err("Invalid arguments: prompts must be an array".to_string()),
+        None => return Err("Invalid arguments: missing field `prompts`".to_string()),
+    };
+
+    prompts
+        .into_iter()
+        .map(|prompt| {
+            let mut item = shared.clone();
+            item.insert("prompt".to_string(), prompt);
+            let args: GenerateArgs = serde_json::from_value(Value::Object(item))
+                .map_err(|e| format!("Invalid arguments: {}", e))?;
+            args.check_limits()?;
+            Ok(args)
+        })
+        .collect()
+}
+
+/// Generate every prompt in order, returning one entry per prompt.
+///
+/// A failing prompt yields `{"error": ...}` in its slot instead of aborting the
+/// batch. An empty batch returns `[]`.
+pub fn generate_batch(batch: &[GenerateArgs]) -> Result<String, String> {
+    let load_error = load_shared_model(batch);
+    let results: Vec<Value> = batch
+        .iter()
+        .map(|args| generate_one(args, load_error.as_deref()))
+        .collect();
+    serde_json::to_string(&results).map_err(|e| e.to_string())
+}
+
+/// Streaming variant of `generate_batch`.
+///
+/// Emits `{"event":"result","index",...}` for each prompt followed by
+/// `{"event":"progress","completed","total","elapsed_ms"}`, and returns a
+/// final summary. Stops early if the consumer returns `false`. An empty batch
+/// emits no events and returns the summary.
+pub fn generate_batch_stream(
+    batch: &[GenerateArgs],
+    on_event: EventSink,
+) -> Result<String, String> {
+    let start = Instant::now();
+    let total = batch.len();
+    let mut completed = 0;
+    let load_error = load_shared_model(batch);
+
+    for (index, args) in batch.iter().enumerate() {
+        let mut event = generate_one(args, load_error.as_deref());
+        event["event"] = json!("result");
+        event["index"] = json!(index);
+        if !on_event(&event.to_string()) {
+            break;
+        }
+
+        completed += 1;
+        let progress = json!({
+            "event": "progress",
+            "completed": completed,
+            "total": total,
+            "elapsed_ms": start.elapsed().as_millis() as u64,
+        });
+        if !on_event(&progress.to_string()) {
+            break;
+        }
+    }
+
+    let summary = json!({
+        "event": "done",
+        "completed": completed,
+        "total": total,
+        "elapsed_ms": start.elapsed().as_millis() as u64,
+    });
+    Ok(summary.to_string())
+}
+
+/// Load the model every prompt shares once, before the first prompt.
+///
+/// Returns the load error so each slot can report it without retrying a
+/// slow failing load per prompt. With a fallback model, loading is left to
+/// the per-prompt path, which knows how to fall back.
+fn load_shared_model(batch: &[GenerateArgs]) -> Option<String> {
+    let first = batch.first()?;
+    if first.fallback_model_path.is_some() {
+        return None;
+    }
+    let path = match first.model_path.as_str() {
+        "" => match default_model() {
+            Ok(path) => path,
+            Err(e) => return Some(e),
+        },
+        path => path.to_string(),
+    };
+    load_model(&path, None).err().map(String::from)
+}
+
+fn generate_one(args: &GenerateArgs, load_error: Option<&str>) -> Value {
+    if let Some(e) = load_error {
+        return json!({ "error": e });
+    }
+    match generate_text(args)
+        .and_then(|output| serde_json::from_str::<Value>(&output).map_err(|e| e.to_string()))
+    {
+        Ok(result) => result,
+        Err(e) => json!({ "error": e }),
+    }
+}
@@ -0,0 +1,124 @@
+//! Repeated fixed-prompt generation for comparing models
+//!
+//! Each iteration is a normal `generate` call, so the numbers come from the
+//! same `elapsed_ms` / `tokens_per_second` metrics the responses report.
+
+use crate::{generate_text, GenerateArgs, SamplingParams};
+use serde_json::{json, Value};
+
+/// Timed iterations when `--iterations` isn't given
+pub const DEFAULT_ITERATIONS: usize = 5;
+
+/// Token limit per iteration when `--max-tokens` isn't given
+pub const DEFAULT_MAX_TOKENS: usize = 128;
+
+/// Fixed so every iteration samples the same continuation
+const BENCHMARK_SEED: u64 = 0;
+
+const BENCHMARK_PROMPT: &str =
+    "Explain in a few paragraphs how a compiler turns source code into machine code.";
+
+/// Run one untimed warm-up (which also loads the model) plus `iterations`
+/// timed generations, and return aggregate statistics as JSON
+pub fn run(model_path: &str, iterations: usize, max_tokens: usize) -> Result<String, String> {
+    if iterations == 0 {
+        return Err("Invalid arguments: --iterations must be at least 1".to_string());
+    }
+    let args = GenerateArgs {
+        model_path: model_path.to_string(),
+        prompt: BENCHMARK_PROMPT.to_string(),
+        sampling: SamplingParams {
+            max_tokens: Some(max_tokens),
+            seed: Some(BENCHMARK_SEED),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let warmup = generate(&args)?;
+
+    let mut latencies = Vec::with_capacity(iterations);
+    let mut throughputs = Vec::with_capacity(iterations);
+    let mut total_tokens = 0;
+    for _ in 0..iterations {
+        let result = generate(&args)?;
+        latencies.push(result["elapsed_ms"].as_u64().unwrap_or(0));
+        throughputs.extend(result["tokens_per_second"].as_f64());
+        total_tokens += result["tokens_generated"].as_u64().unwrap_or(0);
+    }
+
+    let stats = LatencyStats::new(&mut latencies);
+    let result = json!({
+        "model_path": model_path,
+        "iterations": iterations,
+        "max_tokens": max_tokens,
+        "prompt_tokens": warmup["prompt_tokens"],
+        "warmup_load_ms": warmup.get("load_ms"),
+        "latency_ms": {
+            "mean": stats.mean,
+            "median": stats.median,
+            "p95": stats.p95,
+        },
+        "avg_tokens_per_second": mean(&throughputs),
+        "total_tokens": total_tokens,
+    });
+    Ok(serde_json::to_string(&result).unwrap_or_default())
+}
+
+fn generate(args: &GenerateArgs) -> Result<Value, String> {
+    let output = generate_text(args)?;
+    serde_json::from_str(&output).map_err(|e| e.to_string())
+}
+
+#[derive(Debug, PartialEq)]
+struct LatencyStats {
+    mean: f64,
+    median: f64,
+    p95: u64,
+}
+
+impl LatencyStats {
+    /// Summarize non-empty `latencies` (sorted in place); p95 is nearest-rank
+    fn new(latencies: &mut [u64]) -> Self {
+        latencies.sort_unstable();
+        let n = latencies.len();
+        let median = if n.is_multiple_of(2) {
+            (latencies[n / 2 - 1] + latencies[n / 2]) as f64 / 2.0
+        } else {
+            latencies[n / 2] as f64
+        };
+        let p95_rank = (n * 95).div_ceil(100).max(1);
+        Self {
+            mean: latencies.iter().sum::<u64>() as f64 / n as f64,
+            median,
+            p95: latencies[p95_rank - 1],
+        }
+    }
+}
+
+fn mean(values: &[f64]) -> Option<f64> {
+    (!values.is_empty()).then(|| values.iter().sum::<f64>() / values.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latency_stats_use_nearest_rank_p95() {
+        let mut latencies = vec![50, 10, 40, 20, 30];
+        assert_eq!(
+            LatencyStats::new(&mut latencies),
+            LatencyStats {
+                mean: 30.0,
+                median: 30.0,
+                p95: 50,
+            }
+        );
+
+        let mut latencies: Vec<u64> = (1..=20).collect();
+        let stats = LatencyStats::new(&mut latencies);
+        assert_eq!(stats.median, 10.5);
+        assert_eq!(stats.p95, 19);
+    }
+}
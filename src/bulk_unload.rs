@@ -0,0 +1,128 @@
+//! Unloading every model that matches a filter (CLI `unload --older-than`,
+//! `unload --matching`)
+//!
+//! `--older-than` compares against each model's last use, `--matching` is a
+//! glob (`*`, `?`) tried on the model path and its aliases; given both, a
+//! model must match both. Models with a generation running or queued are
+//! kept and reported as skipped.
+
+use crate::{logging, polling, MODELS};
+use serde_json::{json, Value};
+use std::time::{Duration, SystemTime};
+
+/// Which loaded models to unload
+pub struct Filter<'a> {
+    pub older_than: Option<Duration>,
+    pub matching: Option<&'a str>,
+}
+
+/// Unload the models `filter` selects, as `{"unloaded", "skipped", "message"}`
+pub fn unload(filter: &Filter) -> Result<Value, String> {
+    let mut models = MODELS.entries()?;
+    models.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let now = SystemTime::now();
+
+    let mut unloaded = Vec::new();
+    let mut skipped = Vec::new();
+    for (key, model) in models {
+        let idle_for = now
+            .duration_since(model.last_access_at())
+            .unwrap_or_default();
+        if filter.older_than.is_some_and(|age| idle_for < age) {
+            continue;
+        }
+        if let Some(pattern) = filter.matching {
+            let aliases = MODELS.aliases_of(&key);
+            let names = std::iter::once(&key).chain(&aliases);
+            if !names.into_iter().any(|name| glob_matches(pattern, name)) {
+                continue;
+            }
+        }
+        if !MODELS.remove_if_idle(&key) {
+            skipped.push(json!({ "model": key, "reason": "in_flight" }));
+            continue;
+        }
+        logging::model_unloaded(&key, "unload");
+        polling::fail_jobs(&key, "was unloaded while the job was running");
+        unloaded.push(key);
+    }
+
+    let message = match (unloaded.len(), skipped.len()) {
+        (0, 0) => "no models matched".to_string(),
+        (n, 0) => format!("Unloaded {} model(s)", n),
+        (n, busy) => format!("Unloaded {} model(s); {} busy model(s) kept", n, busy),
+    };
+    Ok(json!({ "unloaded": unloaded, "skipped": skipped, "message": message }))
+}
+
+/// Parse `500ms`, `90s`, `30m`, `2h` or `1d`; a bare number is seconds
+pub fn parse_duration(text: &str) -> Option<Duration> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: u64 = number.parse().ok()?;
+    let unit_ms = match unit {
+        "ms" => 1,
+        "" | "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => return None,
+    };
+    number.checked_mul(unit_ms).map(Duration::from_millis)
+}
+
+/// Whether `name` matches `pattern`, where `*` is any run of characters and
+/// `?` any one character
+pub fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*`, and where in `name` it started matching
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p + 1, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                // Let the last `*` take one more character and retry
+                Some((after, start)) => {
+                    p = after;
+                    n = start + 1;
+                    star = Some((after, start + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs_and_durations_parse_like_the_cli_documents() {
+        assert!(glob_matches("*checkpoint-*", "/runs/a/checkpoint-400"));
+        assert!(glob_matches("ckpt-?", "ckpt-7"));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("ckpt-?", "ckpt-17"));
+        assert!(!glob_matches("*.gguf", "/models/llama.safetensors"));
+
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Some(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(7200)));
+        assert_eq!(parse_duration("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_duration("1w"), None);
+        assert_eq!(parse_duration("m"), None);
+    }
+}
@@ -0,0 +1,93 @@
+//! Cancellation of in-flight generations by caller-chosen `request_id`
+//!
+//! A generation started with a `request_id` registers a flag for as long as
+//! it runs. `cancel` sets the flag and the decode loop stops after the
+//! current token, returning the partial output.
+
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Cancellation flags of running generations (request_id -> flag)
+static ACTIVE: Mutex<BTreeMap<String, Arc<AtomicBool>>> = Mutex::new(BTreeMap::new());
+
+/// Keeps a request cancellable until dropped
+pub struct Registration {
+    request_id: String,
+    flag: Arc<AtomicBool>,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut active = active();
+        if active
+            .get(&self.request_id)
+            .is_some_and(|flag| Arc::ptr_eq(flag, &self.flag))
+        {
+            active.remove(&self.request_id);
+        }
+    }
+}
+
+/// Make `request_id` cancellable; ids must be unique among running generations
+pub fn register(request_id: &str) -> Result<Registration, String> {
+    if request_id.is_empty() {
+        return Err("Invalid arguments: request_id must not be empty".to_string());
+    }
+    let mut active = active();
+    if active.contains_key(request_id) {
+        return Err(format!(
+            "Invalid arguments: request_id {:?} is already used by a running generation",
+            request_id
+        ));
+    }
+    let flag = Arc::new(AtomicBool::new(false));
+    active.insert(request_id.to_string(), flag.clone());
+    Ok(Registration {
+        request_id: request_id.to_string(),
+        flag,
+    })
+}
+
+/// Cancellation flag of a running request
+pub fn flag(request_id: &str) -> Option<Arc<AtomicBool>> {
+    active().get(request_id).cloned()
+}
+
+/// Ask a running generation to stop.
+///
+/// Unknown and already finished ids are not an error; the status says which
+/// case applied.
+pub fn cancel(request_id: &str) -> Value {
+    let status = match active().get(request_id) {
+        Some(flag) => {
+            flag.store(true, Ordering::SeqCst);
+            "cancelled"
+        }
+        None => "not_running",
+    };
+    json!({ "request_id": request_id, "status": status })
+}
+
+fn active() -> MutexGuard<'static, BTreeMap<String, Arc<AtomicBool>>> {
+    ACTIVE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_sets_the_flag_of_a_running_request_only() {
+        let registration = register("cancel-test").unwrap();
+        assert!(register("cancel-test").is_err());
+
+        assert_eq!(cancel("cancel-test")["status"], "cancelled");
+        assert!(registration.flag.load(Ordering::SeqCst));
+
+        drop(registration);
+        assert!(flag("cancel-test").is_none());
+        assert_eq!(cancel("cancel-test")["status"], "not_running");
+    }
+}
@@ -0,0 +1,86 @@
+//! Runtime feature discovery for hosts (`capabilities`)
+//!
+//! Keep these lists in step with `GenerateArgs`, `SamplingParams` and the
+//! method tables in `inference.rs`.
+
+use crate::inference::{inference_list_methods, STREAM_METHODS};
+use crate::{model_files, MODELS};
+use serde_json::json;
+use std::path::Path;
+
+/// `generate` parameters the plugin applies
+const GENERATE_PARAMS: &[&str] = &[
+    "max_tokens",
+    "temperature",
+    "top_p",
+    "top_k",
+    "seed",
+    "system",
+    "stop",
+    "replace_stop",
+    "ignore_eos",
+    "timeout_ms",
+    "truncate",
+    "retry_on_gibberish",
+    "max_tokens_ratio",
+    "append_token",
+    "ban_substrings",
+    "stop_on_json_complete",
+    "json_schema",
+    "parse_markdown",
+    "expect_script",
+    "include_output_hash",
+    "include_rendered_prompt",
+    "fallback_model_path",
+    "session_id",
+    "request_id",
+    "n",
+    "validate_only",
+];
+
+/// Parameters that are validated but rejected as unsupported by the engine
+/// (repetition penalties only at non-neutral values)
+const UNSUPPORTED_PARAMS: &[&str] = &[
+    "penalty_last_n",
+    "repetition_penalty",
+    "frequency_penalty",
+    "allowed_tokens",
+    "include_prompt_perplexity",
+    "logprobs",
+    "grammar",
+    "top_logprobs",
+    "images",
+    "draft_model_path",
+    "prefix_cache",
+];
+
+/// Service version, methods, parameters, and per-model context lengths
+pub fn capabilities() -> String {
+    let methods: Vec<&str> = inference_list_methods().iter().map(|m| m.name).collect();
+
+    let mut keys = MODELS.keys();
+    keys.sort();
+    let models: Vec<_> = keys
+        .into_iter()
+        .map(|key| {
+            // Unreadable metadata just leaves the length unknown
+            let context_length = model_files::read_context_length(Path::new(&key))
+                .ok()
+                .flatten();
+            json!({ "path": key, "context_length": context_length })
+        })
+        .collect();
+
+    let result = json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "methods": methods,
+        "streaming": {
+            "available": true,
+            "methods": STREAM_METHODS,
+        },
+        "generate_params": GENERATE_PARAMS,
+        "unsupported_params": UNSUPPORTED_PARAMS,
+        "models": models,
+    });
+    serde_json::to_string(&result).unwrap_or_default()
+}
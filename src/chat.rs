@@ -0,0 +1,385 @@
+//! Chat completion over role-based messages
+//!
+//! Messages are rendered with the model's own chat template (from
+//! `tokenizer_config.json`) and then generated through the normal generate path.
+
+use crate::error::PluginLlmError;
+use crate::model_files::{self, ChatTemplate};
+use crate::{
+    check_max_tokens, check_prompt_bytes, count_prompt_tokens, generate_text, model_key,
+    GenerateArgs, SamplingParams,
+};
+use minijinja::{context, Environment, Error, ErrorKind};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::Path;
+
+/// Default fraction of the context window the prompt plus reply may fill
+const DEFAULT_COMPACT_THRESHOLD: f32 = 1.0;
+
+/// Default token limit for the summary of folded turns
+const DEFAULT_SUMMARY_MAX_TOKENS: usize = 256;
+
+/// Tokens reserved for the reply when `max_tokens` isn't given
+const DEFAULT_REPLY_RESERVE: usize = 512;
+
+const SUMMARY_PREFIX: &str = "Summary of the earlier conversation: ";
+
+/// Roles a chat message may have
+const ROLES: &[&str] = &["system", "user", "assistant"];
+
+/// A single chat turn
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: String,
+    pub content: String,
+}
+
+/// Arguments for `chat`
+#[derive(Debug, Deserialize)]
+pub struct ChatArgs {
+    pub model_path: String,
+    pub messages: Vec<ChatMessage>,
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
+    /// Summarize the oldest turns instead of failing when the history overflows
+    #[serde(default)]
+    pub auto_compact: bool,
+    /// Fraction of the context window the prompt plus reply may fill (0, 1]
+    #[serde(default)]
+    pub compact_threshold: Option<f32>,
+    /// Token limit for the summary that replaces folded turns
+    #[serde(default)]
+    pub summary_max_tokens: Option<usize>,
+    /// System instruction placed before the messages
+    #[serde(default)]
+    pub system: Option<String>,
+}
+
+/// Reject an empty conversation or a message with an unknown role
+fn validate_messages(messages: &[ChatMessage]) -> Result<(), String> {
+    if messages.is_empty() {
+        return Err("Invalid arguments: messages must not be empty".to_string());
+    }
+    if let Some((index, message)) = messages
+        .iter()
+        .enumerate()
+        .find(|(_, m)| !ROLES.contains(&m.role.as_str()))
+    {
+        return Err(format!(
+            "Invalid arguments: messages[{}] has unknown role {:?} (expected one of {})",
+            index,
+            message.role,
+            ROLES.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Render messages through a chat template, ending with the assistant prompt
+pub fn render(template: &ChatTemplate, messages: &[ChatMessage]) -> Result<String, String> {
+    let mut env = Environment::new();
+    env.set_unknown_method_callback(minijinja_contrib::pycompat::unknown_method_callback);
+    env.add_function(
+        "raise_exception",
+        |message: String| -> Result<String, Error> {
+            Err(Error::new(ErrorKind::InvalidOperation, message))
+        },
+    );
+
+    env.template_from_str(&template.template)
+        .and_then(|t| {
+            t.render(context! {
+                messages => messages,
+                add_generation_prompt => true,
+                bos_token => template.bos_token,
+                eos_token => template.eos_token,
+            })
+        })
+        .map_err(|e| format!("Failed to render chat template: {}", e))
+}
+
+/// Combine a system instruction with a plain prompt through the model's chat
+/// template, as a system message followed by a user message.
+///
+/// Models without a chat template get `system`, a blank line, then `prompt`.
+pub fn with_system_prompt(model_path: &str, system: &str, prompt: &str) -> Result<String, String> {
+    let key = model_key(model_path);
+    let template = model_files::read_chat_template(Path::new(&key))?;
+    let Some(template) = template else {
+        return Ok(format!("{}\n\n{}", system, prompt));
+    };
+    let messages = [
+        ChatMessage {
+            role: "system".to_string(),
+            content: system.to_string(),
+        },
+        ChatMessage {
+            role: "user".to_string(),
+            content: prompt.to_string(),
+        },
+    ];
+    render(&template, &messages)
+}
+
+/// The model's chat template; models without one can't chat
+fn require_chat_template(key: &str) -> Result<ChatTemplate, String> {
+    model_files::read_chat_template(Path::new(key))?.ok_or_else(|| {
+        PluginLlmError::UnsupportedCapability(
+            key.to_string(),
+            "chat",
+            "no chat_template in tokenizer_config.json".to_string(),
+        )
+        .into()
+    })
+}
+
+/// Render messages for inspection, with special tokens shown as `<|...|>`
+pub fn test_template(model_path: &str, messages: &[ChatMessage]) -> Result<String, String> {
+    validate_messages(messages)?;
+    let key = model_key(model_path);
+    let template = require_chat_template(&key)?;
+    let rendered = render(&template, messages)?;
+    Ok(mark_special_tokens(&rendered, &template.special_tokens))
+}
+
+/// Wrap every special token occurrence as `<|name|>` (longest match wins).
+///
+/// Tokens already written as `<|name|>` are unchanged; `<s>` becomes `<|s|>`.
+fn mark_special_tokens(text: &str, special_tokens: &[String]) -> String {
+    let mut tokens: Vec<&str> = special_tokens
+        .iter()
+        .map(String::as_str)
+        .filter(|t| !t.is_empty())
+        .collect();
+    tokens.sort_by_key(|t| std::cmp::Reverse(t.len()));
+
+    let mut marked = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        match tokens.iter().find(|t| rest.starts_with(**t)) {
+            Some(token) => {
+                let name = token
+                    .strip_prefix("<|")
+                    .and_then(|t| t.strip_suffix("|>"))
+                    .or_else(|| token.strip_prefix('<').and_then(|t| t.strip_suffix('>')))
+                    .unwrap_or(token);
+                marked.push_str(&format!("<|{}|>", name));
+                rest = &rest[token.len()..];
+            }
+            None => {
+                marked.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+    }
+    marked
+}
+
+/// Generate the assistant reply for a conversation
+pub fn chat(args: &ChatArgs) -> Result<String, String> {
+    validate_messages(&args.messages)?;
+    // Caps apply to the conversation before it's rendered
+    let bytes: usize = args.messages.iter().map(|m| m.content.len()).sum();
+    check_prompt_bytes(bytes + args.system.as_ref().map_or(0, String::len))?;
+    check_max_tokens(args.sampling.max_tokens)?;
+    let key = model_key(&args.model_path);
+    let path = Path::new(&key);
+
+    let template = require_chat_template(&key)?;
+
+    let mut messages = args.messages.clone();
+    if let Some(system) = &args.system {
+        // Two system instructions would be rendered twice
+        if messages[0].role == "system" {
+            return Err(
+                "Invalid arguments: give the system instruction as `system` or as a system message, not both"
+                    .to_string(),
+            );
+        }
+        messages.insert(
+            0,
+            ChatMessage {
+                role: "system".to_string(),
+                content: system.clone(),
+            },
+        );
+    }
+    let mut prompt = render(&template, &messages)?;
+    let mut folded = 0;
+
+    if let Some(context_length) = model_files::read_context_length(path)? {
+        let threshold = args.compact_threshold.unwrap_or(DEFAULT_COMPACT_THRESHOLD);
+        if !(threshold > 0.0 && threshold <= 1.0) {
+            return Err(format!(
+                "Invalid arguments: compact_threshold must be in (0, 1], got {}",
+                threshold
+            ));
+        }
+
+        let limit = (context_length as f32 * threshold) as usize;
+        let reserve = args.sampling.max_tokens.unwrap_or(DEFAULT_REPLY_RESERVE);
+        let budget = limit.saturating_sub(reserve);
+
+        let prompt_tokens = count_prompt_tokens(&key, &prompt)?;
+        if prompt_tokens > budget {
+            if !args.auto_compact {
+                return Err(PluginLlmError::ContextOverflow {
+                    prompt_tokens,
+                    reply_tokens: reserve,
+                    limit,
+                }
+                .into());
+            }
+            (messages, folded) = compact(args, &key, &template, &messages, budget)?;
+            prompt = render(&template, &messages)?;
+        }
+    }
+
+    let output = generate_text(&GenerateArgs {
+        model_path: key,
+        prompt,
+        sampling: args.sampling.clone(),
+        ..Default::default()
+    })?;
+
+    let mut result: Value = serde_json::from_str(&output).map_err(|e| e.to_string())?;
+    let text = result
+        .as_object_mut()
+        .and_then(|r| r.remove("text"))
+        .unwrap_or_default();
+    result["message"] = json!({ "role": "assistant", "content": text });
+    let prompt_tokens = result["prompt_tokens"].as_u64().unwrap_or(0);
+    let completion_tokens = result["tokens_generated"].as_u64().unwrap_or(0);
+    result["usage"] = json!({
+        "prompt_tokens": prompt_tokens,
+        "completion_tokens": completion_tokens,
+        "total_tokens": prompt_tokens + completion_tokens,
+    });
+    result["compacted"] = json!(folded > 0);
+    result["folded_turns"] = json!(folded);
+
+    Ok(serde_json::to_string(&result).unwrap_or_default())
+}
+
+/// Fold the fewest oldest turns into a summary note so the history fits `budget`.
+///
+/// Leading system messages and the latest turn are always kept.
+fn compact(
+    args: &ChatArgs,
+    key: &str,
+    template: &ChatTemplate,
+    messages: &[ChatMessage],
+    budget: usize,
+) -> Result<(Vec<ChatMessage>, usize), String> {
+    let summary_max_tokens = args
+        .summary_max_tokens
+        .unwrap_or(DEFAULT_SUMMARY_MAX_TOKENS);
+    let system = messages.iter().take_while(|m| m.role == "system").count();
+    let foldable = messages.len().saturating_sub(system + 1);
+
+    let with_summary = |summary: &str, keep_from: usize| {
+        let mut compacted = messages[..system].to_vec();
+        compacted.push(ChatMessage {
+            role: "system".to_string(),
+            content: format!("{}{}", SUMMARY_PREFIX, summary),
+        });
+        compacted.extend_from_slice(&messages[keep_from..]);
+        compacted
+    };
+
+    // Smallest fold that fits once the summary is added
+    let mut fold = None;
+    for count in 1..=foldable {
+        let candidate = with_summary("", system + count);
+        let tokens = count_prompt_tokens(key, &render(template, &candidate)?)?;
+        if tokens + summary_max_tokens <= budget {
+            fold = Some(count);
+            break;
+        }
+    }
+    let count = fold.ok_or_else(|| {
+        format!(
+            "Context overflow: history does not fit in {} tokens even after compaction",
+            budget
+        )
+    })?;
+
+    let transcript: String = messages[system..system + count]
+        .iter()
+        .map(|m| format!("{}: {}\n", m.role, m.content))
+        .collect();
+    let request = ChatMessage {
+        role: "user".to_string(),
+        content: format!(
+            "Summarize the following conversation in a few sentences, keeping names, facts and decisions:\n\n{}",
+            transcript
+        ),
+    };
+
+    let output = generate_text(&GenerateArgs {
+        model_path: key.to_string(),
+        prompt: render(template, &[request])?,
+        sampling: SamplingParams {
+            max_tokens: Some(summary_max_tokens),
+            ..Default::default()
+        },
+        ..Default::default()
+    })?;
+    let summary: Value = serde_json::from_str(&output).map_err(|e| e.to_string())?;
+    let summary = summary["text"].as_str().unwrap_or_default().trim();
+
+    Ok((with_summary(summary, system + count), count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_applies_template_with_generation_prompt() {
+        let template = ChatTemplate {
+            template: "{{ bos_token }}{% for m in messages %}<{{ m.role }}>{{ m.content.strip() }}\n{% endfor %}{% if add_generation_prompt %}<assistant>{% endif %}".to_string(),
+            bos_token: Some("<s>".to_string()),
+            eos_token: None,
+            special_tokens: vec!["<s>".to_string()],
+        };
+        let messages = vec![ChatMessage {
+            role: "user".to_string(),
+            content: " hi ".to_string(),
+        }];
+        assert_eq!(
+            render(&template, &messages).unwrap(),
+            "<s><user>hi\n<assistant>"
+        );
+    }
+
+    #[test]
+    fn mark_special_tokens_prefers_longest_match() {
+        let special = vec![
+            "<s>".to_string(),
+            "<|im_start|>".to_string(),
+            "[INST]".to_string(),
+        ];
+        assert_eq!(
+            mark_special_tokens("<s><|im_start|>user [INST] hi", &special),
+            "<|s|><|im_start|>user <|[INST]|> hi"
+        );
+    }
+
+    #[test]
+    fn validate_messages_rejects_empty_and_unknown_roles() {
+        let message = |role: &str| ChatMessage {
+            role: role.to_string(),
+            content: "hi".to_string(),
+        };
+        assert!(validate_messages(&[message("system"), message("user")]).is_ok());
+        assert_eq!(
+            validate_messages(&[]).unwrap_err(),
+            "Invalid arguments: messages must not be empty"
+        );
+        assert!(validate_messages(&[message("user"), message("bot")])
+            .unwrap_err()
+            .starts_with("Invalid arguments: messages[1] has unknown role \"bot\""));
+    }
+}
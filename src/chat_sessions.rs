@@ -0,0 +1,158 @@
+//! Multi-turn chat sessions (`chat_start` / `chat_append` / `chat_end`)
+//!
+//! The conversation is kept plugin-side, so callers only send each new turn.
+//! `lib_client_uzu::Client` builds fresh decode state for every `generate`
+//! call and cannot retain a KV cache between calls, so each turn still
+//! re-processes the whole rendered history; only the bookkeeping is saved.
+//!
+//! A session is tied to the model instance it started on. Once that model is
+//! unloaded, evicted or reloaded, the session is invalidated rather than
+//! silently continuing on a fresh copy.
+
+use crate::chat::{self, ChatArgs, ChatMessage};
+use crate::engine::Client;
+use crate::registry::Entry;
+use crate::{model_key, open_model, SamplingParams, MODELS};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+static NEXT_CHAT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Active chat sessions (session_id -> session)
+static CHATS: Mutex<Option<HashMap<String, Arc<Mutex<ChatSession>>>>> = Mutex::new(None);
+
+struct ChatSession {
+    model_key: String,
+    /// Model instance the session started on; doesn't keep it loaded
+    model: Weak<Entry<Client>>,
+    messages: Vec<ChatMessage>,
+    turns: usize,
+}
+
+/// Arguments for `chat_start`
+#[derive(Debug, Deserialize)]
+pub struct ChatStartArgs {
+    pub model_path: String,
+    /// Opening messages, e.g. a system prompt
+    #[serde(default)]
+    pub messages: Vec<ChatMessage>,
+}
+
+/// Arguments for `chat_append`
+#[derive(Debug, Deserialize)]
+pub struct ChatAppendArgs {
+    pub session_id: String,
+    /// New turns to add before the assistant replies
+    pub messages: Vec<ChatMessage>,
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
+    #[serde(default)]
+    pub auto_compact: bool,
+    #[serde(default)]
+    pub compact_threshold: Option<f32>,
+    #[serde(default)]
+    pub summary_max_tokens: Option<usize>,
+}
+
+fn with_chats<T>(
+    f: impl FnOnce(&mut HashMap<String, Arc<Mutex<ChatSession>>>) -> Result<T, String>,
+) -> Result<T, String> {
+    let mut chats = CHATS
+        .lock()
+        .map_err(|e| format!("Failed to lock chat sessions: {}", e))?;
+    f(chats.get_or_insert_with(HashMap::new))
+}
+
+/// Load the model and open a session on it
+pub fn start(args: &ChatStartArgs) -> Result<String, String> {
+    let key = model_key(&args.model_path);
+    let model = open_model(&key)?;
+
+    let session_id = format!("chat-{}", NEXT_CHAT_ID.fetch_add(1, Ordering::Relaxed));
+    let session = ChatSession {
+        model_key: key.clone(),
+        model: Arc::downgrade(&model),
+        messages: args.messages.clone(),
+        turns: 0,
+    };
+    with_chats(|chats| {
+        chats.insert(session_id.clone(), Arc::new(Mutex::new(session)));
+        Ok(())
+    })?;
+
+    let result = json!({
+        "session_id": session_id,
+        "model_path": key,
+        "messages": args.messages.len(),
+    });
+    Ok(serde_json::to_string(&result).unwrap_or_default())
+}
+
+/// Add turns to the conversation and generate the assistant reply.
+///
+/// Turns of one session run one at a time. The history only grows when the
+/// reply succeeds.
+pub fn append(args: &ChatAppendArgs) -> Result<String, String> {
+    if args.messages.is_empty() {
+        return Err("Invalid arguments: messages must not be empty".to_string());
+    }
+    let session = with_chats(|chats| {
+        chats
+            .get(&args.session_id)
+            .cloned()
+            .ok_or_else(|| format!("Unknown session: {}", args.session_id))
+    })?;
+    let mut session = session
+        .lock()
+        .map_err(|e| format!("Failed to lock chat session: {}", e))?;
+
+    let current = MODELS.get(&session.model_key);
+    let alive = session
+        .model
+        .upgrade()
+        .zip(current)
+        .is_some_and(|(started, current)| Arc::ptr_eq(&started, &current));
+    if !alive {
+        return Err(format!(
+            "Session invalidated: model {} was unloaded since session {} started",
+            session.model_key, args.session_id
+        ));
+    }
+
+    let mut messages = session.messages.clone();
+    messages.extend(args.messages.iter().cloned());
+    let output = chat::chat(&ChatArgs {
+        model_path: session.model_key.clone(),
+        messages: messages.clone(),
+        sampling: args.sampling.clone(),
+        auto_compact: args.auto_compact,
+        compact_threshold: args.compact_threshold,
+        summary_max_tokens: args.summary_max_tokens,
+        // A session's system instruction is part of its history
+        system: None,
+    })?;
+
+    let mut result: Value = serde_json::from_str(&output).map_err(|e| e.to_string())?;
+    let reply: ChatMessage =
+        serde_json::from_value(result["message"].clone()).map_err(|e| e.to_string())?;
+    messages.push(reply);
+    session.messages = messages;
+    session.turns += 1;
+
+    result["session_id"] = json!(args.session_id);
+    result["turns"] = json!(session.turns);
+    Ok(serde_json::to_string(&result).unwrap_or_default())
+}
+
+/// Forget a session and its history
+pub fn end(session_id: &str) -> Result<(), String> {
+    with_chats(|chats| {
+        chats
+            .remove(session_id)
+            .map(|_| ())
+            .ok_or_else(|| format!("Unknown session: {}", session_id))
+    })
+}
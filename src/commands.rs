@@ -0,0 +1,399 @@
+//! CLI command table (`adi.llm.uzu.cli`)
+//!
+//! Every command is one `Command` entry: `list_commands` advertises the
+//! table and `run_command` dispatches through it, so the two can't drift.
+//! Usage errors and `--json-errors` usage lines come from the same entry.
+
+use crate::stop_defaults::StopDefaults;
+use crate::{
+    benchmark, bulk_unload, chat, cli_generate_args, cli_generate_sse, cli_list, cli_option,
+    config, drain_generations, error_code, generate_sse, generate_text, get_help, get_model_info,
+    inspect_model, list_models, list_models_detailed, load_model, memory_usage, option_flag,
+    preload_model, reload_model, scan_models, set_default_model, set_max_models,
+    set_model_sampling, set_model_stops, undrain_generations, unload_all_models, unload_model,
+    GenerateArgs, SamplingParams,
+};
+use lib_plugin_abi_v3::cli::CliCommand;
+use serde_json::Value;
+use std::path::PathBuf;
+
+/// Runs a command with its positional args and options
+pub type Handler = fn(&[&str], &Value) -> Result<String, String>;
+
+pub struct Command {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub usage: &'static str,
+    pub handler: Handler,
+}
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "load",
+        description: "Load a model",
+        usage:
+            "load <model-path> [--as <alias>] [sampling defaults] [--stop <a,b,...>] [--ignore-eos]",
+        handler: load,
+    },
+    Command {
+        name: "preload",
+        description: "Load a model and warm it up with a one-token generation",
+        usage: "preload <model-path> [--no-warmup]",
+        handler: preload,
+    },
+    Command {
+        name: "unload",
+        description: "Unload a model",
+        usage: "unload <model-path> | unload [--older-than <duration>] [--matching <glob>]",
+        handler: unload,
+    },
+    Command {
+        name: "unload-all",
+        description: "Unload every model",
+        usage: "unload-all",
+        handler: unload_all,
+    },
+    Command {
+        name: "reload",
+        description: "Unload and load a model again (e.g. after replacing its files)",
+        usage: "reload <model-path>",
+        handler: reload,
+    },
+    Command {
+        name: "list",
+        description: "List loaded models",
+        usage: "list [--detailed]",
+        handler: list_loaded,
+    },
+    Command {
+        name: "generate",
+        description: "Generate text",
+        usage: "generate [<model-path>] <prompt> [options]",
+        handler: generate,
+    },
+    Command {
+        name: "validate",
+        description: "Check generate arguments without generating",
+        usage: "validate [<model-path>] <prompt> [options]",
+        handler: validate,
+    },
+    Command {
+        name: "benchmark",
+        description: "Time repeated generations of a fixed prompt",
+        usage: "benchmark <model-path> [--iterations <n>] [--max-tokens <m>]",
+        handler: run_benchmark,
+    },
+    Command {
+        name: "scan",
+        description: "List models on disk without loading them",
+        usage: "scan [--dir <path>]",
+        handler: scan,
+    },
+    Command {
+        name: "memory",
+        description: "Show estimated memory of loaded models",
+        usage: "memory",
+        handler: memory,
+    },
+    Command {
+        name: "info",
+        description: "Show model info",
+        usage: "info <model-path> [--load]",
+        handler: info,
+    },
+    Command {
+        name: "inspect",
+        description: "Show a model's format, quantization and tokenizer without loading it",
+        usage: "inspect <model-path>",
+        handler: inspect,
+    },
+    Command {
+        name: "test-template",
+        description: "Render messages through the chat template, special tokens visible",
+        usage: "test-template <model-path> <messages-json>",
+        handler: test_template,
+    },
+    Command {
+        name: "set-default",
+        description: "Set the model used when generate omits the model path",
+        usage: "set-default <model-path>",
+        handler: set_default,
+    },
+    Command {
+        name: "set-max-models",
+        description: "Set how many models may stay loaded (LRU eviction)",
+        usage: "set-max-models <n>",
+        handler: max_models,
+    },
+    Command {
+        name: "drain",
+        description: "Stop accepting generations and wait for in-flight ones",
+        usage: "drain [--timeout-ms <n>]",
+        handler: drain,
+    },
+    Command {
+        name: "undrain",
+        description: "Resume accepting generations",
+        usage: "undrain",
+        handler: undrain,
+    },
+    Command {
+        name: "help",
+        description: "Show commands and options",
+        usage: "help",
+        handler: help,
+    },
+];
+
+/// The command named `name`; an empty name means `help`
+pub fn find(name: &str) -> Option<&'static Command> {
+    let name = if name.is_empty() { "help" } else { name };
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+/// Usage line of a command
+pub fn usage(name: &str) -> Option<&'static str> {
+    find(name).map(|command| command.usage)
+}
+
+/// `Usage: ...` error for a command called with missing arguments
+pub fn usage_error(name: &str) -> String {
+    format!("Usage: {}", usage(name).unwrap_or(name))
+}
+
+/// Table entries as the ABI's `CliCommand`s
+pub fn list() -> Vec<CliCommand> {
+    COMMANDS
+        .iter()
+        .map(|command| CliCommand {
+            name: command.name.to_string(),
+            description: command.description.to_string(),
+            args: vec![],
+            has_subcommands: false,
+        })
+        .collect()
+}
+
+/// Run the command named `name`
+pub fn run(name: &str, args: &[&str], options: &Value) -> Result<String, String> {
+    match find(name) {
+        Some(command) => (command.handler)(args, options),
+        None => Err(format!("Unknown command: {}", name)),
+    }
+}
+
+fn load(args: &[&str], options: &Value) -> Result<String, String> {
+    let path = args.first().ok_or_else(|| usage_error("load"))?;
+    let alias = cli_option::<String>(options, "as", "an alias")?;
+    let sampling = SamplingParams {
+        max_tokens: cli_option(options, "max-tokens", "a positive integer")?,
+        temperature: cli_option(options, "temperature", "a number")?,
+        top_p: cli_option(options, "top-p", "a number")?,
+        top_k: cli_option(options, "top-k", "a positive integer")?,
+        seed: cli_option(options, "seed", "a non-negative integer")?,
+        ..Default::default()
+    };
+    let has_sampling = sampling != SamplingParams::default();
+    if has_sampling {
+        config::check_sampling_defaults(&sampling)
+            .map_err(|e| format!("Invalid arguments: {}", e))?;
+    }
+    let stops = StopDefaults {
+        stop: cli_list(options, "stop")?,
+        ignore_eos: option_flag(options, "ignore-eos"),
+    };
+    let has_stops = stops != StopDefaults::default();
+    if has_stops {
+        stops
+            .validate()
+            .map_err(|e| format!("Invalid arguments: {}", e))?;
+    }
+    load_model(path, alias.as_deref())?;
+    if has_sampling {
+        set_model_sampling(path, sampling)?;
+    }
+    if has_stops {
+        set_model_stops(path, stops)?;
+    }
+    Ok(match alias {
+        Some(alias) => format!("Model loaded: {} as {}", path, alias),
+        None => format!("Model loaded: {}", path),
+    })
+}
+
+fn preload(args: &[&str], options: &Value) -> Result<String, String> {
+    let path = args.first().ok_or_else(|| usage_error("preload"))?;
+    preload_model(path, !option_flag(options, "no-warmup"), None)
+}
+
+fn unload(args: &[&str], options: &Value) -> Result<String, String> {
+    let older_than = cli_option::<String>(options, "older-than", "a duration")?;
+    let matching = cli_option::<String>(options, "matching", "a glob")?;
+    if older_than.is_some() || matching.is_some() {
+        if !args.is_empty() {
+            return Err(usage_error("unload"));
+        }
+        let older_than = older_than
+            .map(|text| {
+                bulk_unload::parse_duration(&text).ok_or_else(|| {
+                    format!(
+                        "Invalid arguments: --older-than expects a duration like 30m or 2h, got {:?}",
+                        text
+                    )
+                })
+            })
+            .transpose()?;
+        let filter = bulk_unload::Filter {
+            older_than,
+            matching: matching.as_deref(),
+        };
+        return Ok(bulk_unload::unload(&filter)?.to_string());
+    }
+    let path = args.first().ok_or_else(|| usage_error("unload"))?;
+    unload_model(path)?;
+    Ok(format!("Model unloaded: {}", path))
+}
+
+fn unload_all(_: &[&str], _: &Value) -> Result<String, String> {
+    let unloaded = unload_all_models()?;
+    Ok(format!("Unloaded {} model(s)", unloaded.len()))
+}
+
+fn reload(args: &[&str], _: &Value) -> Result<String, String> {
+    let path = args.first().ok_or_else(|| usage_error("reload"))?;
+    reload_model(path)?;
+    Ok(format!("Model reloaded: {}", path))
+}
+
+fn list_loaded(_: &[&str], options: &Value) -> Result<String, String> {
+    if option_flag(options, "detailed") {
+        let models = list_models_detailed()?;
+        return serde_json::to_string(&models).map_err(|e| e.to_string());
+    }
+    serde_json::to_string(&list_models()).map_err(|e| e.to_string())
+}
+
+fn generate(args: &[&str], options: &Value) -> Result<String, String> {
+    let sse = cli_generate_sse(args, options)?;
+    let args = cli_generate_args(args, options)?;
+    if sse {
+        generate_sse(&args)
+    } else {
+        generate_text(&args)
+    }
+}
+
+fn validate(args: &[&str], options: &Value) -> Result<String, String> {
+    let args = cli_generate_args(args, options).map_err(|e| match error_code(&e) {
+        "usage" => usage_error("validate"),
+        _ => e,
+    })?;
+    generate_text(&GenerateArgs {
+        validate_only: true,
+        ..args
+    })
+}
+
+fn run_benchmark(args: &[&str], options: &Value) -> Result<String, String> {
+    let path = args.first().ok_or_else(|| usage_error("benchmark"))?;
+    let iterations = cli_option(options, "iterations", "a positive integer")?;
+    let max_tokens = cli_option(options, "max-tokens", "a positive integer")?;
+    benchmark::run(
+        path,
+        iterations.unwrap_or(benchmark::DEFAULT_ITERATIONS),
+        max_tokens.unwrap_or(benchmark::DEFAULT_MAX_TOKENS),
+    )
+}
+
+fn scan(_: &[&str], options: &Value) -> Result<String, String> {
+    let dir = cli_option::<String>(options, "dir", "a directory")?;
+    scan_models(dir.map(PathBuf::from))
+}
+
+fn memory(_: &[&str], _: &Value) -> Result<String, String> {
+    memory_usage()
+}
+
+fn info(args: &[&str], options: &Value) -> Result<String, String> {
+    let path = args.first().ok_or_else(|| usage_error("info"))?;
+    get_model_info(path, option_flag(options, "load"))
+}
+
+fn inspect(args: &[&str], _: &Value) -> Result<String, String> {
+    let path = args.first().ok_or_else(|| usage_error("inspect"))?;
+    inspect_model(path)
+}
+
+fn test_template(args: &[&str], _: &Value) -> Result<String, String> {
+    if args.len() < 2 {
+        return Err(usage_error("test-template"));
+    }
+    let messages: Vec<chat::ChatMessage> = serde_json::from_str(&args[1..].join(" "))
+        .map_err(|e| format!("Invalid arguments: messages: {}", e))?;
+    chat::test_template(args[0], &messages)
+}
+
+fn set_default(args: &[&str], _: &Value) -> Result<String, String> {
+    let path = args.first().ok_or_else(|| usage_error("set-default"))?;
+    set_default_model(path)
+}
+
+fn max_models(args: &[&str], _: &Value) -> Result<String, String> {
+    let max = args.first().ok_or_else(|| usage_error("set-max-models"))?;
+    match max.parse::<usize>() {
+        Ok(max) => set_max_models(max),
+        Err(_) => Err(format!(
+            "Invalid arguments: set-max-models expects a positive integer, got {:?}",
+            max
+        )),
+    }
+}
+
+fn drain(_: &[&str], options: &Value) -> Result<String, String> {
+    let timeout_ms = cli_option(options, "timeout-ms", "a non-negative integer")?;
+    Ok(drain_generations(timeout_ms))
+}
+
+fn undrain(_: &[&str], _: &Value) -> Result<String, String> {
+    Ok(undrain_generations())
+}
+
+fn help(_: &[&str], _: &Value) -> Result<String, String> {
+    Ok(get_help())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn advertised_commands_and_dispatch_match() {
+        let advertised: Vec<String> = list().into_iter().map(|c| c.name).collect();
+        let unique: HashSet<&String> = advertised.iter().collect();
+        assert_eq!(unique.len(), advertised.len(), "duplicate command names");
+
+        let help = get_help();
+        for name in &advertised {
+            let command = find(name).unwrap_or_else(|| panic!("{} has no dispatch arm", name));
+            assert!(
+                command.usage.starts_with(name.as_str()),
+                "{}",
+                command.usage
+            );
+            assert!(
+                help.contains(&format!("\n  {}", name)),
+                "help omits {}",
+                name
+            );
+        }
+        for command in COMMANDS {
+            assert!(advertised.iter().any(|name| name == command.name));
+        }
+
+        assert_eq!(find("").map(|c| c.name), Some("help"));
+        let e = run("frobnicate", &[], &Value::Null).unwrap_err();
+        assert_eq!(error_code(&e), "unknown_command");
+        assert_eq!(usage_error("reload"), "Usage: reload <model-path>");
+    }
+}
@@ -0,0 +1,284 @@
+//! Init configuration (`PluginContext.config`)
+//!
+//! `init` parses and validates the whole object once; a malformed config
+//! fails init instead of being half-applied. Helpers with a configurable
+//! default read it through `get`, which returns the defaults before init.
+
+use crate::device::DeviceConfig;
+use crate::error::PluginLlmError;
+use crate::logging::LogLevel;
+use crate::rate_limit::RateLimit;
+use crate::restore::RestoreMode;
+use crate::sampling::{self, SamplingParams};
+use crate::stop_defaults::StopDefaults;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+/// Resident-model limit when the config doesn't set `max_loaded_models`
+pub const DEFAULT_MAX_LOADED_MODELS: usize = 2;
+
+/// Idle time before a model is unloaded when the config doesn't set
+/// `idle_model_ttl_secs`
+const DEFAULT_IDLE_MODEL_TTL_SECS: u64 = 600;
+
+/// Generations waiting for a busy model when the config doesn't set
+/// `max_queued_generations`
+const DEFAULT_MAX_QUEUED_GENERATIONS: usize = 16;
+
+/// Environment variable giving the `max_prompt_bytes` default
+const MAX_PROMPT_BYTES_ENV: &str = "ADI_UZU_MAX_PROMPT_BYTES";
+
+/// Prompt size cap when neither the config nor the environment sets one (4 MiB)
+const DEFAULT_MAX_PROMPT_BYTES: usize = 4 * 1024 * 1024;
+
+/// Environment variable giving the `json_errors` default
+const JSON_ERRORS_ENV: &str = "ADI_UZU_JSON_ERRORS";
+
+/// Environment variable giving the `gpu_release_grace_ms` default
+const GPU_RELEASE_GRACE_ENV: &str = "ADI_UZU_GPU_RELEASE_GRACE_MS";
+
+/// Idle time before transient GPU memory is released when neither the
+/// config nor the environment sets one
+const DEFAULT_GPU_RELEASE_GRACE_MS: u64 = 60_000;
+
+/// `max_tokens` cap when the config doesn't set `max_tokens_limit`
+const DEFAULT_MAX_TOKENS_LIMIT: usize = 128 * 1024;
+
+/// Image size cap when the config doesn't set `max_image_bytes` (20 MiB)
+const DEFAULT_MAX_IMAGE_BYTES: usize = 20 * 1024 * 1024;
+
+/// Plugin settings from the host's config object
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Models kept resident before the least recently used is unloaded
+    pub max_loaded_models: usize,
+    /// Idle time before a model is unloaded (`0` disables)
+    pub idle_model_ttl_secs: u64,
+    /// Generations running at once per model
+    pub max_concurrent_generations: usize,
+    /// Generations waiting per model before more are refused (`0` = no queue)
+    pub max_queued_generations: usize,
+    /// Largest prompt accepted, in bytes, checked before tokenization
+    pub max_prompt_bytes: usize,
+    /// Largest `max_tokens` a request may ask for
+    pub max_tokens_limit: usize,
+    /// Largest image accepted in `images`, in decoded bytes
+    pub max_image_bytes: usize,
+    /// Generation requests allowed per model (`None` = unlimited)
+    pub rate_limit: Option<RateLimit>,
+    /// Directory `scan` walks by default (`None` = the `hf:` cache)
+    pub models_dir: Option<PathBuf>,
+    /// Cache for `hf:` models (`None` = `~/.cache/adi/llm-uzu/hf`)
+    pub hf_cache_dir: Option<PathBuf>,
+    /// Hash model files against their `SHA256SUMS` manifest on every load
+    pub verify_checksums: bool,
+    /// Metal device and memory budget for model loads
+    pub device: DeviceConfig,
+    /// Estimated model memory `memory_pressure` unloads down to, when the
+    /// message gives no `target_bytes` (`None` = keep the most recent model)
+    pub memory_pressure_floor_bytes: Option<u64>,
+    /// Whether loaded models are saved at shutdown and restored at init
+    pub restore_models: RestoreMode,
+    /// Model used by generations that omit `model_path`
+    pub default_model: Option<String>,
+    /// Sampling parameters used wherever a request leaves one unset
+    pub default_sampling: SamplingParams,
+    /// Per-model sampling defaults by model path, layered between the
+    /// request and `default_sampling` (a `load` with `sampling` replaces them)
+    pub model_sampling: HashMap<String, SamplingParams>,
+    /// Per-model stop sequences and `ignore_eos` by model path (a `load`
+    /// with `stops` replaces them)
+    pub model_stops: HashMap<String, StopDefaults>,
+    /// Fail init on an unsupported platform instead of staying loadable
+    pub strict_platform_check: bool,
+    /// Which model and generation events are logged
+    pub log_level: LogLevel,
+    /// Include prompts in generation-start lines (off: they may hold user data)
+    pub log_prompts: bool,
+    /// Report CLI errors as JSON, as `--json-errors` does
+    pub json_errors: bool,
+    /// Idle time before transient GPU memory is released (`0` disables)
+    pub gpu_release_grace_ms: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            max_loaded_models: DEFAULT_MAX_LOADED_MODELS,
+            idle_model_ttl_secs: DEFAULT_IDLE_MODEL_TTL_SECS,
+            max_concurrent_generations: 1,
+            max_queued_generations: DEFAULT_MAX_QUEUED_GENERATIONS,
+            max_prompt_bytes: default_max_prompt_bytes(),
+            max_tokens_limit: DEFAULT_MAX_TOKENS_LIMIT,
+            max_image_bytes: DEFAULT_MAX_IMAGE_BYTES,
+            rate_limit: None,
+            models_dir: None,
+            hf_cache_dir: None,
+            verify_checksums: false,
+            device: DeviceConfig::default(),
+            memory_pressure_floor_bytes: None,
+            restore_models: RestoreMode::default(),
+            default_model: None,
+            default_sampling: SamplingParams::default(),
+            model_sampling: HashMap::new(),
+            model_stops: HashMap::new(),
+            strict_platform_check: false,
+            log_level: LogLevel::default(),
+            log_prompts: false,
+            json_errors: default_json_errors(),
+            gpu_release_grace_ms: default_gpu_release_grace_ms(),
+        }
+    }
+}
+
+fn default_max_prompt_bytes() -> usize {
+    std::env::var(MAX_PROMPT_BYTES_ENV)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(DEFAULT_MAX_PROMPT_BYTES)
+}
+
+fn default_json_errors() -> bool {
+    std::env::var(JSON_ERRORS_ENV).is_ok_and(|v| matches!(v.trim(), "1" | "true"))
+}
+
+fn default_gpu_release_grace_ms() -> u64 {
+    std::env::var(GPU_RELEASE_GRACE_ENV)
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .unwrap_or(DEFAULT_GPU_RELEASE_GRACE_MS)
+}
+
+static CONFIG: Lazy<RwLock<Arc<Config>>> = Lazy::new(|| RwLock::new(Arc::default()));
+
+/// Parse and validate the init config; `null` means all defaults
+pub fn parse(value: &Value) -> Result<Config, PluginLlmError> {
+    if value.is_null() {
+        return Ok(Config::default());
+    }
+    let config: Config =
+        serde_json::from_value(value.clone()).map_err(|e| invalid(e.to_string()))?;
+
+    if config.max_loaded_models == 0 {
+        return Err(invalid("max_loaded_models must be at least 1".to_string()));
+    }
+    if config.max_concurrent_generations == 0 {
+        return Err(invalid(
+            "max_concurrent_generations must be at least 1".to_string(),
+        ));
+    }
+    if config.max_prompt_bytes == 0 {
+        return Err(invalid("max_prompt_bytes must be at least 1".to_string()));
+    }
+    if config.max_tokens_limit == 0 {
+        return Err(invalid("max_tokens_limit must be at least 1".to_string()));
+    }
+    if config.max_image_bytes == 0 {
+        return Err(invalid("max_image_bytes must be at least 1".to_string()));
+    }
+    if config
+        .hf_cache_dir
+        .as_ref()
+        .is_some_and(|dir| dir.as_os_str().is_empty())
+    {
+        return Err(invalid("hf_cache_dir must not be empty".to_string()));
+    }
+    if let Some(limit) = &config.rate_limit {
+        limit
+            .validate()
+            .map_err(|e| invalid(format!("rate_limit: {}", e)))?;
+    }
+    config
+        .device
+        .validate()
+        .map_err(|e| invalid(format!("device: {}", e)))?;
+    if config.default_model.as_deref() == Some("") {
+        return Err(invalid("default_model must not be empty".to_string()));
+    }
+    check_sampling_defaults(&config.default_sampling)
+        .map_err(|e| invalid(format!("default_sampling: {}", e)))?;
+    for (model, defaults) in &config.model_sampling {
+        check_sampling_defaults(defaults)
+            .map_err(|e| invalid(format!("model_sampling: {}: {}", model, e)))?;
+    }
+    for (model, defaults) in &config.model_stops {
+        defaults
+            .validate()
+            .map_err(|e| invalid(format!("model_stops: {}: {}", model, e)))?;
+    }
+    Ok(config)
+}
+
+/// Reason sampling defaults would fail the requests they apply to, if any
+pub fn check_sampling_defaults(defaults: &SamplingParams) -> Result<(), String> {
+    sampling::resolve(defaults)
+        .map_err(|e| e.trim_start_matches("Invalid arguments: ").to_string())?;
+    // The engine has no repetition penalties, so such defaults would fail every request
+    if defaults.penalty_last_n.is_some()
+        || defaults.repetition_penalty.is_some()
+        || defaults.frequency_penalty.is_some()
+    {
+        return Err("repetition penalty settings are not supported by the engine".to_string());
+    }
+    Ok(())
+}
+
+/// Current config (the defaults before `init`)
+pub fn get() -> Arc<Config> {
+    CONFIG.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+/// Make `config` current
+pub fn set(config: Config) {
+    *CONFIG.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(config);
+}
+
+fn invalid(reason: String) -> PluginLlmError {
+    PluginLlmError::InvalidConfig(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parse_fills_defaults_and_rejects_malformed_configs() {
+        assert_eq!(parse(&Value::Null).unwrap(), Config::default());
+
+        let config = parse(&json!({
+            "idle_model_ttl_secs": 0,
+            "default_sampling": { "temperature": 0.2 }
+        }))
+        .unwrap();
+        assert_eq!(config.idle_model_ttl_secs, 0);
+        assert_eq!(config.max_loaded_models, DEFAULT_MAX_LOADED_MODELS);
+        assert_eq!(config.default_sampling.temperature, Some(0.2));
+
+        for malformed in [
+            json!({ "max_loaded_models": 0 }),
+            json!({ "max_loaded_models": "two" }),
+            json!({ "idle_ttl": 60 }),
+            json!({ "max_prompt_bytes": 0 }),
+            json!({ "max_tokens_limit": -1 }),
+            json!({ "max_image_bytes": 0 }),
+            json!({ "json_errors": "yes" }),
+            json!({ "gpu_release_grace_ms": -1 }),
+            json!({ "device": { "index": 1 } }),
+            json!({ "rate_limit": { "requests_per_minute": 0 } }),
+            json!({ "default_sampling": { "top_p": 1.5 } }),
+            json!({ "default_sampling": { "repetition_penalty": 1.1 } }),
+            json!({ "model_sampling": { "models/a": { "temperature": -1.0 } } }),
+            json!({ "model_stops": { "models/a": { "stop": [""] } } }),
+            json!({ "model_stops": { "models/a": { "eos": false } } }),
+        ] {
+            let e = parse(&malformed).unwrap_err();
+            assert_eq!(e.code(), "invalid_config", "{}", e);
+        }
+    }
+}
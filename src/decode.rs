@@ -0,0 +1,149 @@
+//! Plugin-side decode-loop controls applied on top of the engine's token stream
+
+use std::time::{Duration, Instant};
+
+/// Wall-clock limit for `timeout_ms`, checked between tokens
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// Deadline `timeout_ms` from now; `None` when no timeout is set
+    pub fn from_timeout_ms(timeout_ms: Option<u64>) -> Option<Self> {
+        timeout_ms.map(|ms| Self(Instant::now() + Duration::from_millis(ms)))
+    }
+
+    pub fn expired(&self) -> bool {
+        Instant::now() >= self.0
+    }
+}
+
+/// Incremental substring matcher for `ban_substrings` and `stop`
+pub struct SubstringFilter {
+    needles: Vec<String>,
+    max_len: usize,
+}
+
+impl SubstringFilter {
+    /// Build a filter, ignoring empty strings. Returns `None` when nothing is left.
+    pub fn new(needles: &[String]) -> Option<Self> {
+        let needles: Vec<String> = needles.iter().filter(|n| !n.is_empty()).cloned().collect();
+        let max_len = needles.iter().map(String::len).max()?;
+        Some(Self { needles, max_len })
+    }
+
+    /// Byte offset and value of the earliest match in `text`, only searching the
+    /// region that `text[checked..]` could have completed.
+    pub fn find_match(&self, text: &str, checked: usize) -> Option<(usize, &str)> {
+        let from = floor_char_boundary(text, checked.saturating_sub(self.max_len - 1));
+        self.needles
+            .iter()
+            .filter_map(|n| {
+                text[from..]
+                    .find(n.as_str())
+                    .map(|pos| (from + pos, n.as_str()))
+            })
+            .min_by_key(|(pos, _)| *pos)
+    }
+
+    /// Length of the prefix of `text` that can no longer become part of a match,
+    /// i.e. is safe to hand to a streaming consumer.
+    pub fn safe_len(&self, text: &str) -> usize {
+        let tail_start = floor_char_boundary(text, text.len().saturating_sub(self.max_len - 1));
+        for (start, _) in text[tail_start..].char_indices() {
+            let suffix = &text[tail_start + start..];
+            if self.needles.iter().any(|n| n.starts_with(suffix)) {
+                return tail_start + start;
+            }
+        }
+        text.len()
+    }
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+/// Detects when a complete top-level JSON object or array has been produced,
+/// for `stop_on_json_complete`.
+///
+/// Text before the first `{` or `[` (prose, a code fence) is skipped. Braces
+/// inside strings, including after escaped quotes, are ignored.
+#[derive(Debug, Default)]
+pub struct JsonCompletion {
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+}
+
+impl JsonCompletion {
+    /// Feed the next chunk of output; returns the byte offset in `chunk` just
+    /// past the character that completed the value.
+    pub fn feed(&mut self, chunk: &str) -> Option<usize> {
+        for (i, c) in chunk.char_indices() {
+            if self.in_string {
+                match c {
+                    _ if self.escaped => self.escaped = false,
+                    '\\' => self.escaped = true,
+                    '"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match c {
+                '{' | '[' => self.depth += 1,
+                '"' if self.depth > 0 => self.in_string = true,
+                '}' | ']' if self.depth > 0 => {
+                    self.depth -= 1;
+                    if self.depth == 0 {
+                        return Some(i + c.len_utf8());
+                    }
+                }
+                _ => {}
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_completion_ignores_braces_in_strings() {
+        let mut json = JsonCompletion::default();
+        assert_eq!(json.feed(r#"Sure: {"a": "}\"{", "#), None);
+        assert_eq!(json.feed(r#""b": [1, {"c": 2}]} trailing"#), Some(19));
+    }
+
+    #[test]
+    fn json_completion_handles_escaped_backslash_before_quote() {
+        let mut json = JsonCompletion::default();
+        assert_eq!(json.feed(r#"["a\\", "]"]"#), Some(12));
+    }
+
+    #[test]
+    fn substring_filter_matches_across_token_boundaries() {
+        let stops = SubstringFilter::new(&["\n\nUser:".to_string()]).unwrap();
+        let mut text = String::from("Answer.\n");
+        assert_eq!(stops.find_match(&text, 0), None);
+        assert_eq!(stops.safe_len(&text), "Answer.".len());
+
+        let checked = text.len();
+        text.push_str("\nUser: hi");
+        assert_eq!(stops.find_match(&text, checked), Some((7, "\n\nUser:")));
+    }
+
+    #[test]
+    fn deadline_expires_after_tiny_timeout() {
+        assert!(Deadline::from_timeout_ms(None).is_none());
+
+        let deadline = Deadline::from_timeout_ms(Some(1)).unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+        assert!(deadline.expired());
+        assert!(!Deadline::from_timeout_ms(Some(60_000)).unwrap().expired());
+    }
+}
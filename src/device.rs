@@ -0,0 +1,108 @@
+//! Metal device and memory budget for model loads (`device` config,
+//! `set_device_config`)
+//!
+//! Apple Silicon has a single GPU sharing unified memory, and the engine
+//! opens it itself, so device 0 is the only one that can be selected. The
+//! engine takes no memory limit either: the budget is enforced at load,
+//! where a model whose weights would push the loaded total past it is
+//! refused. Settings only apply to later loads, so they can't change while
+//! models are loaded.
+
+use crate::error::PluginLlmError;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+/// Where models load and how much memory they may take
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// Metal device index
+    pub index: usize,
+    /// Cap on the memory of all loaded models (`None` = no cap)
+    pub memory_budget_bytes: Option<u64>,
+}
+
+impl DeviceConfig {
+    /// Reason the settings can't be applied, if any
+    pub fn validate(&self) -> Result<(), String> {
+        if self.index != 0 {
+            return Err(format!(
+                "device {} doesn't exist: Apple Silicon has one Metal device (0)",
+                self.index
+            ));
+        }
+        if self.memory_budget_bytes == Some(0) {
+            return Err("memory_budget_bytes must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+static DEVICE: Mutex<DeviceConfig> = Mutex::new(DeviceConfig {
+    index: 0,
+    memory_budget_bytes: None,
+});
+
+/// Settings used by the next load
+pub fn get() -> DeviceConfig {
+    *DEVICE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Replace the settings; `loaded_models` must be 0
+pub fn set(config: DeviceConfig, loaded_models: usize) -> Result<(), PluginLlmError> {
+    config.validate().map_err(PluginLlmError::InvalidArgs)?;
+    let mut current = DEVICE.lock().unwrap_or_else(|e| e.into_inner());
+    if *current != config && loaded_models > 0 {
+        return Err(PluginLlmError::InvalidArgs(format!(
+            "device config can't change while {} model(s) are loaded; unload them first",
+            loaded_models
+        )));
+    }
+    *current = config;
+    Ok(())
+}
+
+/// Refuse a model of `model_bytes` if the models staying loaded already use
+/// `resident_bytes` and the sum exceeds the budget
+pub fn check_budget(model_bytes: u64, resident_bytes: u64) -> Result<(), PluginLlmError> {
+    match get().memory_budget_bytes {
+        Some(budget) if resident_bytes.saturating_add(model_bytes) > budget => {
+            Err(PluginLlmError::MemoryBudgetExceeded {
+                model_bytes,
+                resident_bytes,
+                budget_bytes: budget,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_only_change_with_no_models_loaded() {
+        let capped = DeviceConfig {
+            index: 0,
+            memory_budget_bytes: Some(1000),
+        };
+        set(capped, 0).unwrap();
+        assert_eq!(get(), capped);
+        // Re-applying the active settings is allowed while loaded
+        set(capped, 2).unwrap();
+
+        let e = set(DeviceConfig::default(), 2).unwrap_err();
+        assert_eq!(e.code(), "invalid_args");
+        assert!(e.to_string().contains("unload them first"), "{}", e);
+        let e = set(DeviceConfig { index: 1, ..capped }, 0).unwrap_err();
+        assert!(e.to_string().contains("device 1"), "{}", e);
+
+        assert!(check_budget(400, 600).is_ok());
+        let e = check_budget(401, 600).unwrap_err();
+        assert_eq!(e.code(), "memory_budget_exceeded");
+
+        set(DeviceConfig::default(), 0).unwrap();
+        assert!(check_budget(u64::MAX, 1).is_ok());
+    }
+}
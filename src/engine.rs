@@ -0,0 +1,203 @@
+//! The engine operations the plugin uses, behind the `Engine` trait
+//!
+//! Helpers work on `Client`: `lib_client_uzu::Client` in builds, and the
+//! scripted [`mock::MockClient`] in unit tests, so load, unload and
+//! generation paths can be tested without Metal or real weights.
+
+use crate::sampling::EngineSampling;
+use std::path::PathBuf;
+
+/// Engine handle kept per loaded model
+#[cfg(not(test))]
+pub type Client = lib_client_uzu::Client;
+#[cfg(test)]
+pub type Client = mock::MockClient;
+
+/// One engine generation
+pub struct Request<'a> {
+    pub prompt: &'a str,
+    pub max_tokens: Option<usize>,
+    pub sampling: EngineSampling,
+}
+
+/// What one engine generation produced
+pub struct Response {
+    pub tokens_generated: usize,
+    pub stopped: bool,
+    pub stop_reason: Option<String>,
+}
+
+/// Live model info from the engine
+pub struct ModelInfo {
+    pub name: String,
+    pub size: u64,
+    pub loaded: bool,
+}
+
+pub trait Engine: Sized {
+    /// Load the model at `path`
+    fn open(path: PathBuf) -> Result<Self, String>;
+
+    /// Run `request`; with `on_token`, each token is passed as it's produced
+    /// and returning `false` stops generation
+    fn generate(
+        &mut self,
+        request: Request<'_>,
+        on_token: Option<&mut dyn FnMut(&str) -> bool>,
+    ) -> Result<Response, String>;
+
+    fn tokenize(&self, text: &str) -> Result<Vec<u32>, String>;
+
+    fn model_info(&self) -> ModelInfo;
+
+    /// Free scratch buffers, returning the bytes reclaimed
+    fn release_transient_memory(&mut self) -> Result<u64, String>;
+}
+
+impl Engine for lib_client_uzu::Client {
+    fn open(path: PathBuf) -> Result<Self, String> {
+        lib_client_uzu::Client::new(path).map_err(|e| e.to_string())
+    }
+
+    fn generate(
+        &mut self,
+        request: Request<'_>,
+        on_token: Option<&mut dyn FnMut(&str) -> bool>,
+    ) -> Result<Response, String> {
+        let sampling = request.sampling;
+        let mut engine_request = lib_client_uzu::GenerateRequest::new(request.prompt);
+        if let Some(max) = request.max_tokens {
+            engine_request = engine_request.max_tokens(max);
+        }
+        if let Some(temp) = sampling.temperature {
+            engine_request = engine_request.temperature(temp);
+        }
+        if let Some(top_p) = sampling.top_p {
+            engine_request = engine_request.top_p(top_p);
+        }
+        if let Some(top_k) = sampling.top_k {
+            engine_request = engine_request.top_k(top_k);
+        }
+        if let Some(seed) = sampling.seed {
+            engine_request = engine_request.seed(seed);
+        }
+
+        let response = match on_token {
+            Some(on_token) => {
+                lib_client_uzu::Client::generate_stream(self, engine_request, on_token)
+            }
+            None => lib_client_uzu::Client::generate(self, engine_request),
+        }
+        .map_err(|e| e.to_string())?;
+        Ok(Response {
+            tokens_generated: response.tokens_generated,
+            stopped: response.stopped,
+            stop_reason: response.stop_reason,
+        })
+    }
+
+    fn tokenize(&self, text: &str) -> Result<Vec<u32>, String> {
+        lib_client_uzu::Client::tokenize(self, text).map_err(|e| e.to_string())
+    }
+
+    fn model_info(&self) -> ModelInfo {
+        let info = lib_client_uzu::Client::model_info(self);
+        ModelInfo {
+            name: info.name,
+            size: info.size,
+            loaded: info.loaded,
+        }
+    }
+
+    fn release_transient_memory(&mut self) -> Result<u64, String> {
+        lib_client_uzu::Client::release_transient_memory(self).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+pub mod mock {
+    //! Scripted engine for unit tests
+    //!
+    //! Any directory or file opens, except paths containing [`OPEN_FAILURE`].
+    //! Generation replies with `REPLY` one word per token, up to
+    //! `max_tokens`; a prompt containing [`GENERATE_FAILURE`] fails before
+    //! the first token. Tokenizing yields one id per byte.
+
+    use super::*;
+
+    /// Path fragment that makes `open` fail
+    pub const OPEN_FAILURE: &str = "mock-open-failure";
+    /// Prompt fragment that makes `generate` fail
+    pub const GENERATE_FAILURE: &str = "<mock-generate-failure>";
+    pub const REPLY: &str = "one two three four";
+
+    pub struct MockClient {
+        path: PathBuf,
+    }
+
+    impl Engine for MockClient {
+        fn open(path: PathBuf) -> Result<Self, String> {
+            if path.to_string_lossy().contains(OPEN_FAILURE) {
+                return Err("mock engine refused to open the model".to_string());
+            }
+            Ok(Self { path })
+        }
+
+        fn generate(
+            &mut self,
+            request: Request<'_>,
+            mut on_token: Option<&mut dyn FnMut(&str) -> bool>,
+        ) -> Result<Response, String> {
+            if request.prompt.contains(GENERATE_FAILURE) {
+                return Err("mock engine failed to generate".to_string());
+            }
+            let limit = request.max_tokens.unwrap_or(usize::MAX);
+            let mut tokens_generated = 0;
+            for (i, word) in REPLY.split(' ').enumerate() {
+                if tokens_generated == limit {
+                    return Ok(Response {
+                        tokens_generated,
+                        stopped: true,
+                        stop_reason: Some("max_tokens".to_string()),
+                    });
+                }
+                let token = if i == 0 {
+                    word.to_string()
+                } else {
+                    format!(" {}", word)
+                };
+                tokens_generated += 1;
+                if let Some(on_token) = on_token.as_mut() {
+                    if !on_token(&token) {
+                        break;
+                    }
+                }
+            }
+            Ok(Response {
+                tokens_generated,
+                stopped: true,
+                stop_reason: Some("eos".to_string()),
+            })
+        }
+
+        fn tokenize(&self, text: &str) -> Result<Vec<u32>, String> {
+            Ok(text.bytes().map(u32::from).collect())
+        }
+
+        fn model_info(&self) -> ModelInfo {
+            ModelInfo {
+                name: self
+                    .path
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                size: 0,
+                loaded: true,
+            }
+        }
+
+        fn release_transient_memory(&mut self) -> Result<u64, String> {
+            Ok(0)
+        }
+    }
+}
@@ -0,0 +1,296 @@
+//! Plugin error type
+//!
+//! Model management and engine calls return `PluginLlmError` so callers can
+//! match on the failure kind. The ABI boundary still carries plain messages:
+//! `Display` renders the same text the plugin has always returned, and any
+//! message (typed or not) maps back to its machine-readable code.
+
+use std::fmt;
+
+/// Message prefixes of untyped errors and their codes
+const MESSAGE_CODES: &[(&str, &str)] = &[
+    ("Usage:", "usage"),
+    ("Unknown command", "unknown_command"),
+    ("Unknown method", "unknown_method"),
+    ("Unknown message type", "unknown_message"),
+    ("Method does not support streaming", "unknown_method"),
+    ("Invalid arguments", "invalid_args"),
+    ("Invalid config", "invalid_config"),
+    ("Model not loaded", "model_not_loaded"),
+    ("Session invalidated", "session_invalidated"),
+    ("Model not found", "model_not_found"),
+    ("Invalid model path", "invalid_model_path"),
+    ("Failed to load model", "model_load_failed"),
+    ("Model corrupt", "model_corrupt"),
+    ("Download failed", "download_failed"),
+    ("Failed to lock", "lock_poisoned"),
+    ("Models not initialized", "not_initialized"),
+    ("Payload too large", "payload_too_large"),
+    ("Context overflow", "context_overflow"),
+    ("Draining", "draining"),
+    ("Service busy", "service_busy"),
+    ("Rate limited", "rate_limited"),
+    ("Memory budget exceeded", "memory_budget_exceeded"),
+    ("Budget exhausted", "budget_exhausted"),
+    ("Unsupported platform", "unsupported_platform"),
+    ("Unsupported capability", "unsupported_capability"),
+    ("Unsupported", "unsupported"),
+    ("Generation failed", "generation_failed"),
+    ("Tokenization failed", "tokenization_failed"),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PluginLlmError {
+    /// No model is registered under this key
+    ModelNotLoaded(String),
+    /// Nothing exists at the model path
+    ModelNotFound(String),
+    /// A bare name that is neither an alias nor an existing path
+    UnknownModel(String),
+    /// The model path exists but isn't a model (path, what it is instead)
+    InvalidModelPath(String, String),
+    /// The engine could not open the model
+    ModelLoadFailed(String),
+    /// Model files are missing, truncated or fail their checksums (path, problems)
+    ModelCorrupt(String, Vec<String>),
+    /// Fetching an `hf:` model into the cache failed
+    DownloadFailed(String),
+    /// A lock was poisoned by a panicking holder (what, cause)
+    LockPoisoned(&'static str, String),
+    /// The registry is used before `init` or after `shutdown`
+    NotInitialized,
+    InvalidArgs(String),
+    /// The init config is malformed (why)
+    InvalidConfig(String),
+    GenerationFailed(String),
+    TokenizationFailed(String),
+    /// The prompt plus the reply would exceed the context window
+    ContextOverflow {
+        prompt_tokens: usize,
+        reply_tokens: usize,
+        limit: usize,
+    },
+    /// A request is over a size cap from the init config
+    PayloadTooLarge {
+        /// What was measured, e.g. "prompt bytes"
+        what: &'static str,
+        size: usize,
+        limit: usize,
+        /// Config key setting the limit
+        setting: &'static str,
+    },
+    /// The model's generation slots and queue are full (current load)
+    ServiceBusy(String),
+    /// The model's `rate_limit` bucket is empty
+    RateLimited {
+        model: String,
+        requests_per_minute: u32,
+        retry_after_ms: u64,
+    },
+    /// Loading the model would take the loaded models past `memory_budget_bytes`
+    MemoryBudgetExceeded {
+        model_bytes: u64,
+        resident_bytes: u64,
+        budget_bytes: u64,
+    },
+    /// Not Apple Silicon with Metal (why)
+    UnsupportedPlatform(String),
+    /// The model lacks what a method needs (model, capability, why)
+    UnsupportedCapability(String, &'static str, String),
+    /// Any other failure, carried as its message
+    Other(String),
+}
+
+impl PluginLlmError {
+    /// Machine-readable error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::ModelNotLoaded(_) => "model_not_loaded",
+            Self::ModelNotFound(_) | Self::UnknownModel(_) => "model_not_found",
+            Self::InvalidModelPath(..) => "invalid_model_path",
+            Self::ModelLoadFailed(_) => "model_load_failed",
+            Self::ModelCorrupt(..) => "model_corrupt",
+            Self::DownloadFailed(_) => "download_failed",
+            Self::LockPoisoned(..) => "lock_poisoned",
+            Self::NotInitialized => "not_initialized",
+            Self::InvalidArgs(_) => "invalid_args",
+            Self::InvalidConfig(_) => "invalid_config",
+            Self::GenerationFailed(_) => "generation_failed",
+            Self::TokenizationFailed(_) => "tokenization_failed",
+            Self::ContextOverflow { .. } => "context_overflow",
+            Self::PayloadTooLarge { .. } => "payload_too_large",
+            Self::ServiceBusy(_) => "service_busy",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::MemoryBudgetExceeded { .. } => "memory_budget_exceeded",
+            Self::UnsupportedPlatform(_) => "unsupported_platform",
+            Self::UnsupportedCapability(..) => "unsupported_capability",
+            Self::Other(message) => code_of(message),
+        }
+    }
+}
+
+impl fmt::Display for PluginLlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ModelNotLoaded(key) => write!(f, "Model not loaded: {}", key),
+            Self::ModelNotFound(path) => write!(f, "Model not found: {} does not exist", path),
+            Self::UnknownModel(name) => write!(
+                f,
+                "Model not found: {} is neither a model alias nor an existing path",
+                name
+            ),
+            Self::InvalidModelPath(path, reason) => {
+                write!(f, "Invalid model path: {} {}", path, reason)
+            }
+            Self::ModelLoadFailed(e) => write!(f, "Failed to load model: {}", e),
+            Self::ModelCorrupt(path, problems) => {
+                write!(f, "Model corrupt: {}: {}", path, problems.join("; "))
+            }
+            Self::DownloadFailed(e) => write!(f, "Download failed: {}", e),
+            Self::LockPoisoned(what, e) => write!(f, "Failed to lock {}: {}", what, e),
+            Self::NotInitialized => write!(f, "Models not initialized"),
+            Self::InvalidArgs(e) => write!(f, "Invalid arguments: {}", e),
+            Self::InvalidConfig(e) => write!(f, "Invalid config: {}", e),
+            Self::GenerationFailed(e) => write!(f, "Generation failed: {}", e),
+            Self::TokenizationFailed(e) => write!(f, "Tokenization failed: {}", e),
+            Self::ContextOverflow {
+                prompt_tokens,
+                reply_tokens,
+                limit,
+            } => write!(
+                f,
+                "Context overflow: {} prompt tokens + {} reply tokens exceeds limit of {} by {}",
+                prompt_tokens,
+                reply_tokens,
+                limit,
+                prompt_tokens + reply_tokens - limit
+            ),
+            Self::PayloadTooLarge {
+                what,
+                size,
+                limit,
+                setting,
+            } => write!(
+                f,
+                "Payload too large: {} {} exceeds limit of {} ({})",
+                what, size, limit, setting
+            ),
+            Self::ServiceBusy(load) => write!(f, "Service busy: {}", load),
+            Self::RateLimited {
+                model,
+                requests_per_minute,
+                retry_after_ms,
+            } => write!(
+                f,
+                "Rate limited: {} allows {} requests per minute (retry_after_ms: {})",
+                model, requests_per_minute, retry_after_ms
+            ),
+            Self::MemoryBudgetExceeded {
+                model_bytes,
+                resident_bytes,
+                budget_bytes,
+            } => write!(
+                f,
+                "Memory budget exceeded: model needs {} bytes, loaded models use {} of {}",
+                model_bytes, resident_bytes, budget_bytes
+            ),
+            Self::UnsupportedPlatform(why) => write!(
+                f,
+                "Unsupported platform: the Uzu engine requires Apple Silicon (arm64 macOS) with Metal ({})",
+                why
+            ),
+            Self::UnsupportedCapability(model, capability, why) => write!(
+                f,
+                "Unsupported capability: {} has no {} support ({})",
+                model, capability, why
+            ),
+            Self::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for PluginLlmError {}
+
+impl From<String> for PluginLlmError {
+    fn from(message: String) -> Self {
+        Self::Other(message)
+    }
+}
+
+impl From<PluginLlmError> for String {
+    fn from(e: PluginLlmError) -> Self {
+        e.to_string()
+    }
+}
+
+/// Machine-readable code for an error message, by its prefix
+pub fn code_of(message: &str) -> &'static str {
+    MESSAGE_CODES
+        .iter()
+        .find(|(prefix, _)| message.starts_with(prefix))
+        .map(|(_, code)| *code)
+        .unwrap_or("error")
+}
+
+/// Wait a `Rate limited` message asks for
+pub fn retry_after_ms(message: &str) -> Option<u64> {
+    message
+        .strip_suffix(')')?
+        .rsplit_once("(retry_after_ms: ")?
+        .1
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn typed_errors_keep_their_message_codes() {
+        let errors = [
+            PluginLlmError::ModelNotLoaded("m".to_string()),
+            PluginLlmError::ModelNotFound("m".to_string()),
+            PluginLlmError::UnknownModel("m".to_string()),
+            PluginLlmError::InvalidModelPath("m".to_string(), "is a file".to_string()),
+            PluginLlmError::ModelLoadFailed("bad file".to_string()),
+            PluginLlmError::ModelCorrupt(
+                "m".to_string(),
+                vec!["model.safetensors: missing".to_string()],
+            ),
+            PluginLlmError::DownloadFailed("org/model: HTTP 404".to_string()),
+            PluginLlmError::LockPoisoned("model", "poisoned".to_string()),
+            PluginLlmError::NotInitialized,
+            PluginLlmError::InvalidArgs("x".to_string()),
+            PluginLlmError::InvalidConfig("max_loaded_models must be at least 1".to_string()),
+            PluginLlmError::GenerationFailed("oom".to_string()),
+            PluginLlmError::TokenizationFailed("bad utf-8".to_string()),
+            PluginLlmError::ContextOverflow {
+                prompt_tokens: 10,
+                reply_tokens: 5,
+                limit: 12,
+            },
+            PluginLlmError::ServiceBusy("1 generations running and 0 queued".to_string()),
+            PluginLlmError::RateLimited {
+                model: "m".to_string(),
+                requests_per_minute: 6,
+                retry_after_ms: 250,
+            },
+            PluginLlmError::UnsupportedPlatform("Metal is not available".to_string()),
+        ];
+        for e in errors {
+            assert_eq!(code_of(&e.to_string()), e.code(), "{}", e);
+        }
+        assert_eq!(
+            PluginLlmError::ModelNotLoaded("m".to_string()).to_string(),
+            "Model not loaded: m"
+        );
+        let limited = PluginLlmError::RateLimited {
+            model: "m".to_string(),
+            requests_per_minute: 6,
+            retry_after_ms: 250,
+        };
+        assert_eq!(retry_after_ms(&limited.to_string()), Some(250));
+        assert_eq!(retry_after_ms("Service busy: 1 running"), None);
+    }
+}
@@ -0,0 +1,175 @@
+//! Models named by Hugging Face repo id (`hf:org/model`)
+//!
+//! `model_key` maps an id to its directory in the local cache, so the
+//! registry and everything keyed by it only ever see paths. The first load of
+//! a model missing from the cache downloads the repo there with `curl`, into
+//! a `.partial` directory that is renamed into place once complete.
+
+use crate::config;
+use crate::error::PluginLlmError;
+use serde_json::Value;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+/// Prefix marking a model path as a Hugging Face repo id
+pub const PREFIX: &str = "hf:";
+
+/// Hub used when `HF_ENDPOINT` isn't set
+const DEFAULT_ENDPOINT: &str = "https://huggingface.co";
+
+/// Held while downloading, so concurrent loads of one repo don't interleave
+static DOWNLOADS: Mutex<()> = Mutex::new(());
+
+/// Cache directory (`hf_cache_dir` init config), defaulting to
+/// `~/.cache/adi/llm-uzu/hf`
+pub fn cache_dir() -> PathBuf {
+    if let Some(dir) = &config::get().hf_cache_dir {
+        return dir.clone();
+    }
+    std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join(".cache/adi/llm-uzu/hf")
+}
+
+/// Cache directory of a well-formed `hf:org/model` id
+pub fn cache_path(id: &str) -> Option<PathBuf> {
+    repo_id(id).map(|repo| cache_dir().join(repo))
+}
+
+/// `org/model` of an `hf:` id, if it is one and is well-formed
+fn repo_id(id: &str) -> Option<&str> {
+    let repo = id.strip_prefix(PREFIX)?;
+    let parts: Vec<&str> = repo.split('/').collect();
+    let valid_part = |part: &&str| {
+        !part.is_empty()
+            && !part.starts_with('.')
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    (parts.len() == 2 && parts.iter().all(valid_part)).then_some(repo)
+}
+
+/// Download the repo behind a cache path if it isn't cached yet.
+///
+/// Paths outside the cache are left alone. A key still carrying the `hf:`
+/// prefix is an id `cache_path` rejected.
+pub fn ensure_cached(key: &str) -> Result<(), PluginLlmError> {
+    if key.starts_with(PREFIX) {
+        return Err(PluginLlmError::InvalidArgs(format!(
+            "{:?} is not a Hugging Face repo id of the form hf:org/model",
+            key
+        )));
+    }
+    let path = Path::new(key);
+    let Ok(repo) = path.strip_prefix(cache_dir()) else {
+        return Ok(());
+    };
+    if repo.components().count() != 2 {
+        return Ok(());
+    }
+
+    let _downloading = DOWNLOADS.lock().unwrap_or_else(|e| e.into_inner());
+    if path.exists() {
+        return Ok(());
+    }
+    download(&repo.to_string_lossy(), path)
+}
+
+fn download(repo: &str, dest: &Path) -> Result<(), PluginLlmError> {
+    let endpoint = std::env::var("HF_ENDPOINT").unwrap_or_else(|_| DEFAULT_ENDPOINT.to_string());
+    let endpoint = endpoint.trim_end_matches('/');
+    tracing::info!(
+        "Downloading {} from {} into {}",
+        repo,
+        endpoint,
+        dest.display()
+    );
+
+    let listing = curl(&format!("{}/api/models/{}", endpoint, repo), None)?;
+    let listing: Value = serde_json::from_slice(&listing)
+        .map_err(|e| failed(repo, format!("unreadable file listing: {}", e)))?;
+    let files: Vec<&str> = listing["siblings"]
+        .as_array()
+        .map(|s| s.iter().filter_map(|f| f["rfilename"].as_str()).collect())
+        .unwrap_or_default();
+    if files.is_empty() {
+        return Err(failed(repo, "the repo lists no files".to_string()));
+    }
+
+    let mut partial = dest.as_os_str().to_owned();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let _ = std::fs::remove_dir_all(&partial);
+    for file in files {
+        // File names come from the server; never write outside the repo dir
+        let relative = Path::new(file);
+        if !relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+        {
+            return Err(failed(
+                repo,
+                format!("refusing unsafe file name {:?}", file),
+            ));
+        }
+        let target = partial.join(relative);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| failed(repo, format!("{}: {}", parent.display(), e)))?;
+        }
+        let url = format!("{}/{}/resolve/main/{}", endpoint, repo, file);
+        curl(&url, Some(&target))?;
+    }
+
+    std::fs::rename(&partial, dest).map_err(|e| failed(repo, format!("{}: {}", dest.display(), e)))
+}
+
+/// Fetch `url` with curl, into `output` or returning the body.
+///
+/// Sends `HF_TOKEN` as a bearer token when set, for gated and private repos.
+fn curl(url: &str, output: Option<&Path>) -> Result<Vec<u8>, PluginLlmError> {
+    let mut command = Command::new("curl");
+    command.args(["--silent", "--show-error", "--fail", "--location"]);
+    if let Ok(token) = std::env::var("HF_TOKEN") {
+        command
+            .arg("--header")
+            .arg(format!("Authorization: Bearer {}", token));
+    }
+    if let Some(output) = output {
+        command.arg("--output").arg(output);
+    }
+    let result = command
+        .arg(url)
+        .output()
+        .map_err(|e| PluginLlmError::DownloadFailed(format!("could not run curl: {}", e)))?;
+    if !result.status.success() {
+        return Err(PluginLlmError::DownloadFailed(format!(
+            "{}: {}",
+            url,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )));
+    }
+    Ok(result.stdout)
+}
+
+fn failed(repo: &str, reason: String) -> PluginLlmError {
+    PluginLlmError::DownloadFailed(format!("{}: {}", repo, reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repo_id_requires_org_and_model() {
+        assert_eq!(repo_id("hf:org/model-1.5B"), Some("org/model-1.5B"));
+        assert_eq!(repo_id("org/model"), None);
+        assert_eq!(repo_id("hf:model"), None);
+        assert_eq!(repo_id("hf:org/model/extra"), None);
+        assert_eq!(repo_id("hf:../model"), None);
+        assert_eq!(repo_id("hf:org/"), None);
+    }
+}
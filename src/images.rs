@@ -0,0 +1,173 @@
+//! Image inputs for vision models (`images` on generate)
+//!
+//! Each image is a file path or base64 data. Images are decoded and checked
+//! against `max_image_bytes` and the PNG, JPEG, GIF and WebP signatures
+//! before the model is consulted, so a bad image fails the same way for
+//! every model. Models without a vision encoder in `config.json` are
+//! refused as `UnsupportedCapability`.
+
+use crate::{check_payload, config, error::PluginLlmError, model_files, unsupported};
+use serde::Deserialize;
+use std::path::Path;
+
+/// One image passed with a prompt
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageInput {
+    /// Image file on disk
+    Path(String),
+    /// Image bytes, standard base64 (a `data:` URL prefix is accepted)
+    DataBase64(String),
+}
+
+/// Image formats recognized by their leading bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png,
+    Jpeg,
+    Gif,
+    WebP,
+}
+
+impl ImageFormat {
+    fn sniff(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+            Some(Self::Png)
+        } else if bytes.starts_with(&[0xff, 0xd8, 0xff]) {
+            Some(Self::Jpeg)
+        } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+            Some(Self::Gif)
+        } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+            Some(Self::WebP)
+        } else {
+            None
+        }
+    }
+}
+
+/// Decode and check every image, then make sure `model_key` can take them.
+///
+/// Even a vision model fails here for now: `Client` only accepts a text
+/// prompt, so there is no multimodal input to hand the images to.
+pub fn check(model_key: &str, images: &[ImageInput]) -> Result<(), String> {
+    for (i, image) in images.iter().enumerate() {
+        decode(image).map_err(|e| match e {
+            PluginLlmError::InvalidArgs(why) => {
+                PluginLlmError::InvalidArgs(format!("images[{}]: {}", i, why))
+            }
+            e => e,
+        })?;
+    }
+
+    let capabilities = model_files::read_capabilities(Path::new(model_key))?;
+    if !capabilities.vision {
+        return Err(PluginLlmError::UnsupportedCapability(
+            model_key.to_string(),
+            "vision",
+            "config.json declares no vision encoder".to_string(),
+        )
+        .into());
+    }
+    Err(unsupported("images", "multimodal image input"))
+}
+
+/// Read or decode an image, returning its format
+fn decode(image: &ImageInput) -> Result<ImageFormat, PluginLlmError> {
+    let limit = config::get().max_image_bytes;
+    let check_size = |bytes: usize| check_payload("image bytes", bytes, limit, "max_image_bytes");
+    let unreadable = |path: &str, e: std::io::Error| {
+        PluginLlmError::InvalidArgs(format!("cannot read {}: {}", path, e))
+    };
+    let bytes = match image {
+        ImageInput::Path(path) => {
+            let size = std::fs::metadata(path)
+                .map_err(|e| unreadable(path, e))?
+                .len();
+            check_size(usize::try_from(size).unwrap_or(usize::MAX))?;
+            std::fs::read(path).map_err(|e| unreadable(path, e))?
+        }
+        ImageInput::DataBase64(data) => {
+            let data = strip_data_url(data);
+            // Decoded size is about 3/4 of the encoded length; refuse before decoding
+            check_size(data.len() / 4 * 3)?;
+            decode_base64(data).map_err(PluginLlmError::InvalidArgs)?
+        }
+    };
+    check_size(bytes.len())?;
+    ImageFormat::sniff(&bytes).ok_or_else(|| {
+        PluginLlmError::InvalidArgs("not a PNG, JPEG, GIF or WebP image".to_string())
+    })
+}
+
+/// `data:image/png;base64,AAAA` -> `AAAA`
+fn strip_data_url(data: &str) -> &str {
+    match data.strip_prefix("data:") {
+        Some(rest) => rest.split_once(";base64,").map_or(data, |(_, body)| body),
+        None => data,
+    }
+}
+
+/// Standard-alphabet base64, padding optional, whitespace ignored
+fn decode_base64(data: &str) -> Result<Vec<u8>, String> {
+    fn value(c: u8) -> Option<u32> {
+        match c {
+            b'A'..=b'Z' => Some(u32::from(c - b'A')),
+            b'a'..=b'z' => Some(u32::from(c - b'a') + 26),
+            b'0'..=b'9' => Some(u32::from(c - b'0') + 52),
+            b'+' => Some(62),
+            b'/' => Some(63),
+            _ => None,
+        }
+    }
+
+    let symbols: Vec<u8> = data.bytes().filter(|c| !c.is_ascii_whitespace()).collect();
+    let body = symbols
+        .strip_suffix(b"==")
+        .or_else(|| symbols.strip_suffix(b"="))
+        .unwrap_or(&symbols);
+    if body.is_empty() {
+        return Err("data_base64 must not be empty".to_string());
+    }
+    if body.len() % 4 == 1 {
+        return Err("data_base64 has a truncated final group".to_string());
+    }
+
+    let mut bytes = Vec::with_capacity(body.len() / 4 * 3 + 2);
+    for chunk in body.chunks(4) {
+        let mut group = 0;
+        for (i, &c) in chunk.iter().enumerate() {
+            let v = value(c)
+                .ok_or_else(|| format!("data_base64 has an invalid character {:?}", c as char))?;
+            group |= v << (18 - 6 * i);
+        }
+        let decoded = [(group >> 16) as u8, (group >> 8) as u8, group as u8];
+        bytes.extend_from_slice(&decoded[..chunk.len() - 1]);
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base64_images_decode_and_are_sniffed() {
+        // Start of a 1x1 GIF
+        let format = decode(&ImageInput::DataBase64(
+            "data:image/gif;base64,R0lGODlhAQABAAAAACw=".to_string(),
+        ));
+        assert_eq!(format.unwrap(), ImageFormat::Gif);
+        assert_eq!(&decode_base64("R0lGODlh").unwrap(), b"GIF89a");
+
+        assert_eq!(decode_base64("aGk").unwrap(), b"hi");
+        assert_eq!(decode_base64("aGk=").unwrap(), b"hi");
+        assert!(decode_base64("a").is_err());
+        assert!(decode_base64("aG!k").is_err());
+
+        let e = decode(&ImageInput::DataBase64("aGVsbG8gd29ybGQ=".to_string())).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Invalid arguments: not a PNG, JPEG, GIF or WebP image"
+        );
+    }
+}
@@ -0,0 +1,741 @@
+//! Inference service (`adi.llm.inference`)
+//!
+//! JSON method interface for programmatic access from other plugins.
+//! Arguments and results are JSON strings; errors are human-readable messages.
+
+use crate::error::PluginLlmError;
+use crate::images::ImageInput;
+use crate::sampling::{self, SamplingParams};
+use crate::stop_defaults::StopDefaults;
+use crate::{batch, capabilities, chat, chat_sessions, config, device, polling, sessions, stats};
+use crate::{
+    check_max_tokens, check_prompt_size, count_prompt_tokens, detokenize, device_config,
+    drain_generations, embed, generate_stream, generate_text, get_model_info, get_template_info,
+    health, inspect_model, list_models, list_models_detailed, load_model, load_model_bytes,
+    memory_usage, next_token_logits, prefix_overlap, preload_model, scan_models,
+    score_continuation, set_device_config, set_model_sampling, set_model_stops, start_session,
+    tokenize_text, undrain_generations, unload_model,
+};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+/// Inference service ID
+pub const SERVICE_INFERENCE: &str = "adi.llm.inference";
+
+/// Methods that can be invoked through `inference_invoke_stream`
+pub const STREAM_METHODS: &[&str] = &["generate_stream", "generate_batch", "subscribe", "preload"];
+
+/// Default number of candidates returned by `next_token_logits`
+const DEFAULT_LOGITS_TOP_K: usize = 20;
+
+/// Event callback for streaming methods; returning `false` stops the stream
+pub type EventSink<'a> = &'a mut dyn FnMut(&str) -> bool;
+
+/// Inference method descriptor
+#[derive(Debug, Clone)]
+pub struct InferenceMethod {
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+/// Arguments for `generate`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct GenerateArgs {
+    /// Empty uses the default model (`default_model` config, `set-default`)
+    #[serde(default)]
+    pub model_path: String,
+    pub prompt: String,
+    /// System instruction combined with `prompt` through the chat template
+    #[serde(default)]
+    pub system: Option<String>,
+    /// Images for vision models, each a file `path` or `data_base64`
+    #[serde(default)]
+    pub images: Option<Vec<ImageInput>>,
+    #[serde(flatten)]
+    pub sampling: SamplingParams,
+    /// Vocabulary token appended to the prompt before generation
+    #[serde(default)]
+    pub append_token: Option<String>,
+    /// Stop once a complete top-level JSON object or array has been produced
+    #[serde(default)]
+    pub stop_on_json_complete: bool,
+    /// Schema the output's JSON is validated against (implies `stop_on_json_complete`)
+    #[serde(default)]
+    pub json_schema: Option<serde_json::Value>,
+    /// Grammar to constrain decoding to
+    #[serde(default)]
+    pub grammar: Option<String>,
+    /// Bound on total generation time; partial output is returned on expiry
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Sequences that end generation; matched across token boundaries
+    #[serde(default)]
+    pub stop: Vec<String>,
+    /// Use only `stop`, not the model's default stop sequences
+    #[serde(default)]
+    pub replace_stop: bool,
+    /// Keep generating past EOS until `max_tokens` (default: the model's setting)
+    #[serde(default)]
+    pub ignore_eos: Option<bool>,
+    /// Substrings that must not appear in the output
+    #[serde(default)]
+    pub ban_substrings: Vec<String>,
+    /// Also return the output split into text/code `blocks`
+    #[serde(default)]
+    pub parse_markdown: bool,
+    /// Token ids sampling is restricted to (empty = unconstrained)
+    #[serde(default)]
+    pub allowed_tokens: Vec<u32>,
+    /// Flag output whose dominant script differs (e.g. "Latin", "Cyrillic")
+    #[serde(default)]
+    pub expect_script: Option<String>,
+    /// Also return the prompt's perplexity (`prompt_perplexity`)
+    #[serde(default)]
+    pub include_prompt_perplexity: bool,
+    /// Also return per-token `logprobs` of the generated tokens
+    #[serde(default)]
+    pub logprobs: Option<bool>,
+    /// Alternatives to list per token with `logprobs`
+    #[serde(default)]
+    pub top_logprobs: Option<usize>,
+    /// Also return `output_hash`, the SHA-256 of the output text
+    #[serde(default)]
+    pub include_output_hash: bool,
+    /// Also return `rendered_prompt`, the exact text sent to the engine
+    #[serde(default)]
+    pub include_rendered_prompt: bool,
+    /// Model to retry on once if the primary fails to load or generate
+    #[serde(default)]
+    pub fallback_model_path: Option<String>,
+    /// Small model proposing tokens for the main model to verify
+    #[serde(default)]
+    pub draft_model_path: Option<String>,
+    /// Resume from cached KV state for a previously seen prompt prefix
+    #[serde(default)]
+    pub prefix_cache: bool,
+    /// Session to charge this generation to (see `session_start`)
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// Drop the oldest prompt tokens instead of failing when prompt plus
+    /// `max_tokens` exceeds the context window
+    #[serde(default)]
+    pub truncate: bool,
+    /// Caller-chosen id under which `cancel` can stop this generation
+    #[serde(default)]
+    pub request_id: Option<String>,
+    /// Independent samples to generate; above 1 the result is an array
+    #[serde(default)]
+    pub n: Option<usize>,
+    /// Run every check and count prompt tokens, but don't generate
+    #[serde(default)]
+    pub validate_only: bool,
+}
+
+impl GenerateArgs {
+    /// Refuse a prompt over `max_prompt_bytes` or a `max_tokens` over
+    /// `max_tokens_limit` before any model work
+    pub fn check_limits(&self) -> Result<(), PluginLlmError> {
+        check_prompt_size(&self.prompt)?;
+        if let Some(system) = &self.system {
+            check_prompt_size(system)?;
+        }
+        check_max_tokens(self.sampling.max_tokens)
+    }
+}
+
+/// Arguments for `generate_stream`
+#[derive(Debug, Deserialize)]
+struct GenerateStreamArgs {
+    #[serde(flatten)]
+    generate: GenerateArgs,
+    /// Echoed on every event so hosts can multiplex streams
+    stream_id: String,
+}
+
+/// Arguments for `cancel`
+#[derive(Debug, Deserialize)]
+struct CancelArgs {
+    request_id: String,
+}
+
+/// Arguments for `load`
+#[derive(Debug, Deserialize)]
+struct LoadArgs {
+    model_path: String,
+    /// Name to refer to the model by instead of its path
+    #[serde(default)]
+    alias: Option<String>,
+    /// Sampling defaults for requests to this model (replacing any it has)
+    #[serde(default)]
+    sampling: Option<SamplingParams>,
+    /// Stop sequences and `ignore_eos` for requests to this model
+    /// (replacing any it has)
+    #[serde(default)]
+    stops: Option<StopDefaults>,
+}
+
+/// Arguments for `preload`
+#[derive(Debug, Deserialize)]
+struct PreloadArgs {
+    model_path: String,
+    /// Run a one-token generation after loading
+    #[serde(default = "default_true")]
+    warmup: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Arguments for `scan`
+#[derive(Debug, Deserialize)]
+struct ScanArgs {
+    /// Directory to walk instead of the configured models dir
+    #[serde(default)]
+    dir: Option<std::path::PathBuf>,
+}
+
+/// Arguments for `info`
+#[derive(Debug, Deserialize)]
+struct InfoArgs {
+    model_path: String,
+    /// Load the model if needed to report live engine info
+    #[serde(default)]
+    load: bool,
+}
+
+/// Arguments for methods that only take a model path
+#[derive(Debug, Deserialize)]
+struct ModelArgs {
+    model_path: String,
+}
+
+/// Arguments for `resolve_sampling`
+#[derive(Debug, Deserialize)]
+struct ResolveSamplingArgs {
+    #[serde(default)]
+    params: SamplingParams,
+}
+
+/// Arguments for `session_start`
+#[derive(Debug, Deserialize)]
+struct SessionStartArgs {
+    model_path: String,
+    /// Total tokens the session may generate across all turns
+    #[serde(default)]
+    token_budget: Option<usize>,
+}
+
+/// Arguments for `session_info` / `session_end` / `chat_end`
+#[derive(Debug, Deserialize)]
+struct SessionArgs {
+    session_id: String,
+}
+
+/// Arguments for `load_bytes`
+#[derive(Debug, Deserialize)]
+struct LoadBytesArgs {
+    key: String,
+    data_base64: String,
+}
+
+/// Arguments for `count_tokens`
+#[derive(Debug, Deserialize)]
+struct CountTokensArgs {
+    model_path: String,
+    text: String,
+}
+
+/// Arguments for `tokenize`
+#[derive(Debug, Deserialize)]
+struct TokenizeArgs {
+    model_path: String,
+    text: String,
+}
+
+/// Arguments for `detokenize`
+#[derive(Debug, Deserialize)]
+struct DetokenizeArgs {
+    model_path: String,
+    ids: Vec<u64>,
+}
+
+/// Arguments for `job_status` and `job_result`
+#[derive(Debug, Deserialize)]
+struct JobArgs {
+    /// Id returned by `generate_async`
+    job_id: String,
+}
+
+/// Arguments for `poll` and `unsubscribe`
+#[derive(Debug, Deserialize)]
+struct PollArgs {
+    request_id: String,
+    /// Defaults to the consumer created by `generate_async`
+    #[serde(default)]
+    consumer_id: Option<String>,
+}
+
+/// Arguments for `subscribe`
+#[derive(Debug, Deserialize)]
+struct SubscribeArgs {
+    request_id: String,
+}
+
+/// Arguments for `drain`
+#[derive(Debug, Default, Deserialize)]
+struct DrainArgs {
+    #[serde(default)]
+    timeout_ms: Option<u64>,
+}
+
+/// Arguments for `next_token_logits`
+#[derive(Debug, Deserialize)]
+struct NextTokenLogitsArgs {
+    model_path: String,
+    prompt: String,
+    #[serde(default)]
+    top_k: Option<usize>,
+}
+
+/// Arguments for `embed`
+#[derive(Debug, Deserialize)]
+struct EmbedArgs {
+    model_path: String,
+    input: EmbedInput,
+}
+
+/// A single string or a batch of strings
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum EmbedInput {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// Arguments for `prefix_overlap`
+#[derive(Debug, Deserialize)]
+struct PrefixOverlapArgs {
+    model_path: String,
+    prompt_a: String,
+    prompt_b: String,
+}
+
+/// Arguments for `score`
+#[derive(Debug, Deserialize)]
+struct ScoreArgs {
+    model_path: String,
+    prompt: String,
+    continuation: String,
+}
+
+/// List methods provided by the inference service
+pub fn inference_list_methods() -> Vec<InferenceMethod> {
+    vec![
+        InferenceMethod {
+            name: "generate",
+            description: "Generate text from a prompt",
+        },
+        InferenceMethod {
+            name: "generate_stream",
+            description: "Generate text, streaming tokens as events (stream only)",
+        },
+        InferenceMethod {
+            name: "generate_batch",
+            description:
+                "Generate several prompts with shared parameters (streams progress events)",
+        },
+        InferenceMethod {
+            name: "cancel",
+            description: "Stop a running generation by request_id, keeping its partial output",
+        },
+        InferenceMethod {
+            name: "chat",
+            description: "Reply to role-based messages using the model's chat template",
+        },
+        InferenceMethod {
+            name: "generate_async",
+            description: "Start a background generation and return a request_id to poll",
+        },
+        InferenceMethod {
+            name: "job_status",
+            description:
+                "State (running, done, error, cancelled) and chunks streamed so far of a generate_async job",
+        },
+        InferenceMethod {
+            name: "job_result",
+            description: "Final result of a finished generate_async job",
+        },
+        InferenceMethod {
+            name: "poll",
+            description: "Fetch tokens produced since the last poll and a done flag",
+        },
+        InferenceMethod {
+            name: "subscribe",
+            description:
+                "Add a consumer to a running request (streams its tokens when invoked as a stream)",
+        },
+        InferenceMethod {
+            name: "unsubscribe",
+            description: "Detach a consumer; the generation is cancelled when none remain",
+        },
+        InferenceMethod {
+            name: "chat_start",
+            description: "Start a chat session kept plugin-side, optionally with opening messages",
+        },
+        InferenceMethod {
+            name: "chat_append",
+            description: "Add turns to a chat session and generate the assistant reply",
+        },
+        InferenceMethod {
+            name: "chat_end",
+            description: "End a chat session",
+        },
+        InferenceMethod {
+            name: "session_start",
+            description: "Start a session with an optional token budget across turns",
+        },
+        InferenceMethod {
+            name: "session_info",
+            description: "Show session turns, tokens used and remaining budget",
+        },
+        InferenceMethod {
+            name: "session_end",
+            description: "End a session",
+        },
+        InferenceMethod {
+            name: "load",
+            description: "Load a model",
+        },
+        InferenceMethod {
+            name: "preload",
+            description: "Load a model and warm it up (streams phase events)",
+        },
+        InferenceMethod {
+            name: "load_bytes",
+            description:
+                "Load a model from in-memory bytes (unsupported: the engine loads from paths)",
+        },
+        InferenceMethod {
+            name: "unload",
+            description: "Unload a model",
+        },
+        InferenceMethod {
+            name: "health",
+            description: "Readiness check that never loads a model",
+        },
+        InferenceMethod {
+            name: "list",
+            description: "List loaded models",
+        },
+        InferenceMethod {
+            name: "list_detailed",
+            description: "List loaded models with size, memory, timestamps and usage",
+        },
+        InferenceMethod {
+            name: "info",
+            description: "Show model info",
+        },
+        InferenceMethod {
+            name: "inspect",
+            description: "Format, quantization, precision, parameter count and tokenizer read from a model's files (no load)",
+        },
+        InferenceMethod {
+            name: "capabilities",
+            description: "Service version, supported methods and parameters, model context lengths",
+        },
+        InferenceMethod {
+            name: "scan",
+            description: "Models found on disk under a directory, with metadata (no load)",
+        },
+        InferenceMethod {
+            name: "memory_usage",
+            description: "Estimated memory of each loaded model and in total",
+        },
+        InferenceMethod {
+            name: "device_config",
+            description: "Metal device and memory budget used for model loads",
+        },
+        InferenceMethod {
+            name: "set_device_config",
+            description:
+                "Set the Metal device and memory budget for later loads (no models loaded)",
+        },
+        InferenceMethod {
+            name: "drain",
+            description: "Stop accepting generations and wait for in-flight ones to finish",
+        },
+        InferenceMethod {
+            name: "undrain",
+            description: "Resume accepting generations",
+        },
+        InferenceMethod {
+            name: "stats",
+            description: "Generation counters and rejected requests by reason",
+        },
+        InferenceMethod {
+            name: "stats_reset",
+            description: "Zero all counters reported by stats",
+        },
+        InferenceMethod {
+            name: "resolve_sampling",
+            description: "Effective sampling settings for a parameter set, without generating",
+        },
+        InferenceMethod {
+            name: "count_tokens",
+            description: "Number of tokens a text encodes to (loads the model, no generation)",
+        },
+        InferenceMethod {
+            name: "tokenize",
+            description:
+                "Token ids and their text pieces for a text (loads the model, no generation)",
+        },
+        InferenceMethod {
+            name: "detokenize",
+            description: "Text of a token id sequence (read from the tokenizer file, no load)",
+        },
+        InferenceMethod {
+            name: "template_info",
+            description: "Chat template and recommended stop sequences (read from disk, no load)",
+        },
+        InferenceMethod {
+            name: "prefix_overlap",
+            description: "Number of leading tokens two prompts share (for prefix-cache planning)",
+        },
+        InferenceMethod {
+            name: "next_token_logits",
+            description: "Top-K next-token logits for a prompt (single forward pass, no sampling)",
+        },
+        InferenceMethod {
+            name: "embed",
+            description: "Embedding vectors for one string or a batch",
+        },
+        InferenceMethod {
+            name: "score",
+            description:
+                "Total and per-token logprob of a continuation given a prompt (no sampling)",
+        },
+    ]
+}
+
+/// Invoke an inference method with JSON arguments
+pub fn inference_invoke(method: &str, args: &str) -> Result<String, String> {
+    match method {
+        "generate" => {
+            let args = parse_generate_args(args)?;
+            generate_text(&args)
+        }
+        "generate_batch" => batch::generate_batch(&batch::parse_batch_args(args)?),
+        "cancel" => {
+            let args: CancelArgs = parse_args(args)?;
+            Ok(polling::cancel_job(&args.request_id).to_string())
+        }
+        "chat" => {
+            let args: chat::ChatArgs = parse_args(args)?;
+            chat::chat(&args)
+        }
+        "generate_async" => {
+            let args = parse_generate_args(args)?;
+            let request_id = polling::generate_async(args)?;
+            Ok(serde_json::json!({ "request_id": request_id, "job_id": request_id }).to_string())
+        }
+        "job_status" => {
+            let args: JobArgs = parse_args(args)?;
+            polling::job_status(&args.job_id)
+        }
+        "job_result" => {
+            let args: JobArgs = parse_args(args)?;
+            polling::job_result(&args.job_id)
+        }
+        "poll" => {
+            let args: PollArgs = parse_args(args)?;
+            polling::poll(&args.request_id, args.consumer_id.as_deref())
+        }
+        "subscribe" => {
+            let args: SubscribeArgs = parse_args(args)?;
+            let consumer_id = polling::subscribe(&args.request_id)?;
+            Ok(
+                serde_json::json!({ "request_id": args.request_id, "consumer_id": consumer_id })
+                    .to_string(),
+            )
+        }
+        "unsubscribe" => {
+            let args: PollArgs = parse_args(args)?;
+            polling::unsubscribe(&args.request_id, args.consumer_id.as_deref())
+        }
+        "chat_start" => chat_sessions::start(&parse_args(args)?),
+        "chat_append" => chat_sessions::append(&parse_args(args)?),
+        "chat_end" => {
+            let args: SessionArgs = parse_args(args)?;
+            chat_sessions::end(&args.session_id)?;
+            Ok(serde_json::json!({ "ended": args.session_id }).to_string())
+        }
+        "session_start" => {
+            let args: SessionStartArgs = parse_args(args)?;
+            start_session(&args.model_path, args.token_budget)
+        }
+        "session_info" => {
+            let args: SessionArgs = parse_args(args)?;
+            Ok(sessions::info(&args.session_id)?.to_string())
+        }
+        "session_end" => {
+            let args: SessionArgs = parse_args(args)?;
+            sessions::end(&args.session_id)?;
+            Ok(serde_json::json!({ "ended": args.session_id }).to_string())
+        }
+        "load" => {
+            let args: LoadArgs = parse_args(args)?;
+            if let Some(sampling) = &args.sampling {
+                config::check_sampling_defaults(sampling)
+                    .map_err(|e| format!("Invalid arguments: sampling: {}", e))?;
+            }
+            if let Some(stops) = &args.stops {
+                stops
+                    .validate()
+                    .map_err(|e| format!("Invalid arguments: stops: {}", e))?;
+            }
+            load_model(&args.model_path, args.alias.as_deref())?;
+            if let Some(sampling) = args.sampling {
+                set_model_sampling(&args.model_path, sampling)?;
+            }
+            if let Some(stops) = args.stops {
+                set_model_stops(&args.model_path, stops)?;
+            }
+            Ok(serde_json::json!({ "loaded": args.model_path, "alias": args.alias }).to_string())
+        }
+        "preload" => {
+            let args: PreloadArgs = parse_args(args)?;
+            preload_model(&args.model_path, args.warmup, None)
+        }
+        "load_bytes" => {
+            let args: LoadBytesArgs = parse_args(args)?;
+            load_model_bytes(&args.key, &args.data_base64)
+        }
+        "unload" => {
+            let args: ModelArgs = parse_args(args)?;
+            unload_model(&args.model_path)?;
+            Ok(serde_json::json!({ "unloaded": args.model_path }).to_string())
+        }
+        "health" => Ok(health()),
+        "list" => serde_json::to_string(&list_models()).map_err(|e| e.to_string()),
+        "list_detailed" => {
+            serde_json::to_string(&list_models_detailed()?).map_err(|e| e.to_string())
+        }
+        "info" => {
+            let args: InfoArgs = parse_args(args)?;
+            get_model_info(&args.model_path, args.load)
+        }
+        "inspect" => {
+            let args: ModelArgs = parse_args(args)?;
+            inspect_model(&args.model_path)
+        }
+        "capabilities" => Ok(capabilities::capabilities()),
+        "scan" => {
+            let args: ScanArgs = parse_args(args)?;
+            scan_models(args.dir)
+        }
+        "memory_usage" => memory_usage(),
+        "device_config" => device_config(),
+        "set_device_config" => {
+            let args: device::DeviceConfig = parse_args(args)?;
+            set_device_config(args)
+        }
+        "drain" => {
+            let args: DrainArgs = parse_args(args)?;
+            Ok(drain_generations(args.timeout_ms))
+        }
+        "undrain" => Ok(undrain_generations()),
+        "stats" => Ok(stats::snapshot().to_string()),
+        "stats_reset" => {
+            stats::reset();
+            Ok(stats::snapshot().to_string())
+        }
+        "resolve_sampling" => {
+            let args: ResolveSamplingArgs = parse_args(args)?;
+            let defaults = &config::get().default_sampling;
+            let resolved = sampling::resolve(&args.params.or(defaults))?;
+            serde_json::to_string(&resolved).map_err(|e| e.to_string())
+        }
+        "count_tokens" => {
+            let args: CountTokensArgs = parse_args(args)?;
+            check_prompt_size(&args.text)?;
+            let token_count = count_prompt_tokens(&args.model_path, &args.text)?;
+            Ok(serde_json::json!({ "token_count": token_count }).to_string())
+        }
+        "tokenize" => {
+            let args: TokenizeArgs = parse_args(args)?;
+            tokenize_text(&args.model_path, &args.text)
+        }
+        "detokenize" => {
+            let args: DetokenizeArgs = parse_args(args)?;
+            detokenize(&args.model_path, &args.ids)
+        }
+        "template_info" => {
+            let args: ModelArgs = parse_args(args)?;
+            get_template_info(&args.model_path)
+        }
+        "prefix_overlap" => {
+            let args: PrefixOverlapArgs = parse_args(args)?;
+            prefix_overlap(&args.model_path, &args.prompt_a, &args.prompt_b)
+        }
+        "embed" => {
+            let args: EmbedArgs = parse_args(args)?;
+            let inputs = match args.input {
+                EmbedInput::One(text) => vec![text],
+                EmbedInput::Many(texts) => texts,
+            };
+            embed(&args.model_path, &inputs)
+        }
+        "next_token_logits" => {
+            let args: NextTokenLogitsArgs = parse_args(args)?;
+            next_token_logits(
+                &args.model_path,
+                &args.prompt,
+                args.top_k.unwrap_or(DEFAULT_LOGITS_TOP_K),
+            )
+        }
+        "score" => {
+            let args: ScoreArgs = parse_args(args)?;
+            score_continuation(&args.model_path, &args.prompt, &args.continuation)
+        }
+        _ => Err(format!("Unknown method: {}", method)),
+    }
+}
+
+/// Invoke a streaming inference method, passing each event to `on_event`.
+///
+/// Returns the method's final result once the stream ends.
+pub fn inference_invoke_stream(
+    method: &str,
+    args: &str,
+    on_event: EventSink,
+) -> Result<String, String> {
+    match method {
+        "generate_stream" => {
+            let args: GenerateStreamArgs = parse_args(args)?;
+            args.generate.check_limits()?;
+            generate_stream(&args.generate, &args.stream_id, on_event)
+        }
+        "generate_batch" => batch::generate_batch_stream(&batch::parse_batch_args(args)?, on_event),
+        "subscribe" => {
+            let args: SubscribeArgs = parse_args(args)?;
+            polling::attach(&args.request_id, on_event)
+        }
+        "preload" => {
+            let args: PreloadArgs = parse_args(args)?;
+            preload_model(&args.model_path, args.warmup, Some(on_event))
+        }
+        _ => Err(format!("Method does not support streaming: {}", method)),
+    }
+}
+
+fn parse_args<T: DeserializeOwned>(args: &str) -> Result<T, String> {
+    serde_json::from_str(args).map_err(|e| format!("Invalid arguments: {}", e))
+}
+
+/// Parse `GenerateArgs`, refusing payloads over the configured caps
+fn parse_generate_args(args: &str) -> Result<GenerateArgs, String> {
+    let args: GenerateArgs = parse_args(args)?;
+    args.check_limits()?;
+    Ok(args)
+}
@@ -0,0 +1,337 @@
+//! Detailed on-disk metadata of one model (`inspect`)
+//!
+//! Unlike `info`, nothing is loaded, and unlike `scan`, the weight files are
+//! opened: safetensors headers give tensor dtypes and parameter counts, GGUF
+//! headers the format version and tensor count. The tensor data itself is
+//! never read.
+
+use crate::error::PluginLlmError;
+use crate::model_files;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Largest safetensors JSON header read (real headers are a few MiB at most)
+const MAX_SAFETENSORS_HEADER: u64 = 64 * 1024 * 1024;
+
+/// Weight file extensions, in the order a format is picked when a directory
+/// mixes them
+const WEIGHT_FORMATS: &[&str] = &["safetensors", "gguf", "bin"];
+
+#[derive(Debug, Serialize)]
+pub struct Inspection {
+    pub name: String,
+    pub architecture: Option<String>,
+    /// `safetensors`, `gguf` or `bin` (PyTorch pickle, headers not read)
+    pub format: Option<String>,
+    /// GGUF header version; safetensors has no versioned container
+    pub format_version: Option<u32>,
+    pub weight_files: Vec<String>,
+    /// Size of the weight files in bytes
+    pub size: u64,
+    /// `torch_dtype` from `config.json`, else the dtype holding most elements
+    pub precision: Option<String>,
+    /// Elements per tensor dtype across the safetensors files
+    pub dtypes: BTreeMap<String, u64>,
+    /// Elements stored in the safetensors files; packed quantized weights
+    /// count as stored, not unpacked
+    pub parameter_count: Option<u64>,
+    pub tensor_count: Option<u64>,
+    pub quantization: Option<Quantization>,
+    pub context_length: Option<usize>,
+    pub tokenizer: Tokenizer,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Quantization {
+    /// `quant_method` (e.g. "gptq", "awq"), else `"<bits>bit"` when only
+    /// bits are given
+    pub scheme: Option<String>,
+    pub bits: Option<u64>,
+    pub group_size: Option<u64>,
+    /// The config entry as given
+    pub config: Value,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Tokenizer {
+    /// `model.type` from `tokenizer.json` (e.g. "BPE", "Unigram")
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
+    /// `tokenizer_class` from `tokenizer_config.json`
+    pub class: Option<String>,
+    pub vocab_size: Option<u64>,
+}
+
+/// Read everything `inspect` reports about the model at `path`
+pub fn inspect(path: &Path) -> Result<Inspection, String> {
+    let info = model_files::read_static_info(path)?;
+    let config = model_files::read_config(path)?;
+    let weight_files = weight_files(path);
+    if config.is_none() && weight_files.is_empty() {
+        return Err(PluginLlmError::InvalidModelPath(
+            path.display().to_string(),
+            "is not a recognized model: no config.json or weight files".to_string(),
+        )
+        .into());
+    }
+    let config = config.unwrap_or_default();
+    let text_config = config.get("text_config").unwrap_or(&config);
+
+    let format = WEIGHT_FORMATS
+        .iter()
+        .find(|format| weight_files.iter().any(|file| extension(file) == **format))
+        .map(|format| format.to_string());
+
+    let mut dtypes = BTreeMap::new();
+    let mut tensor_count = None;
+    let mut format_version = None;
+    match format.as_deref() {
+        Some("safetensors") => {
+            let mut tensors = 0;
+            for file in weight_files
+                .iter()
+                .filter(|f| extension(f) == "safetensors")
+            {
+                tensors += read_safetensors(file, &mut dtypes)?;
+            }
+            tensor_count = Some(tensors);
+        }
+        Some("gguf") => {
+            let file = weight_files.iter().find(|f| extension(f) == "gguf");
+            if let Some(file) = file {
+                let (version, tensors) = read_gguf_header(file)?;
+                format_version = Some(version);
+                tensor_count = Some(tensors);
+            }
+        }
+        _ => {}
+    }
+    let parameter_count = (!dtypes.is_empty()).then(|| dtypes.values().sum());
+    let precision = text_config
+        .get("torch_dtype")
+        .or_else(|| config.get("torch_dtype"))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .or_else(|| {
+            dtypes
+                .iter()
+                .max_by_key(|(_, elements)| **elements)
+                .map(|(dtype, _)| dtype.clone())
+        });
+
+    Ok(Inspection {
+        name: info.name,
+        architecture: info.architecture,
+        format,
+        format_version,
+        weight_files: weight_files
+            .iter()
+            .filter_map(|f| f.file_name())
+            .map(|n| n.to_string_lossy().into_owned())
+            .collect(),
+        size: info.size,
+        precision,
+        dtypes,
+        parameter_count,
+        tensor_count,
+        quantization: info.quantization.map(quantization),
+        context_length: info.context_length,
+        tokenizer: tokenizer(path, text_config)?,
+    })
+}
+
+fn quantization(config: Value) -> Quantization {
+    let number = |key: &str| config.get(key).and_then(Value::as_u64);
+    let bits = number("bits").or_else(|| number("w_bit"));
+    let group_size = number("group_size").or_else(|| number("q_group_size"));
+    let scheme = config
+        .get("quant_method")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .or_else(|| bits.map(|bits| format!("{}bit", bits)));
+    Quantization {
+        scheme,
+        bits,
+        group_size,
+        config,
+    }
+}
+
+fn tokenizer(path: &Path, text_config: &Value) -> Result<Tokenizer, String> {
+    let tokenizer = model_files::read_tokenizer(path)?;
+    let tokenizer_config = model_files::read_tokenizer_config(path)?;
+    Ok(Tokenizer {
+        kind: tokenizer
+            .as_ref()
+            .and_then(|t| t.pointer("/model/type"))
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        class: tokenizer_config
+            .as_ref()
+            .and_then(|c| c.get("tokenizer_class"))
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        vocab_size: text_config.get("vocab_size").and_then(Value::as_u64),
+    })
+}
+
+/// Weight files of the model, sorted; the file itself for single-file models
+fn weight_files(path: &Path) -> Vec<PathBuf> {
+    if path.is_file() {
+        return vec![path.to_path_buf()];
+    }
+    let mut files: Vec<PathBuf> = std::fs::read_dir(path)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|file| file.is_file() && WEIGHT_FORMATS.contains(&extension(file)))
+                .collect()
+        })
+        .unwrap_or_default();
+    files.sort();
+    files
+}
+
+fn extension(path: &Path) -> &str {
+    path.extension().and_then(|e| e.to_str()).unwrap_or("")
+}
+
+/// Add the elements per dtype in one safetensors file to `dtypes`,
+/// returning its tensor count
+fn read_safetensors(file: &Path, dtypes: &mut BTreeMap<String, u64>) -> Result<u64, String> {
+    let invalid = |why: &str| format!("Invalid {}: {}", file.display(), why);
+    let mut reader =
+        File::open(file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+    let mut len = [0u8; 8];
+    reader
+        .read_exact(&mut len)
+        .map_err(|_| invalid("truncated safetensors header"))?;
+    let len = u64::from_le_bytes(len);
+    if len > MAX_SAFETENSORS_HEADER {
+        return Err(invalid("safetensors header is implausibly large"));
+    }
+    let mut header = Vec::with_capacity(len as usize);
+    reader
+        .take(len)
+        .read_to_end(&mut header)
+        .map_err(|e| format!("Failed to read {}: {}", file.display(), e))?;
+    if header.len() as u64 != len {
+        return Err(invalid("truncated safetensors header"));
+    }
+    let header: BTreeMap<String, Value> =
+        serde_json::from_slice(&header).map_err(|e| invalid(&e.to_string()))?;
+
+    let mut tensors = 0;
+    for (name, tensor) in &header {
+        if name == "__metadata__" {
+            continue;
+        }
+        let dtype = tensor
+            .get("dtype")
+            .and_then(Value::as_str)
+            .ok_or_else(|| invalid(&format!("tensor {} has no dtype", name)))?;
+        let elements = tensor
+            .get("shape")
+            .and_then(Value::as_array)
+            .ok_or_else(|| invalid(&format!("tensor {} has no shape", name)))?
+            .iter()
+            .map(|dim| dim.as_u64().unwrap_or(0))
+            .product::<u64>();
+        *dtypes.entry(dtype.to_string()).or_insert(0) += elements;
+        tensors += 1;
+    }
+    Ok(tensors)
+}
+
+/// GGUF version and tensor count from the fixed file header
+fn read_gguf_header(file: &Path) -> Result<(u32, u64), String> {
+    let mut header = [0u8; 16];
+    File::open(file)
+        .and_then(|mut f| f.read_exact(&mut header))
+        .map_err(|_| format!("Invalid {}: truncated GGUF header", file.display()))?;
+    if &header[..4] != b"GGUF" {
+        return Err(format!("Invalid {}: not a GGUF file", file.display()));
+    }
+    let version = u32::from_le_bytes(header[4..8].try_into().expect("4 bytes"));
+    let tensors = u64::from_le_bytes(header[8..16].try_into().expect("8 bytes"));
+    Ok((version, tensors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("uzu-inspect-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn safetensors(header: &str) -> Vec<u8> {
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend_from_slice(header.as_bytes());
+        bytes
+    }
+
+    #[test]
+    fn inspect_reads_headers_quantization_and_tokenizer() {
+        let dir = scratch_dir("safetensors");
+        std::fs::write(
+            dir.join("config.json"),
+            r#"{"architectures": ["LlamaForCausalLM"], "max_position_embeddings": 4096,
+                "vocab_size": 32000, "quantization": {"bits": 4, "group_size": 64}}"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("tokenizer.json"), r#"{"model": {"type": "BPE"}}"#).unwrap();
+        std::fs::write(
+            dir.join("model.safetensors"),
+            safetensors(
+                r#"{"__metadata__": {"format": "pt"},
+                    "embed": {"dtype": "BF16", "shape": [32000, 64], "data_offsets": [0, 0]},
+                    "norm": {"dtype": "F32", "shape": [64], "data_offsets": [0, 0]}}"#,
+            ),
+        )
+        .unwrap();
+
+        let inspection = inspect(&dir).unwrap();
+        assert_eq!(inspection.format.as_deref(), Some("safetensors"));
+        assert_eq!(inspection.tensor_count, Some(2));
+        assert_eq!(inspection.parameter_count, Some(32000 * 64 + 64));
+        assert_eq!(inspection.precision.as_deref(), Some("BF16"));
+        let quantization = inspection.quantization.unwrap();
+        assert_eq!(quantization.scheme.as_deref(), Some("4bit"));
+        assert_eq!(quantization.group_size, Some(64));
+        assert_eq!(inspection.context_length, Some(4096));
+        assert_eq!(inspection.tokenizer.kind.as_deref(), Some("BPE"));
+        assert_eq!(inspection.tokenizer.vocab_size, Some(32000));
+
+        let gguf = dir.join("tiny.gguf");
+        let mut header = b"GGUF".to_vec();
+        header.extend_from_slice(&3u32.to_le_bytes());
+        header.extend_from_slice(&7u64.to_le_bytes());
+        std::fs::write(&gguf, header).unwrap();
+        let inspection = inspect(&gguf).unwrap();
+        assert_eq!(inspection.format.as_deref(), Some("gguf"));
+        assert_eq!(inspection.format_version, Some(3));
+        assert_eq!(inspection.tensor_count, Some(7));
+    }
+
+    #[test]
+    fn directories_without_model_files_are_not_recognized() {
+        let dir = scratch_dir("empty");
+        std::fs::write(dir.join("README.md"), "notes").unwrap();
+        let e = inspect(&dir).unwrap_err();
+        assert!(e.starts_with("Invalid model path:"), "{}", e);
+        assert!(e.contains("not a recognized model"), "{}", e);
+
+        std::fs::write(dir.join("model.safetensors"), [1, 0]).unwrap();
+        let e = inspect(&dir).unwrap_err();
+        assert!(e.contains("truncated safetensors header"), "{}", e);
+    }
+}
@@ -0,0 +1,285 @@
+//! Model file integrity checks run before a model is handed to the engine
+//!
+//! Always checked, since it only reads headers and directory listings:
+//! shards named by `model.safetensors.index.json` and files named by the
+//! `SHA256SUMS` manifest exist, and each safetensors file is as long as its
+//! header says. Hashing every file against `SHA256SUMS` reads all weights,
+//! so it only runs with the `verify_checksums` config.
+
+use crate::error::PluginLlmError;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// `sha256sum` output listing model files, next to the model
+pub const MANIFEST: &str = "SHA256SUMS";
+
+const SAFETENSORS_INDEX: &str = "model.safetensors.index.json";
+
+/// Check the model at `path`, listing every problem found in the error
+pub fn check(path: &Path, verify_checksums: bool) -> Result<(), PluginLlmError> {
+    let (dir, only) = if path.is_dir() {
+        (path.to_path_buf(), None)
+    } else {
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        (
+            dir,
+            path.file_name().map(|n| n.to_string_lossy().into_owned()),
+        )
+    };
+    let in_scope = |name: &str| only.as_deref().is_none_or(|only| only == name);
+
+    let mut problems = Vec::new();
+    let manifest = read_manifest(&dir.join(MANIFEST), &mut problems);
+    let mut expected: Vec<String> = manifest.iter().map(|(name, _)| name.clone()).collect();
+    expected.extend(index_shards(&dir.join(SAFETENSORS_INDEX), &mut problems));
+    expected.sort();
+    expected.dedup();
+    for name in expected.iter().filter(|name| in_scope(name)) {
+        if !dir.join(name).is_file() {
+            problems.push(format!("{}: missing", name));
+        }
+    }
+
+    for file in safetensors_files(&dir).iter().filter(|f| in_scope(f)) {
+        if let Err(problem) = check_safetensors_length(&dir.join(file)) {
+            problems.push(format!("{}: {}", file, problem));
+        }
+    }
+
+    if verify_checksums {
+        for (name, expected) in manifest.iter().filter(|(name, _)| in_scope(name)) {
+            let file = dir.join(name);
+            if !file.is_file() {
+                continue;
+            }
+            match sha256_file(&file) {
+                Ok(actual) if actual == *expected => {}
+                Ok(_) => problems.push(format!("{}: checksum mismatch", name)),
+                Err(e) => problems.push(format!("{}: {}", name, e)),
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(PluginLlmError::ModelCorrupt(
+            path.display().to_string(),
+            problems,
+        ))
+    }
+}
+
+/// `(file, lowercase hex digest)` lines of a `sha256sum` manifest
+fn read_manifest(path: &Path, problems: &mut Vec<String>) -> Vec<(String, String)> {
+    let Ok(content) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let mut entries = Vec::new();
+    for line in content.lines().filter(|l| !l.trim().is_empty()) {
+        // `<hex>  <name>`, or `<hex> *<name>` for binary mode
+        let parsed = line.split_once(' ').and_then(|(hex, name)| {
+            let name = name.trim_start_matches([' ', '*']);
+            let valid = hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit());
+            (valid && is_relative_name(name)).then(|| (name.to_string(), hex.to_ascii_lowercase()))
+        });
+        match parsed {
+            Some(entry) => entries.push(entry),
+            None => problems.push(format!("{}: malformed line {:?}", MANIFEST, line)),
+        }
+    }
+    entries
+}
+
+/// Shard files named by a safetensors index's `weight_map`
+fn index_shards(path: &Path, problems: &mut Vec<String>) -> Vec<String> {
+    let Ok(content) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+    let index: Value = match serde_json::from_str(&content) {
+        Ok(index) => index,
+        Err(e) => {
+            problems.push(format!("{}: {}", SAFETENSORS_INDEX, e));
+            return Vec::new();
+        }
+    };
+    index["weight_map"]
+        .as_object()
+        .map(|map| {
+            map.values()
+                .filter_map(Value::as_str)
+                .filter(|name| is_relative_name(name))
+                .map(str::to_string)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn safetensors_files(dir: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<String> = entries
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".safetensors"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// A safetensors file is an 8-byte header length, a JSON header, then the
+/// tensor data; the header's largest end offset gives the expected length
+fn check_safetensors_length(path: &Path) -> Result<(), String> {
+    let mut file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let actual = file.metadata().map_err(|e| e.to_string())?.len();
+
+    let mut len = [0u8; 8];
+    file.read_exact(&mut len)
+        .map_err(|_| format!("truncated ({} bytes, no header)", actual))?;
+    let header_len = u64::from_le_bytes(len);
+    if header_len > actual.saturating_sub(8) {
+        return Err(format!(
+            "truncated ({} bytes, header alone needs {})",
+            actual,
+            header_len + 8
+        ));
+    }
+    let mut header = vec![0u8; header_len as usize];
+    file.read_exact(&mut header).map_err(|e| e.to_string())?;
+    let header: Value =
+        serde_json::from_slice(&header).map_err(|e| format!("unreadable header: {}", e))?;
+
+    let data_len = header
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter_map(|(_, tensor)| tensor["data_offsets"].get(1)?.as_u64())
+        .max()
+        .unwrap_or(0);
+    let expected = 8 + header_len + data_len;
+    if actual < expected {
+        return Err(format!("truncated ({} of {} bytes)", actual, expected));
+    }
+    Ok(())
+}
+
+fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut file = std::fs::File::open(path).map_err(|e| e.to_string())?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 1 << 20];
+    loop {
+        let read = file.read(&mut buffer).map_err(|e| e.to_string())?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Names from model files must stay inside the model directory
+fn is_relative_name(name: &str) -> bool {
+    let path = PathBuf::from(name);
+    !name.is_empty()
+        && path
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh scratch directory under the system temp dir
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("uzu-integrity-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A safetensors file holding one 4-float tensor
+    fn safetensors() -> Vec<u8> {
+        let header = br#"{"w":{"dtype":"F32","shape":[4],"data_offsets":[0,16]}}"#;
+        let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+        bytes.extend_from_slice(header);
+        bytes.extend_from_slice(&[0u8; 16]);
+        bytes
+    }
+
+    fn problems(path: &Path, verify_checksums: bool) -> Vec<String> {
+        match check(path, verify_checksums) {
+            Ok(()) => Vec::new(),
+            Err(PluginLlmError::ModelCorrupt(_, problems)) => problems,
+            Err(e) => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn truncated_safetensors_file_is_reported() {
+        let dir = scratch_dir("truncated");
+        let full = safetensors();
+        std::fs::write(dir.join("model.safetensors"), &full).unwrap();
+        assert!(problems(&dir, false).is_empty());
+
+        std::fs::write(dir.join("model.safetensors"), &full[..full.len() - 6]).unwrap();
+        let e = check(&dir, false).unwrap_err();
+        assert_eq!(e.code(), "model_corrupt");
+        assert_eq!(
+            problems(&dir, false),
+            vec![format!(
+                "model.safetensors: truncated ({} of {} bytes)",
+                full.len() - 6,
+                full.len()
+            )]
+        );
+    }
+
+    #[test]
+    fn missing_shard_is_reported() {
+        let dir = scratch_dir("missing");
+        std::fs::write(dir.join("model-00001-of-00002.safetensors"), safetensors()).unwrap();
+        std::fs::write(
+            dir.join(SAFETENSORS_INDEX),
+            r#"{"weight_map": {
+                "a": "model-00001-of-00002.safetensors",
+                "b": "model-00002-of-00002.safetensors"
+            }}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            problems(&dir, false),
+            vec!["model-00002-of-00002.safetensors: missing"]
+        );
+    }
+
+    #[test]
+    fn checksums_are_only_verified_when_enabled() {
+        let dir = scratch_dir("checksums");
+        std::fs::write(dir.join("model.safetensors"), safetensors()).unwrap();
+        let digest = sha256_file(&dir.join("model.safetensors")).unwrap();
+        let wrong = "0".repeat(64);
+        std::fs::write(
+            dir.join(MANIFEST),
+            format!("{}  model.safetensors\n{}  tokenizer.json\n", digest, wrong),
+        )
+        .unwrap();
+        std::fs::write(dir.join("tokenizer.json"), "{}").unwrap();
+
+        assert!(problems(&dir, false).is_empty());
+        assert_eq!(
+            problems(&dir, true),
+            vec!["tokenizer.json: checksum mismatch"]
+        );
+        // A single-file path only checks that file
+        assert!(problems(&dir.join("model.safetensors"), true).is_empty());
+    }
+}
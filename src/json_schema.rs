@@ -0,0 +1,308 @@
+//! JSON Schema validation of generated output (`json_schema`)
+//!
+//! Covers the subset of JSON Schema used for tool-call arguments: `type`,
+//! `enum`, `const`, `properties`, `required`, `additionalProperties`, `items`,
+//! `minItems`/`maxItems`, `minLength`/`maxLength` and `minimum`/`maximum`.
+//! Other keywords are ignored, as JSON Schema does with unknown keywords.
+
+use serde_json::{Map, Value};
+
+const TYPES: &[&str] = &[
+    "null", "boolean", "object", "array", "number", "integer", "string",
+];
+
+/// Reject a schema whose supported keywords are malformed
+pub fn check_schema(schema: &Value) -> Result<(), String> {
+    check_at(schema, "#")
+}
+
+fn check_at(schema: &Value, path: &str) -> Result<(), String> {
+    let schema = match schema {
+        Value::Bool(_) => return Ok(()),
+        Value::Object(schema) => schema,
+        _ => return Err(format!("{} must be an object or boolean", path)),
+    };
+
+    if let Some(types) = schema.get("type") {
+        let names = match types {
+            Value::Array(names) => names.iter().collect(),
+            name => vec![name],
+        };
+        if let Some(name) = names
+            .into_iter()
+            .find(|name| !name.as_str().is_some_and(|n| TYPES.contains(&n)))
+        {
+            return Err(format!("{}/type has unknown type {}", path, name));
+        }
+    }
+    if schema.get("enum").is_some_and(|e| !e.is_array()) {
+        return Err(format!("{}/enum must be an array", path));
+    }
+    if let Some(properties) = schema.get("properties") {
+        let properties = properties
+            .as_object()
+            .ok_or_else(|| format!("{}/properties must be an object", path))?;
+        for (name, property) in properties {
+            check_at(
+                property,
+                &format!("{}/properties/{}", path, escape_pointer(name)),
+            )?;
+        }
+    }
+    if let Some(required) = schema.get("required") {
+        if !required
+            .as_array()
+            .is_some_and(|r| r.iter().all(Value::is_string))
+        {
+            return Err(format!("{}/required must be an array of strings", path));
+        }
+    }
+    for keyword in ["additionalProperties", "items"] {
+        if let Some(subschema) = schema.get(keyword) {
+            check_at(subschema, &format!("{}/{}", path, keyword))?;
+        }
+    }
+    for keyword in ["minItems", "maxItems", "minLength", "maxLength"] {
+        if schema.get(keyword).is_some_and(|v| !v.is_u64()) {
+            return Err(format!(
+                "{}/{} must be a non-negative integer",
+                path, keyword
+            ));
+        }
+    }
+    for keyword in ["minimum", "maximum"] {
+        if schema.get(keyword).is_some_and(|v| !v.is_number()) {
+            return Err(format!("{}/{} must be a number", path, keyword));
+        }
+    }
+    Ok(())
+}
+
+/// Parse the first JSON object or array in `text`; prose before it is skipped
+pub fn parse_output(text: &str) -> Result<Value, String> {
+    let start = text
+        .find(['{', '['])
+        .ok_or_else(|| "output contains no JSON object or array".to_string())?;
+    serde_json::Deserializer::from_str(&text[start..])
+        .into_iter::<Value>()
+        .next()
+        .unwrap_or(Ok(Value::Null))
+        .map_err(|e| format!("output is not valid JSON: {}", e))
+}
+
+/// Every violation of `schema` by `value`, as `<pointer>: <problem>`
+pub fn validate(value: &Value, schema: &Value) -> Vec<String> {
+    let mut errors = Vec::new();
+    validate_at(value, schema, "#", &mut errors);
+    errors
+}
+
+fn validate_at(value: &Value, schema: &Value, pointer: &str, errors: &mut Vec<String>) {
+    let schema = match schema {
+        Value::Bool(true) => return,
+        Value::Bool(false) => {
+            errors.push(format!("{}: no value is allowed here", pointer));
+            return;
+        }
+        Value::Object(schema) => schema,
+        _ => return,
+    };
+
+    if let Some(types) = schema.get("type") {
+        let allowed: Vec<&str> = match types {
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            name => name.as_str().into_iter().collect(),
+        };
+        if !allowed.iter().any(|t| has_type(value, t)) {
+            errors.push(format!(
+                "{}: expected {}, got {}",
+                pointer,
+                allowed.join(" or "),
+                type_name(value)
+            ));
+            return;
+        }
+    }
+    if let Some(Value::Array(options)) = schema.get("enum") {
+        if !options.contains(value) {
+            errors.push(format!(
+                "{}: {} is not one of the enum values",
+                pointer, value
+            ));
+        }
+    }
+    if let Some(expected) = schema.get("const") {
+        if value != expected {
+            errors.push(format!("{}: expected {}, got {}", pointer, expected, value));
+        }
+    }
+
+    match value {
+        Value::Object(object) => validate_object(object, schema, pointer, errors),
+        Value::Array(items) => {
+            check_length(schema, "Items", items.len(), pointer, errors);
+            if let Some(item_schema) = schema.get("items") {
+                for (index, item) in items.iter().enumerate() {
+                    validate_at(item, item_schema, &format!("{}/{}", pointer, index), errors);
+                }
+            }
+        }
+        Value::String(s) => check_length(schema, "Length", s.chars().count(), pointer, errors),
+        Value::Number(n) => {
+            let n = n.as_f64().unwrap_or_default();
+            if let Some(min) = schema.get("minimum").and_then(Value::as_f64) {
+                if n < min {
+                    errors.push(format!("{}: {} is less than minimum {}", pointer, n, min));
+                }
+            }
+            if let Some(max) = schema.get("maximum").and_then(Value::as_f64) {
+                if n > max {
+                    errors.push(format!(
+                        "{}: {} is greater than maximum {}",
+                        pointer, n, max
+                    ));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn validate_object(
+    object: &Map<String, Value>,
+    schema: &Map<String, Value>,
+    pointer: &str,
+    errors: &mut Vec<String>,
+) {
+    if let Some(Value::Array(required)) = schema.get("required") {
+        for name in required.iter().filter_map(Value::as_str) {
+            if !object.contains_key(name) {
+                errors.push(format!("{}: missing required property {:?}", pointer, name));
+            }
+        }
+    }
+
+    let properties = schema.get("properties").and_then(Value::as_object);
+    for (name, value) in object {
+        let path = format!("{}/{}", pointer, escape_pointer(name));
+        match properties.and_then(|p| p.get(name)) {
+            Some(property) => validate_at(value, property, &path, errors),
+            None => {
+                if let Some(additional) = schema.get("additionalProperties") {
+                    validate_at(value, additional, &path, errors);
+                }
+            }
+        }
+    }
+}
+
+/// `min<kind>` / `max<kind>` bounds on an array or string length
+fn check_length(
+    schema: &Map<String, Value>,
+    kind: &str,
+    len: usize,
+    pointer: &str,
+    errors: &mut Vec<String>,
+) {
+    let bound = |keyword: String| schema.get(&keyword).and_then(Value::as_u64);
+    if let Some(min) = bound(format!("min{}", kind)) {
+        if (len as u64) < min {
+            errors.push(format!(
+                "{}: length {} is below min{} {}",
+                pointer, len, kind, min
+            ));
+        }
+    }
+    if let Some(max) = bound(format!("max{}", kind)) {
+        if len as u64 > max {
+            errors.push(format!(
+                "{}: length {} is above max{} {}",
+                pointer, len, kind, max
+            ));
+        }
+    }
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "number" => value.is_number(),
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "string" => value.is_string(),
+        _ => false,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Escape a property name as a JSON Pointer token
+fn escape_pointer(name: &str) -> String {
+    name.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn check_schema_rejects_malformed_keywords() {
+        assert!(check_schema(&json!({ "type": "object", "required": ["a"] })).is_ok());
+        assert_eq!(
+            check_schema(&json!({ "properties": { "a": { "type": "text" } } })).unwrap_err(),
+            "#/properties/a/type has unknown type \"text\""
+        );
+        assert_eq!(
+            check_schema(&json!({ "required": "a" })).unwrap_err(),
+            "#/required must be an array of strings"
+        );
+        assert!(check_schema(&json!("object")).is_err());
+    }
+
+    #[test]
+    fn validate_reports_each_violation_by_pointer() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "name": { "type": "string", "minLength": 1 },
+                "count": { "type": "integer" },
+                "tags": { "type": "array", "items": { "enum": ["a", "b"] } }
+            },
+            "required": ["name", "count"],
+            "additionalProperties": false
+        });
+        let value = json!({ "name": "", "tags": ["a", "c"], "extra": 1 });
+        assert_eq!(
+            validate(&value, &schema),
+            vec![
+                "#: missing required property \"count\"",
+                "#/extra: no value is allowed here",
+                "#/name: length 0 is below minLength 1",
+                "#/tags/1: \"c\" is not one of the enum values",
+            ]
+        );
+        assert!(validate(&json!({ "name": "x", "count": 2 }), &schema).is_empty());
+    }
+
+    #[test]
+    fn parse_output_skips_leading_prose() {
+        assert_eq!(
+            parse_output("Sure: {\"a\": [1]} done").unwrap(),
+            json!({ "a": [1] })
+        );
+        assert!(parse_output("no json here").is_err());
+        assert!(parse_output("{\"a\": ").is_err());
+    }
+}
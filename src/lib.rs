@@ -3,19 +3,86 @@
 //! Provides local LLM inference on Apple Silicon using the Uzu engine.
 //! Optimized for M1/M2/M3 chips with Metal acceleration.
 
-use lib_client_uzu::{Client, GenerateRequest};
+use engine::{Client, Engine};
+use error::PluginLlmError;
 use lib_plugin_abi_v3::{
     async_trait,
     cli::{CliCommand, CliCommands, CliContext, CliResult},
-    Plugin, PluginContext, PluginMetadata, PluginType, Result as PluginResult, SERVICE_CLI_COMMANDS,
+    Plugin, PluginContext, PluginError, PluginMetadata, PluginType, Result as PluginResult,
+    SERVICE_CLI_COMMANDS,
 };
-use once_cell::sync::Mutex;
+use registry::Registry;
 use serde_json::json;
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use stop_defaults::StopDefaults;
+use stop_reason::StopReason;
 
-/// Loaded models (path -> Client)
-static MODELS: Mutex<Option<HashMap<String, Client>>> = Mutex::new(None);
+mod batch;
+mod benchmark;
+mod bulk_unload;
+mod cancel;
+mod capabilities;
+mod chat;
+mod chat_sessions;
+mod commands;
+mod config;
+mod decode;
+mod device;
+mod drain;
+mod engine;
+mod error;
+mod hf;
+mod images;
+mod inference;
+mod inspect;
+mod integrity;
+mod json_schema;
+mod logging;
+mod maintenance;
+mod messages;
+mod model_files;
+mod platform;
+mod polling;
+mod postprocess;
+mod rate_limit;
+mod registry;
+mod restore;
+mod sampling;
+mod scan;
+mod sessions;
+mod sse;
+mod stats;
+mod stop_defaults;
+mod stop_reason;
+mod vocab;
+
+pub use inference::{EventSink, GenerateArgs, InferenceMethod, SERVICE_INFERENCE};
+pub use sampling::{ResolvedSampling, SamplingParams};
+
+/// Banned-substring rejections allowed before giving up with `ban_exhausted`
+const MAX_BAN_RETRIES: usize = 3;
+
+/// Streaming token callback; returning `false` stops generation
+type TokenSink<'a> = &'a mut dyn FnMut(&str) -> bool;
+
+/// Whether transient GPU memory was already released for the current idle period
+static GPU_RELEASED: AtomicBool = AtomicBool::new(false);
+
+/// Loaded models (path -> client), locked per model
+static MODELS: Registry<Client> =
+    Registry::new(config::DEFAULT_MAX_LOADED_MODELS).on_evict(evicted_past_limit);
+
+/// Model used by generations that omit `model_path` (`default_model` config,
+/// `set-default`)
+static DEFAULT_MODEL: Mutex<Option<String>> = Mutex::new(None);
+
+/// When `init` last ran (`None` before init and after shutdown)
+static STARTED_AT: Mutex<Option<Instant>> = Mutex::new(None);
 
 /// Uzu LLM Plugin
 pub struct UzuLlmPlugin;
@@ -46,60 +113,57 @@ impl Plugin for UzuLlmPlugin {
         }
     }
 
-    async fn init(&mut self, _ctx: &PluginContext) -> PluginResult<()> {
-        // Initialize models hashmap
-        *MODELS.lock().unwrap() = Some(HashMap::new());
+    async fn init(&mut self, ctx: &PluginContext) -> PluginResult<()> {
+        let config = config::parse(&ctx.config).map_err(|e| {
+            tracing::error!("{}", e);
+            PluginError::InitFailed(e.to_string())
+        })?;
+        if let Err(e) = platform::check() {
+            if config.strict_platform_check {
+                return Err(PluginError::InitFailed(e.to_string()));
+            }
+            // Stay loadable so the CLI can explain the problem; loads will fail
+            tracing::error!("{}", e);
+        }
+        MODELS.init();
+        MODELS
+            .set_max_models(config.max_loaded_models)
+            .map_err(|e| PluginError::InitFailed(e.to_string()))?;
+        *DEFAULT_MODEL.lock().unwrap_or_else(|e| e.into_inner()) = config.default_model.clone();
+        device::set(config.device, 0).map_err(|e| PluginError::InitFailed(e.to_string()))?;
+        rate_limit::reset();
+        let restore_mode = config.restore_models;
+        config::set(config);
+        restore::restore(&ctx.data_dir, restore_mode);
+        maintenance::start();
+        *STARTED_AT.lock().unwrap_or_else(|e| e.into_inner()) = Some(Instant::now());
         Ok(())
     }
 
     async fn shutdown(&self) -> PluginResult<()> {
-        // Clear loaded models
-        if let Ok(mut models) = MODELS.lock() {
-            *models = None;
-        }
+        maintenance::stop();
+        *STARTED_AT.lock().unwrap_or_else(|e| e.into_inner()) = None;
+        *DEFAULT_MODEL.lock().unwrap_or_else(|e| e.into_inner()) = None;
+
+        restore::save();
+
+        MODELS.clear();
         Ok(())
     }
 
     fn provides(&self) -> Vec<&'static str> {
-        vec![SERVICE_CLI_COMMANDS]
+        if platform::is_supported() {
+            vec![SERVICE_CLI_COMMANDS, SERVICE_INFERENCE]
+        } else {
+            vec![SERVICE_CLI_COMMANDS]
+        }
     }
 }
 
 #[async_trait]
 impl CliCommands for UzuLlmPlugin {
     async fn list_commands(&self) -> Vec<CliCommand> {
-        vec![
-            CliCommand {
-                name: "load".to_string(),
-                description: "Load a model".to_string(),
-                args: vec![],
-                has_subcommands: false,
-            },
-            CliCommand {
-                name: "unload".to_string(),
-                description: "Unload a model".to_string(),
-                args: vec![],
-                has_subcommands: false,
-            },
-            CliCommand {
-                name: "list".to_string(),
-                description: "List loaded models".to_string(),
-                args: vec![],
-                has_subcommands: false,
-            },
-            CliCommand {
-                name: "generate".to_string(),
-                description: "Generate text".to_string(),
-                args: vec![],
-                has_subcommands: false,
-            },
-            CliCommand {
-                name: "info".to_string(),
-                description: "Show model info".to_string(),
-                args: vec![],
-                has_subcommands: false,
-            },
-        ]
+        commands::list()
     }
 
     async fn run_command(&self, ctx: &CliContext) -> PluginResult<CliResult> {
@@ -107,55 +171,17 @@ impl CliCommands for UzuLlmPlugin {
         let args: Vec<&str> = ctx.args.iter().map(|s| s.as_str()).collect();
         let options = ctx.options_as_json();
 
-        let result = match subcommand {
-            "load" => {
-                if args.is_empty() {
-                    Err("Usage: load <model-path>".to_string())
-                } else {
-                    load_model(args[0]).map(|_| format!("Model loaded: {}", args[0]))
-                }
-            }
-            "unload" => {
-                if args.is_empty() {
-                    Err("Usage: unload <model-path>".to_string())
-                } else {
-                    unload_model(args[0]).map(|_| format!("Model unloaded: {}", args[0]))
-                }
-            }
-            "list" => {
-                let models = list_models();
-                serde_json::to_string(&models).map_err(|e| e.to_string())
-            }
-            "generate" => {
-                if args.len() < 2 {
-                    Err("Usage: generate <model-path> <prompt> [--max-tokens <n>]".to_string())
-                } else {
-                    let path = args[0];
-                    let prompt = args[1..].join(" ");
-                    let max_tokens = options
-                        .get("max-tokens")
-                        .and_then(|v| v.as_str())
-                        .and_then(|s| s.parse().ok());
-                    let temperature = options
-                        .get("temperature")
-                        .and_then(|v| v.as_str())
-                        .and_then(|s| s.parse().ok());
-                    generate_text(path, &prompt, max_tokens, temperature)
-                }
-            }
-            "info" => {
-                if args.is_empty() {
-                    Err("Usage: info <model-path>".to_string())
-                } else {
-                    get_model_info(args[0])
-                }
-            }
-            "" | "help" => Ok(get_help()),
-            _ => Err(format!("Unknown command: {}", subcommand)),
-        };
+        let result = commands::run(subcommand, &args, &options);
+
+        if let Err(e) = &result {
+            stats::record_error(error_code(e));
+        }
 
         match result {
             Ok(output) => Ok(CliResult::success(output)),
+            Err(e) if option_flag(&options, "json-errors") || config::get().json_errors => {
+                Ok(CliResult::error(json_error(subcommand, &e)))
+            }
             Err(e) => Ok(CliResult::error(e)),
         }
     }
@@ -173,21 +199,112 @@ pub fn plugin_create_cli() -> Box<dyn CliCommands> {
     Box::new(UzuLlmPlugin::new())
 }
 
+/// List inference service methods
+#[no_mangle]
+pub fn plugin_inference_list_methods() -> Vec<InferenceMethod> {
+    inference::inference_list_methods()
+}
+
+/// Invoke an inference service method (JSON args in, JSON result out)
+#[no_mangle]
+pub fn plugin_inference_invoke(method: &str, args: &str) -> Result<String, String> {
+    // Readiness probes get an answer even where inference can't run
+    if method == "health" {
+        return Ok(health());
+    }
+    platform::check()
+        .map_err(String::from)
+        .and_then(|_| inference::inference_invoke(method, args))
+        .inspect_err(|e| stats::record_error(error_code(e)))
+}
+
+/// Invoke a streaming inference service method, receiving JSON events via `on_event`
+#[no_mangle]
+pub fn plugin_inference_invoke_stream(
+    method: &str,
+    args: &str,
+    on_event: EventSink,
+) -> Result<String, String> {
+    platform::check()
+        .map_err(String::from)
+        .and_then(|_| inference::inference_invoke_stream(method, args, on_event))
+        .inspect_err(|e| stats::record_error(error_code(e)))
+}
+
+/// Handle a host-initiated JSON message (`{"type": ...}`) and return the reply
+#[no_mangle]
+pub fn plugin_handle_message(message: &str) -> Result<String, String> {
+    messages::handle(message).inspect_err(|e| stats::record_error(error_code(e)))
+}
+
 // === Helper Functions ===
 
 fn get_help() -> String {
     r#"ADI Uzu LLM - Local LLM inference on Apple Silicon
 
 Commands:
-  load <model-path>           Load a model
+  load <model-path>           Load a model (--as <alias> to refer to it by name);
+                              hf:org/model downloads it into the cache first.
+                              --temperature, --top-p, --top-k, --max-tokens and
+                              --seed set the model's sampling defaults; --stop
+                              <a,b,...> sets default stop sequences and
+                              --ignore-eos keeps generating past EOS until
+                              max_tokens
+  preload <model-path>        Load a model and warm it up (--no-warmup to skip),
+                              reporting load_ms and warmup_ms
   unload <model-path>         Unload a model
-  list                        List loaded models
-  generate <path> <prompt>    Generate text
-  info <model-path>           Show model info
+  unload --older-than <d>     Unload models unused for d (90s, 30m, 2h, 1d) and/or
+         --matching <glob>    whose path or alias matches (* and ?); reports the
+                              unloaded ones and keeps models still generating
+  unload-all                  Unload every model
+  reload <model-path>         Unload and load a loaded model again (picks up new files)
+  list                        List loaded models (--detailed adds size, memory,
+                              timestamps and generations, most recent first)
+  generate <path> <prompt>    Generate text (<path> may be omitted once a default is set)
+  validate <path> <prompt>    Check generate arguments and count prompt tokens
+                              without generating (takes the generate options)
+  info <model-path>           Show model info (read from disk unless loaded;
+                              --load loads it for live engine info)
+  inspect <model-path>        Show a model's format, quantization, precision, parameter
+                              count, context length and tokenizer, without loading
+  scan [--dir <path>]         List models on disk with name, size and quantization,
+                              without loading (default dir: models_dir config)
+  memory                      Show estimated memory per loaded model and in total
+  benchmark <path>            Time a fixed prompt: one warm-up, then --iterations runs
+                              (default 5) of --max-tokens tokens (default 128)
+  test-template <path> <json> Render messages ([{"role","content"}]) through the
+                              chat template, special tokens shown as <|...|>
+  set-default <model-path>    Use this model when generate omits the model path
+  set-max-models <n>          Keep at most n models loaded, evicting least recently used
+  drain [--timeout-ms <n>]    Stop accepting generations, wait for in-flight ones
+  undrain                     Resume accepting generations
+  help                        Show this message
 
 Options:
   --max-tokens <n>            Maximum tokens to generate
+  --system <text>             System instruction, applied via the chat template
   --temperature <t>           Sampling temperature
+  --top-p <p>                 Nucleus sampling threshold (0.0-1.0)
+  --top-k <k>                 Sample from the k most likely tokens
+  --seed <n>                  RNG seed for reproducible output
+  --max-tokens-ratio <r>      Cap max tokens at r x prompt tokens
+  --retry-on-gibberish        Retry once at a lower temperature on gibberish output
+  --penalty-last-n <n>        Unsupported: repetition penalty window
+  --repetition-penalty <p>    Unsupported: only the neutral 1.0 is accepted
+  --frequency-penalty <p>     Unsupported: only the neutral 0.0 is accepted
+  --ban <a,b,...>             Comma-separated substrings that must not appear in output
+  --stop <a,b,...>            Comma-separated sequences that end generation
+  --timeout-ms <n>            Stop after n ms and return the partial output
+  --format <json|sse>         Print the result object (default), or server-sent
+                              events: one per token, the result, then [DONE]
+  --append-token <token>      Vocabulary token appended to the prompt (base models)
+  --stop-on-json-complete     Stop once a complete JSON object/array is produced
+  --truncate                  Drop the oldest prompt text if it overflows the context
+  --fallback-model <path>     Retry once on this model if the primary fails
+  --parse-markdown            Also return output split into text/code blocks
+  --include-output-hash       Also return the SHA-256 hex of the output text
+  --expect-script <script>    Flag output in another script (Latin, Cyrillic, Han, ...)
+  --json-errors               Report errors as JSON (or set ADI_UZU_JSON_ERRORS=1)
 
 Examples:
   adi llm-uzu load models/llama-3.2-1b.gguf
@@ -195,116 +312,2409 @@ Examples:
         .to_string()
 }
 
-fn load_model(path: &str) -> Result<(), String> {
-    let mut models = MODELS
-        .lock()
-        .map_err(|e| format!("Failed to lock models: {}", e))?;
+/// Machine-readable error code for an error message
+fn error_code(message: &str) -> &'static str {
+    error::code_of(message)
+}
 
-    let models_map = models
-        .as_mut()
-        .ok_or_else(|| "Models not initialized".to_string())?;
+/// Format a CLI error as `{"error": {"code", "message", "usage"}}`, plus
+/// `retry_after_ms` for rate-limited requests
+fn json_error(command: &str, message: &str) -> String {
+    let mut result = json!({
+        "error": {
+            "code": error_code(message),
+            "message": message,
+            "usage": commands::usage(command),
+        }
+    });
+    if let Some(retry_after_ms) = error::retry_after_ms(message) {
+        result["error"]["retry_after_ms"] = json!(retry_after_ms);
+    }
+    serde_json::to_string(&result).unwrap_or_default()
+}
 
-    if models_map.contains_key(path) {
-        return Ok(()); // Already loaded
+/// Whether a boolean CLI flag is set (`--flag` or `--flag true`)
+fn option_flag(options: &serde_json::Value, name: &str) -> bool {
+    match options.get(name) {
+        Some(serde_json::Value::Bool(b)) => *b,
+        Some(serde_json::Value::String(s)) => s.is_empty() || s == "true",
+        Some(serde_json::Value::Null) => true,
+        _ => false,
     }
+}
 
-    let client = Client::new(PathBuf::from(path))
-        .map_err(|e| format!("Failed to load model: {}", e))?;
+/// `generate` options that take a value
+const GENERATE_VALUE_OPTIONS: &[&str] = &[
+    "max-tokens",
+    "temperature",
+    "top-p",
+    "top-k",
+    "seed",
+    "max-tokens-ratio",
+    "penalty-last-n",
+    "repetition-penalty",
+    "frequency-penalty",
+    "ban",
+    "stop",
+    "timeout-ms",
+    "format",
+    "append-token",
+    "expect-script",
+    "fallback-model",
+    "system",
+];
 
-    models_map.insert(path.to_string(), client);
+/// `generate` options that are plain switches
+const GENERATE_FLAG_OPTIONS: &[&str] = &[
+    "retry-on-gibberish",
+    "parse-markdown",
+    "include-output-hash",
+    "stop-on-json-complete",
+    "truncate",
+];
+
+/// Positional `generate` args, and the options with any `--option` tokens
+/// found among them merged in
+fn split_generate_options<'a>(
+    args: &[&'a str],
+    options: &serde_json::Value,
+) -> Result<(Vec<&'a str>, serde_json::Value), String> {
+    let mut options = options.as_object().cloned().unwrap_or_default();
+    let mut positional = Vec::new();
+    let mut tokens = args.iter();
+    while let Some(token) = tokens.next() {
+        let Some(name) = token.strip_prefix("--") else {
+            positional.push(*token);
+            continue;
+        };
+        if GENERATE_VALUE_OPTIONS.contains(&name) {
+            let value = tokens
+                .next()
+                .ok_or_else(|| format!("Invalid arguments: --{} requires a value", name))?;
+            options.insert(name.to_string(), json!(value));
+        } else if GENERATE_FLAG_OPTIONS.contains(&name) || name == "json-errors" {
+            options.insert(name.to_string(), json!(true));
+        } else {
+            positional.push(*token);
+        }
+    }
+    Ok((positional, serde_json::Value::Object(options)))
+}
+
+/// Whether CLI `generate` prints server-sent events (`--format sse`) rather
+/// than the result object (`--format json`, the default)
+fn cli_generate_sse(args: &[&str], options: &serde_json::Value) -> Result<bool, String> {
+    let (_, options) = split_generate_options(args, options)?;
+    match cli_option::<String>(&options, "format", "json or sse")?.as_deref() {
+        None | Some("json") => Ok(false),
+        Some("sse") => Ok(true),
+        Some(other) => Err(format!(
+            "Invalid arguments: --format expects json or sse, got {:?}",
+            other
+        )),
+    }
+}
+
+/// Build `GenerateArgs` for the CLI `generate` command.
+///
+/// `--option` tokens left among the positional args are pulled out as well, so
+/// they never end up in the prompt.
+fn cli_generate_args(args: &[&str], options: &serde_json::Value) -> Result<GenerateArgs, String> {
+    let (positional, options) = split_generate_options(args, options)?;
+
+    // With a default model, a first word that names no model starts the prompt
+    let has_default = default_model_path().is_some();
+    let (model_path, prompt) = match positional.split_first() {
+        Some((first, rest)) if !rest.is_empty() && (!has_default || names_model(first)) => {
+            (first.to_string(), rest.join(" "))
+        }
+        Some(_) if has_default => (String::new(), positional.join(" ")),
+        _ => return Err(commands::usage_error("generate")),
+    };
+
+    let temperature: Option<f32> = cli_option(&options, "temperature", "a number")?;
+    if let Some(t) = temperature.filter(|t| *t < 0.0) {
+        return Err(format!(
+            "Invalid arguments: --temperature must be non-negative, got {}",
+            t
+        ));
+    }
+
+    Ok(GenerateArgs {
+        model_path,
+        prompt,
+        sampling: SamplingParams {
+            max_tokens: cli_option(&options, "max-tokens", "a non-negative integer")?,
+            temperature,
+            retry_on_gibberish: option_flag(&options, "retry-on-gibberish"),
+            top_p: cli_option(&options, "top-p", "a number")?,
+            top_k: cli_option(&options, "top-k", "a positive integer")?,
+            seed: cli_option(&options, "seed", "a non-negative integer")?,
+            max_tokens_ratio: cli_option(&options, "max-tokens-ratio", "a number")?,
+            penalty_last_n: cli_option(&options, "penalty-last-n", "a non-negative integer")?,
+            repetition_penalty: cli_option(&options, "repetition-penalty", "a number")?,
+            frequency_penalty: cli_option(&options, "frequency-penalty", "a number")?,
+        },
+        append_token: cli_option(&options, "append-token", "a token")?,
+        parse_markdown: option_flag(&options, "parse-markdown"),
+        include_output_hash: option_flag(&options, "include-output-hash"),
+        stop_on_json_complete: option_flag(&options, "stop-on-json-complete"),
+        truncate: option_flag(&options, "truncate"),
+        fallback_model_path: cli_option(&options, "fallback-model", "a model path")?,
+        expect_script: cli_option(&options, "expect-script", "a script name")?,
+        system: cli_option(&options, "system", "a system instruction")?,
+        ban_substrings: cli_list(&options, "ban")?,
+        timeout_ms: cli_option(&options, "timeout-ms", "a positive integer")?,
+        stop: cli_list(&options, "stop")?,
+        ..Default::default()
+    })
+}
+
+/// Parse a CLI option value, rejecting values that don't parse
+fn cli_option<T: std::str::FromStr>(
+    options: &serde_json::Value,
+    name: &str,
+    expected: &str,
+) -> Result<Option<T>, String> {
+    let value = match options.get(name) {
+        None | Some(serde_json::Value::Null) => return Ok(None),
+        Some(serde_json::Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    };
+    value.parse().map(Some).map_err(|_| {
+        format!(
+            "Invalid arguments: --{} expects {}, got {:?}",
+            name, expected, value
+        )
+    })
+}
+
+/// Comma-separated `--<name>` values (empty when the option is absent)
+fn cli_list(options: &serde_json::Value, name: &str) -> Result<Vec<String>, String> {
+    Ok(
+        cli_option::<String>(options, name, "a comma-separated list")?
+            .map(|s| s.split(',').map(str::to_string).collect())
+            .unwrap_or_default(),
+    )
+}
+
+/// Reject prompts larger than `max_prompt_bytes` before any expensive work
+fn check_prompt_size(prompt: &str) -> Result<(), PluginLlmError> {
+    check_prompt_bytes(prompt.len())
+}
+
+/// Like `check_prompt_size`, for a prompt of `bytes` still in parts
+fn check_prompt_bytes(bytes: usize) -> Result<(), PluginLlmError> {
+    check_payload(
+        "prompt bytes",
+        bytes,
+        config::get().max_prompt_bytes,
+        "max_prompt_bytes",
+    )
+}
+
+/// Reject a `max_tokens` above `max_tokens_limit`
+fn check_max_tokens(max_tokens: Option<usize>) -> Result<(), PluginLlmError> {
+    max_tokens.map_or(Ok(()), |requested| {
+        check_payload(
+            "max_tokens",
+            requested,
+            config::get().max_tokens_limit,
+            "max_tokens_limit",
+        )
+    })
+}
+
+fn check_payload(
+    what: &'static str,
+    size: usize,
+    limit: usize,
+    setting: &'static str,
+) -> Result<(), PluginLlmError> {
+    if size > limit {
+        return Err(PluginLlmError::PayloadTooLarge {
+            what,
+            size,
+            limit,
+            setting,
+        });
+    }
     Ok(())
 }
 
-fn unload_model(path: &str) -> Result<(), String> {
-    let mut models = MODELS
-        .lock()
-        .map_err(|e| format!("Failed to lock models: {}", e))?;
+/// Normalize a model path into its `MODELS` key.
+///
+/// Registered aliases resolve to the key of the model they name and
+/// `hf:org/model` ids to their cache directory; anything else is treated as
+/// a path.
+///
+/// Collapses redundant separators and `.` components and strips trailing
+/// slashes, so `models/foo/` and `models//foo` share one entry.
+fn model_key(path: &str) -> String {
+    if let Some(key) = MODELS.resolve_alias(path) {
+        return key;
+    }
+    if let Some(cached) = hf::cache_path(path) {
+        return cached.to_string_lossy().into_owned();
+    }
+    let normalized: PathBuf = Path::new(path)
+        .components()
+        .filter(|c| !matches!(c, Component::CurDir))
+        .collect();
 
-    let models_map = models
-        .as_mut()
-        .ok_or_else(|| "Models not initialized".to_string())?;
+    if normalized.as_os_str().is_empty() {
+        ".".to_string()
+    } else {
+        normalized.to_string_lossy().into_owned()
+    }
+}
 
-    models_map
-        .remove(path)
-        .ok_or_else(|| format!("Model not loaded: {}", path))?;
+fn load_model(path: &str, alias: Option<&str>) -> Result<(), PluginLlmError> {
+    let key = model_key(path);
+    if let Some(alias) = alias {
+        check_alias(alias)?;
+    }
+    open_model(&key)?;
+    if let Some(alias) = alias {
+        MODELS.set_alias(alias, &key)?;
+    }
+    Ok(())
+}
 
+/// Aliases are bare names, so they can't be mistaken for paths
+fn check_alias(alias: &str) -> Result<(), PluginLlmError> {
+    if alias.is_empty() || alias.contains(['/', '\\']) || alias == "." || alias == ".." {
+        return Err(PluginLlmError::InvalidArgs(format!(
+            "alias must be a non-empty name without path separators, got {:?}",
+            alias
+        )));
+    }
     Ok(())
 }
 
-fn list_models() -> Vec<String> {
-    MODELS
-        .lock()
-        .ok()
-        .and_then(|m| m.as_ref().map(|map| map.keys().cloned().collect()))
-        .unwrap_or_default()
+/// Registry entry for a model key, loading the model if needed
+fn open_model(key: &str) -> Result<Arc<registry::Entry<Client>>, PluginLlmError> {
+    load_entry(key).map(|(model, _)| model)
+}
+
+/// Like `open_model`, also telling whether this call loaded the model
+fn load_entry(key: &str) -> Result<(Arc<registry::Entry<Client>>, bool), PluginLlmError> {
+    platform::check()?;
+    let mut loaded = false;
+    let model = MODELS
+        .load(key, || {
+            loaded = true;
+            hf::ensure_cached(key)?;
+            model_files::check_model_path(Path::new(key))?;
+            integrity::check(Path::new(key), config::get().verify_checksums)?;
+            check_memory_budget(key)?;
+            Client::open(PathBuf::from(key)).map_err(PluginLlmError::ModelLoadFailed)
+        })
+        .inspect_err(|e| logging::model_load_failed(key, &e.to_string()))?;
+    if loaded {
+        model.set_sampling_defaults(configured_model_sampling(key));
+        model.set_stop_defaults(configured_model_stops(key));
+        logging::model_loaded(key, model.load_ms);
+    }
+    Ok((model, loaded))
+}
+
+/// Refuse to load `key` if its weights don't fit the memory budget next to
+/// the models staying loaded (at the model limit the least recently used is
+/// evicted, so it doesn't count)
+fn check_memory_budget(key: &str) -> Result<(), PluginLlmError> {
+    if device::get().memory_budget_bytes.is_none() {
+        return Ok(());
+    }
+    let mut models = MODELS.entries()?;
+    models.sort_by_key(|(_, model)| model.last_access_at());
+    let evicted = (models.len() + 1).saturating_sub(MODELS.max_models());
+    let resident_bytes = models
+        .iter()
+        .skip(evicted)
+        .map(|(_, model)| {
+            let engine_size = model
+                .try_lock()
+                .map(|client| Engine::model_info(&*client).size);
+            memory_estimate(model, engine_size).0
+        })
+        .sum();
+    device::check_budget(model_files::weights_bytes(Path::new(key)), resident_bytes)
+}
+
+/// Active device settings, and the estimated memory of the loaded models
+fn device_config() -> Result<String, String> {
+    let device = device::get();
+    let resident_bytes: u64 = MODELS
+        .entries()?
+        .iter()
+        .map(|(_, model)| {
+            let engine_size = model
+                .try_lock()
+                .map(|client| Engine::model_info(&*client).size);
+            memory_estimate(model, engine_size).0
+        })
+        .sum();
+    let result = json!({
+        "index": device.index,
+        "memory_budget_bytes": device.memory_budget_bytes,
+        "resident_bytes": resident_bytes,
+    });
+    Ok(serde_json::to_string(&result).unwrap_or_default())
+}
+
+/// Apply device settings to later loads; refused while models are loaded
+fn set_device_config(config: device::DeviceConfig) -> Result<String, String> {
+    device::set(config, MODELS.entries()?.len())?;
+    device_config()
 }
 
-fn generate_text(
+/// Prompt of the one-token `preload` warm-up generation
+const WARMUP_PROMPT: &str = "Hello";
+
+/// Load a model and, unless `warmup` is off, run a one-token generation so
+/// the first real request doesn't pay for cold caches.
+///
+/// `on_event` gets `{"event":"loading"}` and `{"event":"warming_up"}` as each
+/// phase starts; the engine reports no finer-grained load progress. Returning
+/// `false` skips the warm-up.
+fn preload_model(
     path: &str,
-    prompt: &str,
-    max_tokens: Option<usize>,
-    temperature: Option<f32>,
+    warmup: bool,
+    mut on_event: Option<EventSink>,
 ) -> Result<String, String> {
-    // Ensure model is loaded
-    load_model(path)?;
+    let mut emit = |phase: &str| {
+        on_event
+            .as_mut()
+            .is_none_or(|sink| sink(&json!({ "event": phase }).to_string()))
+    };
 
-    let mut models = MODELS
-        .lock()
-        .map_err(|e| format!("Failed to lock models: {}", e))?;
+    let key = model_key(path);
+    emit("loading");
+    let (model, loaded) = load_entry(&key)?;
 
-    let models_map = models
-        .as_mut()
-        .ok_or_else(|| "Models not initialized".to_string())?;
+    let mut warmup_ms = None;
+    if warmup && emit("warming_up") {
+        let sampling = sampling::resolve(&SamplingParams {
+            max_tokens: Some(1),
+            seed: Some(0),
+            ..Default::default()
+        })?;
+        let start = Instant::now();
+        run_generation(
+            &mut model.lock(),
+            WARMUP_PROMPT,
+            &sampling,
+            sampling.max_tokens,
+            None,
+        )?;
+        model.touch();
+        warmup_ms = Some(start.elapsed().as_millis() as u64);
+    }
+
+    let result = json!({
+        "model_path": key,
+        "already_loaded": !loaded,
+        "load_ms": loaded.then_some(model.load_ms),
+        "warmup_ms": warmup_ms,
+    });
+    Ok(serde_json::to_string(&result).unwrap_or_default())
+}
+
+fn unload_model(path: &str) -> Result<(), PluginLlmError> {
+    let key = model_key(path);
+    MODELS.remove(&key)?;
+    logging::model_unloaded(&key, "unload");
+    polling::fail_jobs(&key, "was unloaded while the job was running");
+    Ok(())
+}
+
+/// Log an eviction and fail the jobs still running on the model
+fn model_evicted(key: &str, reason: &str) {
+    logging::model_unloaded(key, reason);
+    polling::fail_jobs(key, "was evicted while the job was running");
+}
+
+/// A load pushed the registry past `max_loaded_models`
+fn evicted_past_limit(key: &str) {
+    model_evicted(key, "max_models");
+}
+
+fn unload_all_models() -> Result<Vec<String>, PluginLlmError> {
+    let unloaded = MODELS.remove_all()?;
+    for key in &unloaded {
+        logging::model_unloaded(key, "unload");
+        polling::fail_jobs(key, "was unloaded while the job was running");
+    }
+    Ok(unloaded)
+}
 
-    let client = models_map
-        .get_mut(path)
-        .ok_or_else(|| format!("Model not loaded: {}", path))?;
+/// Unload a loaded model and open it again from disk.
+///
+/// Fails with `Model not loaded` rather than loading a model fresh. If the
+/// new load fails, the model stays unloaded.
+fn reload_model(path: &str) -> Result<(), PluginLlmError> {
+    let key = model_key(path);
+    // Defaults given at load survive the reload
+    let defaults = MODELS
+        .get(&key)
+        .map(|model| (model.sampling_defaults(), model.stop_defaults()));
+    MODELS.remove(&key)?;
+    logging::model_unloaded(&key, "reload");
+    polling::fail_jobs(&key, "was reloaded while the job was running");
+    let model = open_model(&key)?;
+    if let Some((sampling, stops)) = defaults {
+        model.set_sampling_defaults(sampling);
+        model.set_stop_defaults(stops);
+    }
+    Ok(())
+}
 
-    let mut request = GenerateRequest::new(prompt);
-    if let Some(max) = max_tokens {
-        request = request.max_tokens(max);
+/// Sampling defaults of the model at `key`: those it was loaded with, or
+/// its `model_sampling` config entry while it isn't loaded
+fn model_sampling(key: &str) -> SamplingParams {
+    match MODELS.get(key) {
+        Some(model) => model.sampling_defaults(),
+        None => configured_model_sampling(key),
     }
-    if let Some(temp) = temperature {
-        request = request.temperature(temp);
+}
+
+/// The `model_sampling` config entry for `key` (empty if none)
+fn configured_model_sampling(key: &str) -> SamplingParams {
+    config::get()
+        .model_sampling
+        .iter()
+        .find(|(path, _)| model_key(path) == key)
+        .map(|(_, defaults)| defaults.clone())
+        .unwrap_or_default()
+}
+
+/// Replace the sampling defaults of the loaded model at `path`
+fn set_model_sampling(path: &str, defaults: SamplingParams) -> Result<(), PluginLlmError> {
+    config::check_sampling_defaults(&defaults).map_err(PluginLlmError::InvalidArgs)?;
+    let key = model_key(path);
+    let model = MODELS
+        .get(&key)
+        .ok_or(PluginLlmError::ModelNotLoaded(key))?;
+    model.set_sampling_defaults(defaults);
+    Ok(())
+}
+
+/// Stop defaults of the model at `key`, like `model_sampling`
+fn model_stops(key: &str) -> StopDefaults {
+    match MODELS.get(key) {
+        Some(model) => model.stop_defaults(),
+        None => configured_model_stops(key),
     }
+}
+
+/// The `model_stops` config entry for `key` (empty if none)
+fn configured_model_stops(key: &str) -> StopDefaults {
+    config::get()
+        .model_stops
+        .iter()
+        .find(|(path, _)| model_key(path) == key)
+        .map(|(_, defaults)| defaults.clone())
+        .unwrap_or_default()
+}
+
+/// Replace the stop defaults of the loaded model at `path`
+fn set_model_stops(path: &str, defaults: StopDefaults) -> Result<(), PluginLlmError> {
+    defaults.validate().map_err(PluginLlmError::InvalidArgs)?;
+    let key = model_key(path);
+    let model = MODELS
+        .get(&key)
+        .ok_or(PluginLlmError::ModelNotLoaded(key))?;
+    model.set_stop_defaults(defaults);
+    Ok(())
+}
+
+/// Loaded models as `{"path", "aliases"}`, sorted by path
+fn list_models() -> Vec<serde_json::Value> {
+    let mut keys = MODELS.keys();
+    keys.sort();
+    keys.into_iter()
+        .map(|key| json!({ "aliases": MODELS.aliases_of(&key), "path": key }))
+        .collect()
+}
+
+/// Loaded models with usage metadata, most recently used first.
+///
+/// `memory_bytes` comes from the engine when the model is idle, otherwise
+/// from its weight files, as in `memory_usage`. Timestamps are Unix ms.
+fn list_models_detailed() -> Result<Vec<serde_json::Value>, String> {
+    let mut models = MODELS.entries()?;
+    models.sort_by_key(|(_, model)| std::cmp::Reverse(model.last_access_at()));
+    Ok(models
+        .iter()
+        .map(|(key, model)| {
+            let engine_size = model
+                .try_lock()
+                .map(|client| Engine::model_info(&*client).size);
+            let (memory_bytes, _) = memory_estimate(model, engine_size);
+            let slots = model.slots();
+            json!({
+                "path": key,
+                "aliases": MODELS.aliases_of(key),
+                "size_bytes": model_files::weights_bytes(&model.resolved_path),
+                "memory_bytes": memory_bytes,
+                "loaded_at_ms": unix_ms(model.loaded_at),
+                "last_access_ms": unix_ms(model.last_access_at()),
+                "generations": model.generations(),
+                "running": slots.running,
+                "queued": slots.queued,
+            })
+        })
+        .collect())
+}
+
+/// Models on disk under `dir`, else the `models_dir` config, else the
+/// `hf:` cache
+fn scan_models(dir: Option<PathBuf>) -> Result<String, String> {
+    let dir = dir
+        .or_else(|| config::get().models_dir.clone())
+        .unwrap_or_else(hf::cache_dir);
+    scan::scan(&dir)
+}
 
-    let response = client
-        .generate(request)
-        .map_err(|e| format!("Generation failed: {}", e))?;
+fn unix_ms(time: std::time::SystemTime) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// Time since `init`, `None` before it or after `shutdown`
+fn uptime_ms() -> Option<u64> {
+    STARTED_AT
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .map(|start| start.elapsed().as_millis() as u64)
+}
 
+/// Readiness summary; never loads a model or calls into the engine
+fn health() -> String {
+    let uptime_ms = uptime_ms();
+    let platform_supported = platform::is_supported();
+    let status = if !platform_supported {
+        "unsupported_platform"
+    } else if uptime_ms.is_none() {
+        "not_initialized"
+    } else if drain::is_draining() {
+        "draining"
+    } else {
+        "ok"
+    };
     let result = json!({
-        "text": response.text,
-        "tokens_generated": response.tokens_generated,
-        "stopped": response.stopped,
-        "stop_reason": response.stop_reason,
+        "status": status,
+        "models_loaded": MODELS.keys().len(),
+        "platform_supported": platform_supported,
+        "uptime_ms": uptime_ms,
     });
+    serde_json::to_string(&result).unwrap_or_default()
+}
+
+/// Record the model generations use when they omit `model_path`.
+///
+/// The model need not be loaded, but must be loaded, an `hf:` id or an
+/// existing model path, so typos fail here rather than on every generation.
+fn set_default_model(path: &str) -> Result<String, String> {
+    if !names_model(path) {
+        model_files::check_model_path(Path::new(&model_key(path)))?;
+    }
+    *DEFAULT_MODEL.lock().unwrap_or_else(|e| e.into_inner()) = Some(path.to_string());
+    Ok(json!({ "default_model": path }).to_string())
+}
 
+fn default_model_path() -> Option<String> {
+    DEFAULT_MODEL
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .clone()
+}
+
+/// The default model, or an error listing the loaded models to pick from
+fn default_model() -> Result<String, String> {
+    default_model_path().ok_or_else(|| {
+        let loaded = MODELS.keys();
+        format!(
+            "Invalid arguments: model_path is required when no default model is set (loaded models: {})",
+            if loaded.is_empty() {
+                "none".to_string()
+            } else {
+                loaded.join(", ")
+            }
+        )
+    })
+}
+
+/// Whether `name` is a loaded model, an alias, an `hf:` id or an existing path
+fn names_model(name: &str) -> bool {
+    MODELS.resolve_alias(name).is_some()
+        || MODELS.keys().contains(&model_key(name))
+        || hf::cache_path(name).is_some()
+        || Path::new(name).exists()
+}
+
+fn set_max_models(max: usize) -> Result<String, String> {
+    let evicted = MODELS.set_max_models(max)?;
+    for key in &evicted {
+        model_evicted(key, "max_models");
+    }
+    let result = json!({ "max_loaded_models": max, "evicted": evicted });
     Ok(serde_json::to_string(&result).unwrap_or_default())
 }
 
-fn get_model_info(path: &str) -> Result<String, String> {
-    // Ensure model is loaded
-    load_model(path)?;
+fn generate_text(args: &GenerateArgs) -> Result<String, String> {
+    let _in_flight = drain::InFlightGuard::acquire()?;
+    args.check_limits()?;
+    check_rate_limit(args)?;
+    let _cancellable = register_request(args)?;
+    match args.n {
+        Some(n) if n > 1 && !args.validate_only => generate_samples(args, n),
+        _ => generate_inner(args, None),
+    }
+}
 
-    let models = MODELS
-        .lock()
-        .map_err(|e| format!("Failed to lock models: {}", e))?;
+/// Generate `n` independent samples, returning a JSON array of results.
+///
+/// Sample `i` is seeded with `seed + i` (wrapping), so sample 0 matches a
+/// single generation with the same seed and the whole set is reproducible.
+fn generate_samples(args: &GenerateArgs, n: usize) -> Result<String, String> {
+    let base_seed = args.sampling.seed.unwrap_or_else(sampling::random_seed);
+    let mut samples = Vec::with_capacity(n);
+    for index in 0..n {
+        let sample_args = GenerateArgs {
+            sampling: SamplingParams {
+                seed: Some(base_seed.wrapping_add(index as u64)),
+                ..args.sampling.clone()
+            },
+            n: None,
+            ..args.clone()
+        };
+        let output = generate_inner(&sample_args, None)?;
+        let sample: serde_json::Value = serde_json::from_str(&output).map_err(|e| e.to_string())?;
+        let cancelled = sample["stop_reason"] == "cancelled";
+        samples.push(sample);
+        if cancelled {
+            break;
+        }
+    }
+    Ok(serde_json::to_string(&samples).unwrap_or_default())
+}
+
+/// Make the generation cancellable through `cancel` if it has a `request_id`
+fn register_request(args: &GenerateArgs) -> Result<Option<cancel::Registration>, String> {
+    args.request_id.as_deref().map(cancel::register).transpose()
+}
+
+/// Generate, passing each token to `on_token` as it is produced.
+///
+/// Callers register `request_id` for cancellation themselves, since the
+/// generation may start on another thread. Returning `false` from `on_token`
+/// stops generation. `retry_on_gibberish`
+/// is not applied since streamed tokens can't be taken back.
+fn generate_text_streaming(args: &GenerateArgs, on_token: TokenSink) -> Result<String, String> {
+    args.check_limits()?;
+    check_rate_limit(args)?;
+    generate_inner(args, Some(on_token))
+}
 
-    let models_map = models
-        .as_ref()
-        .ok_or_else(|| "Models not initialized".to_string())?;
+/// Count a generation request against its model's `rate_limit`, if any
+fn check_rate_limit(args: &GenerateArgs) -> Result<(), String> {
+    let Some(limit) = config::get().rate_limit else {
+        return Ok(());
+    };
+    if args.validate_only {
+        return Ok(());
+    }
+    let model = match args.model_path.as_str() {
+        "" => default_model()?,
+        path => model_key(path),
+    };
+    Ok(rate_limit::acquire(&model, &limit)?)
+}
 
-    let client = models_map
-        .get(path)
-        .ok_or_else(|| format!("Model not loaded: {}", path))?;
+/// CLI `generate --format sse`: one `{"token"}` event per streamed token,
+/// the result object, then `[DONE]`, framed as server-sent events
+fn generate_sse(args: &GenerateArgs) -> Result<String, String> {
+    let _in_flight = drain::InFlightGuard::acquire()?;
+    let _cancellable = register_request(args)?;
 
-    let info = client.model_info();
+    let mut body = String::new();
+    let result = generate_text_streaming(args, &mut |token| {
+        body.push_str(&sse::event(&json!({ "token": token }).to_string()));
+        true
+    })?;
+    body.push_str(&sse::event(&result));
+    body.push_str(&sse::event(sse::DONE));
+    Ok(body)
+}
+
+/// Generate, emitting `{"stream_id","token","done":false}` per token.
+///
+/// The final `{"stream_id","done":true,...}` event carries the generation
+/// result and is also returned. Returning `false` from `on_event` cancels
+/// the generation; no further events are sent.
+fn generate_stream(
+    args: &GenerateArgs,
+    stream_id: &str,
+    on_event: EventSink,
+) -> Result<String, String> {
+    let _in_flight = drain::InFlightGuard::acquire()?;
+    let _cancellable = register_request(args)?;
+
+    let mut cancelled = false;
+    let output = generate_text_streaming(args, &mut |token| {
+        let event = json!({ "stream_id": stream_id, "token": token, "done": false });
+        cancelled = !on_event(&event.to_string());
+        !cancelled
+    })?;
+
+    let mut result: serde_json::Value = serde_json::from_str(&output).map_err(|e| e.to_string())?;
+    result["stream_id"] = json!(stream_id);
+    result["done"] = json!(true);
+    let result = result.to_string();
+    if !cancelled {
+        on_event(&result);
+    }
+    Ok(result)
+}
+
+/// Generate on the requested (or default) model, logging start and failure
+fn generate_inner(args: &GenerateArgs, on_token: Option<TokenSink>) -> Result<String, String> {
+    if args.model_path.is_empty() {
+        let with_default = GenerateArgs {
+            model_path: default_model()?,
+            ..args.clone()
+        };
+        return generate_inner(&with_default, on_token);
+    }
+    if !args.validate_only {
+        logging::generation_started(&args.model_path, args.request_id.as_deref(), &args.prompt);
+    }
+    generate_with_fallback(args, on_token).inspect_err(|e| {
+        if is_infrastructure_error(e) {
+            logging::generation_failed(&args.model_path, args.request_id.as_deref(), e);
+        }
+    })
+}
+
+/// Generate, retrying once on `fallback_model_path` after an infrastructure failure.
+///
+/// Validation errors never fall back, and neither do session turns (sessions
+/// are bound to one model) or streams that already delivered tokens.
+fn generate_with_fallback(
+    args: &GenerateArgs,
+    mut on_token: Option<TokenSink>,
+) -> Result<String, String> {
+    let Some(fallback) = args
+        .fallback_model_path
+        .as_deref()
+        .filter(|_| args.session_id.is_none() && !args.validate_only)
+    else {
+        return generate_once(args, on_token);
+    };
+
+    let mut delivered = false;
+    let primary = match on_token.as_mut() {
+        Some(sink) => generate_once(
+            args,
+            Some(&mut |token: &str| {
+                delivered = true;
+                sink(token)
+            }),
+        ),
+        None => generate_once(args, None),
+    };
+
+    let (output, served_by) = match primary {
+        Err(e) if !delivered && is_infrastructure_error(&e) => {
+            tracing::warn!(
+                "Generation on {} failed, falling back to {}: {}",
+                args.model_path,
+                fallback,
+                e
+            );
+            let fallback_args = GenerateArgs {
+                model_path: fallback.to_string(),
+                fallback_model_path: None,
+                ..args.clone()
+            };
+            // The fallback model's own limit applies to the retry
+            check_rate_limit(&fallback_args)?;
+            (generate_once(&fallback_args, on_token)?, fallback)
+        }
+        result => (result?, args.model_path.as_str()),
+    };
+
+    let mut result: serde_json::Value = serde_json::from_str(&output).map_err(|e| e.to_string())?;
+    result["served_by"] = json!(model_key(served_by));
+    Ok(serde_json::to_string(&result).unwrap_or_default())
+}
+
+/// Failures of the model or engine, as opposed to a rejected request
+fn is_infrastructure_error(message: &str) -> bool {
+    matches!(
+        error_code(message),
+        "model_not_found"
+            | "invalid_model_path"
+            | "model_load_failed"
+            | "model_corrupt"
+            | "download_failed"
+            | "model_not_loaded"
+            | "generation_failed"
+            | "tokenization_failed"
+            | "lock_poisoned"
+    )
+}
+
+fn generate_once(args: &GenerateArgs, on_token: Option<TokenSink>) -> Result<String, String> {
+    // The model's defaults, then the init config's, fill whatever sampling
+    // parameters the request leaves unset
+    let config = config::get();
+    let sampling = args
+        .sampling
+        .or(&model_sampling(&model_key(&args.model_path)))
+        .or(&config.default_sampling);
+    if sampling != args.sampling {
+        let with_defaults = GenerateArgs {
+            sampling,
+            ..args.clone()
+        };
+        return generate_once(&with_defaults, on_token);
+    }
+    // The model's stop sequences join the request's unless it replaces them
+    let (stop, ignore_eos) = stop_defaults::resolve(
+        &model_stops(&model_key(&args.model_path)),
+        &args.stop,
+        args.replace_stop,
+        args.ignore_eos,
+    );
+    let with_stops = GenerateArgs {
+        stop,
+        ignore_eos: Some(ignore_eos),
+        ..args.clone()
+    };
+    generate_resolved(&with_stops, on_token)
+}
+
+/// `generate_once` once `args` carries the final sampling parameters, stop
+/// sequences and `ignore_eos`
+fn generate_resolved(args: &GenerateArgs, on_token: Option<TokenSink>) -> Result<String, String> {
+    // Rendered per attempt: a fallback model may use a different template
+    if let Some(system) = &args.system {
+        let rendered = GenerateArgs {
+            prompt: chat::with_system_prompt(&args.model_path, system, &args.prompt)?,
+            system: None,
+            ..args.clone()
+        };
+        return generate_resolved(&rendered, on_token);
+    }
+    check_prompt_size(&args.prompt)?;
+    let streaming = on_token.is_some();
+
+    match args.n {
+        Some(0) => return Err("Invalid arguments: n must be at least 1".to_string()),
+        // `generate_text` splits n > 1 into single samples before getting here
+        Some(n) if n > 1 && !args.validate_only => {
+            return Err(format!(
+            "Invalid arguments: n = {} is only supported by generate (streams return one sample)",
+            n
+        ))
+        }
+        _ => {}
+    }
+
+    // Restricting the vocabulary means masking logits before each sampling step
+    if !args.allowed_tokens.is_empty() {
+        return Err(unsupported("allowed_tokens", "logit masking"));
+    }
+    // Perplexity needs the same per-token logprobs as `score`
+    if args.include_prompt_perplexity {
+        return Err(unsupported(
+            "include_prompt_perplexity",
+            "prompt token logprobs",
+        ));
+    }
+    if args.top_logprobs.is_some() && args.logprobs != Some(true) {
+        return Err("Invalid arguments: top_logprobs requires logprobs: true".to_string());
+    }
+    // Constraining decoding to a grammar means masking logits, like allowed_tokens
+    if args.grammar.is_some() {
+        return Err(unsupported("grammar", "logit masking"));
+    }
+    if let Some(schema) = &args.json_schema {
+        json_schema::check_schema(schema)
+            .map_err(|e| format!("Invalid arguments: malformed json_schema: {}", e))?;
+    }
+    // Per-token logprobs need the sampling distribution at each step
+    if args.logprobs == Some(true) {
+        return Err(unsupported("logprobs", "token logprobs"));
+    }
+    if args.sampling.penalty_last_n.is_some() {
+        return Err(unsupported("penalty_last_n", "repetition penalty settings"));
+    }
+    // Verifying draft tokens needs the main model's logits for a span of
+    // tokens at once, so there is nothing to load the draft for
+    if args.draft_model_path.is_some() {
+        return Err(unsupported(
+            "draft_model_path",
+            "batched draft verification",
+        ));
+    }
+    // `Client` builds fresh decode state per call and can't snapshot or
+    // restore it, so there is no cached prefix to resume from
+    if args.prefix_cache {
+        return Err(unsupported("prefix_cache", "KV cache snapshots"));
+    }
+    if let Some(inputs) = &args.images {
+        images::check(&model_key(&args.model_path), inputs)?;
+    }
+    let expect_script = match &args.expect_script {
+        Some(name) => Some(postprocess::script_name(name).ok_or_else(|| {
+            format!(
+                "Invalid arguments: unknown expect_script {:?} (expected one of {})",
+                name,
+                postprocess::SCRIPTS.join(", ")
+            )
+        })?),
+        None => None,
+    };
+
+    if args.timeout_ms == Some(0) {
+        return Err("Invalid arguments: timeout_ms must be at least 1".to_string());
+    }
+    let mut sampling = sampling::resolve(&args.sampling)?;
+    if sampling.repetition_penalty != sampling::DEFAULT_REPETITION_PENALTY
+        || sampling.frequency_penalty != sampling::DEFAULT_FREQUENCY_PENALTY
+    {
+        return Err(unsupported(
+            "repetition_penalty/frequency_penalty",
+            "repetition penalty settings",
+        ));
+    }
+    // A ban retry re-prompts from before the match; greedy decoding would
+    // pick the banned token again every time
+    if !args.ban_substrings.is_empty() && (sampling.greedy || sampling.top_k == Some(1)) {
+        return Err(
+            "Invalid arguments: ban_substrings needs sampling; greedy decoding (temperature 0 or top_k 1) repeats a banned token on every retry"
+                .to_string(),
+        );
+    }
+    // Session turns may not exceed the remaining token budget, held for
+    // this turn until it is charged
+    let session = match &args.session_id {
+        Some(session_id) => Some(sessions::reserve(session_id, &model_key(&args.model_path))?),
+        None => None,
+    };
+    if let Some(session) = &session {
+        if let Some(remaining) = session.limit() {
+            sampling.max_tokens = Some(
+                sampling
+                    .max_tokens
+                    .map_or(remaining, |max| max.min(remaining)),
+            );
+        }
+    }
+    // Past EOS only `max_tokens` ends a reply that no stop sequence catches
+    if args.ignore_eos == Some(true) && sampling.max_tokens.is_none() {
+        return Err(
+            "Invalid arguments: ignore_eos requires max_tokens (on the request or in sampling defaults)"
+                .to_string(),
+        );
+    }
+
+    // Unreadable metadata just skips the check; the engine still enforces it
+    let context_length = model_files::read_context_length(Path::new(&model_key(&args.model_path)))
+        .ok()
+        .flatten();
+    let mut truncated_tokens = None;
+    let mut rendered_prompt = None;
+
+    // Only `generate_inner`'s start line and the engine call are skipped
+    if args.validate_only {
+        let (_, prompt_tokens, truncated_tokens) = with_model(&args.model_path, |client| {
+            prepare_prompt(client, args, &mut sampling, context_length)
+        })?;
+        let mut result = json!({
+            "valid": true,
+            "model_path": model_key(&args.model_path),
+            "prompt_tokens": prompt_tokens,
+            "effective_params": sampling,
+        });
+        if let Some(truncated) = truncated_tokens {
+            result["truncated_prompt_tokens"] = json!(truncated);
+        }
+        return Ok(serde_json::to_string(&result).unwrap_or_default());
+    }
+    let seed = *sampling.seed.get_or_insert_with(sampling::random_seed);
+
+    let ((generation, retry, prompt_tokens, elapsed), load_ms) =
+        with_generation_slot(&args.model_path, |client| {
+            let (prompt, prompt_tokens, truncated) =
+                prepare_prompt(client, args, &mut sampling, context_length)?;
+            truncated_tokens = truncated;
+            if args.include_rendered_prompt {
+                rendered_prompt = Some(prompt.to_string());
+            }
+
+            let start = Instant::now();
+            // One deadline for the whole call, gibberish retry included
+            let deadline = decode::Deadline::from_timeout_ms(args.timeout_ms);
+            let cancelled = args.request_id.as_deref().and_then(cancel::flag);
+            let cancelled = cancelled.as_deref();
+            let generation = run_controlled(
+                client, args, &prompt, &sampling, deadline, cancelled, on_token,
+            )?;
+
+            let Some(retry_temperature) = sampling.retry_temperature else {
+                return Ok((generation, None, prompt_tokens, start.elapsed()));
+            };
+            if streaming
+                || deadline.is_some_and(|d| d.expired())
+                || generation.stop_reason == Some(StopReason::Cancelled)
+                || generation.error.is_some()
+                || !postprocess::is_gibberish(&generation.text)
+            {
+                return Ok((generation, None, prompt_tokens, start.elapsed()));
+            }
+
+            // Single retry at a reduced temperature
+            let retry = ResolvedSampling {
+                temperature: Some(retry_temperature),
+                ..sampling.clone()
+            };
+            let generation =
+                run_controlled(client, args, &prompt, &retry, deadline, cancelled, None)?;
+            Ok((
+                generation,
+                Some(retry_temperature),
+                prompt_tokens,
+                start.elapsed(),
+            ))
+        })?;
+
+    let mut result = json!({
+        "text": generation.text,
+        "tokens_generated": generation.tokens_generated,
+        "stopped": generation.stopped,
+        "stop_reason": generation.stop_reason,
+        "seed": seed,
+        "prompt_tokens": prompt_tokens,
+        "elapsed_ms": elapsed.as_millis() as u64,
+        "tokens_per_second": tokens_per_second(generation.tokens_generated, elapsed),
+        "effective_params": sampling,
+    });
+    if let Some(load_ms) = load_ms {
+        result["load_ms"] = json!(load_ms);
+    }
+    if let Some(truncated) = truncated_tokens {
+        result["truncated_prompt_tokens"] = json!(truncated);
+    }
+    if let Some(sequence) = &generation.stop_sequence {
+        result["stop_sequence"] = json!(sequence);
+    }
+    if let Some(error) = &generation.error {
+        result["error"] = json!(error);
+        logging::generation_failed(
+            &model_key(&args.model_path),
+            args.request_id.as_deref(),
+            error,
+        );
+    }
+    if args.sampling.max_tokens_ratio.is_some() {
+        result["effective_max_tokens"] = json!(sampling.max_tokens);
+    }
+    if args.sampling.retry_on_gibberish {
+        let mut temperatures = vec![sampling.temperature];
+        temperatures.extend(retry.map(Some));
+        result["gibberish_retry"] = json!({
+            "retried": retry.is_some(),
+            "temperatures": temperatures,
+        });
+    }
+    if let Some(schema) = &args.json_schema {
+        let errors = match json_schema::parse_output(&generation.text) {
+            Ok(value) => {
+                let errors = json_schema::validate(&value, schema);
+                result["json"] = value;
+                errors
+            }
+            Err(e) => vec![e],
+        };
+        result["valid"] = json!(errors.is_empty());
+        result["validation_errors"] = json!(errors);
+    }
+    if args.parse_markdown {
+        result["blocks"] = json!(postprocess::markdown_blocks(&generation.text));
+    }
+    if let Some(expected) = expect_script {
+        // Advisory only: output without letters never mismatches
+        let detected = postprocess::dominant_script(&generation.text);
+        result["detected_script"] = json!(detected);
+        result["script_mismatch"] = json!(detected.is_some_and(|d| d != expected));
+    }
+    if let Some(prompt) = rendered_prompt {
+        result["rendered_prompt"] = json!(prompt);
+    }
+    if args.include_output_hash {
+        result["output_hash"] = json!(postprocess::output_hash(&generation.text));
+    }
+    if !args.ban_substrings.is_empty() {
+        result["ban_retries"] = json!(generation.ban_retries);
+    }
+    if args.ignore_eos == Some(true) {
+        result["eos_ignored"] = json!(generation.eos_ignored);
+    }
+    if let (Some(session), Some(session_id)) = (session, &args.session_id) {
+        session.charge(generation.tokens_generated)?;
+        result["session"] = sessions::info(session_id)?;
+    }
+
+    stats::record_generation(
+        &model_key(&args.model_path),
+        generation.tokens_generated,
+        elapsed,
+    );
+    logging::generation_finished(
+        &model_key(&args.model_path),
+        prompt_tokens,
+        generation.tokens_generated,
+        elapsed,
+        generation
+            .stop_reason
+            .as_ref()
+            .map_or("", StopReason::as_str),
+    );
+    if let Some(model) = MODELS.get(&model_key(&args.model_path)) {
+        model.record_generation();
+    }
+    Ok(serde_json::to_string(&result).unwrap_or_default())
+}
+
+/// Prompt as sent to the engine and its token count, plus how many tokens
+/// `truncate` dropped. Applies `max_tokens_ratio` to `sampling` and checks
+/// the prompt and reply against `context_length`.
+fn prepare_prompt<'a>(
+    client: &mut Client,
+    args: &'a GenerateArgs,
+    sampling: &mut ResolvedSampling,
+    context_length: Option<usize>,
+) -> Result<(Cow<'a, str>, usize, Option<usize>), String> {
+    let mut prompt = match &args.append_token {
+        Some(token) => Cow::Owned(append_prompt_token(client, &args.prompt, token)?),
+        None => Cow::Borrowed(args.prompt.as_str()),
+    };
+
+    let mut prompt_tokens = count_tokens(client, &prompt)?;
+
+    // The smaller of max_tokens and ratio x prompt tokens wins
+    if let Some(ratio) = args.sampling.max_tokens_ratio {
+        let by_ratio = ((prompt_tokens as f32 * ratio) as usize).max(1);
+        sampling.max_tokens = Some(sampling.max_tokens.map_or(by_ratio, |m| m.min(by_ratio)));
+    }
+
+    let mut truncated_tokens = None;
+    if let Some(limit) = context_length {
+        let reply_tokens = sampling.max_tokens.unwrap_or(0);
+        if prompt_tokens + reply_tokens > limit {
+            if !args.truncate || reply_tokens >= limit {
+                return Err(PluginLlmError::ContextOverflow {
+                    prompt_tokens,
+                    reply_tokens,
+                    limit,
+                }
+                .into());
+            }
+            let (kept, kept_tokens) = truncate_prompt_start(client, &prompt, limit - reply_tokens)?;
+            truncated_tokens = Some(prompt_tokens - kept_tokens);
+            prompt = Cow::Owned(kept);
+            prompt_tokens = kept_tokens;
+        }
+    }
+    Ok((prompt, prompt_tokens, truncated_tokens))
+}
+
+fn count_tokens(client: &mut Client, text: &str) -> Result<usize, PluginLlmError> {
+    Engine::tokenize(client, text)
+        .map(|tokens| tokens.len())
+        .map_err(|e| PluginLlmError::TokenizationFailed(e.to_string()))
+}
+
+/// Drop the oldest part of `prompt` so it encodes to at most `budget` tokens.
+///
+/// Cuts at a char boundary found by binary search over the start offset;
+/// returns the kept text and its token count.
+fn truncate_prompt_start(
+    client: &mut Client,
+    prompt: &str,
+    budget: usize,
+) -> Result<(String, usize), PluginLlmError> {
+    let starts: Vec<usize> = prompt
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain([prompt.len()])
+        .collect();
+    // Invariant: the suffix from starts[high] fits
+    let (mut low, mut high) = (0, starts.len() - 1);
+    while low < high {
+        let mid = (low + high) / 2;
+        if count_tokens(client, &prompt[starts[mid]..])? <= budget {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    let kept = &prompt[starts[low]..];
+    Ok((kept.to_string(), count_tokens(client, kept)?))
+}
+
+/// Generation throughput, rounded to 0.01; `None` if no time was measured
+fn tokens_per_second(tokens: usize, elapsed: Duration) -> Option<f64> {
+    let secs = elapsed.as_secs_f64();
+    (secs > 0.0).then(|| (tokens as f64 / secs * 100.0).round() / 100.0)
+}
+
+/// Append `token` to the prompt, after checking it is a single vocabulary entry.
+///
+/// `Client` only takes text, so the token is appended as text. The prompt is
+/// then tokenized again to make sure it still ends with that token's id
+/// rather than having merged it with the prompt's last characters.
+fn append_prompt_token(client: &Client, prompt: &str, token: &str) -> Result<String, String> {
+    let tokenize = |text: &str| {
+        Engine::tokenize(client, text)
+            .map_err(|e| PluginLlmError::TokenizationFailed(e.to_string()))
+    };
+    let ids = tokenize(token)?;
+    if ids.len() != 1 {
+        return Err(PluginLlmError::InvalidArgs(format!(
+            "append_token is not a single vocabulary token: {:?}",
+            token
+        ))
+        .into());
+    }
+    let appended = format!("{}{}", prompt, token);
+    if tokenize(&appended)?.last() != ids.first() {
+        return Err(PluginLlmError::InvalidArgs(format!(
+            "append_token {:?} merges with the end of the prompt when tokenized",
+            token
+        ))
+        .into());
+    }
+    Ok(appended)
+}
+
+/// Outcome of a generation after plugin-side decode controls
+struct Generation {
+    text: String,
+    tokens_generated: usize,
+    stopped: bool,
+    /// `None` when the engine reported no reason
+    stop_reason: Option<StopReason>,
+    /// Stop sequence that ended generation
+    stop_sequence: Option<String>,
+    ban_retries: usize,
+    /// `ignore_eos` continued past at least one EOS
+    eos_ignored: bool,
+    /// Engine failure that cut the output short (`stop_reason: "error"`)
+    error: Option<String>,
+}
+
+/// Run a generation, applying `ban_substrings`, `stop` and
+/// `stop_on_json_complete` in the decode loop.
+///
+/// When a token completes a banned substring, generation is interrupted, the
+/// output is cut back to before the match and decoding resumes from there, so
+/// a different continuation is sampled, with the seed advanced by one per
+/// retry so a seeded request doesn't draw the same token again. After
+/// `MAX_BAN_RETRIES` rejections
+/// generation stops with `stop_reason: "ban_exhausted"`. Token counts across
+/// retries are approximate.
+///
+/// Stop sequences are matched against the accumulated text, so they may span
+/// tokens; the output is cut before the match, with `stop_reason:
+/// "stop_sequence"`. With `stop_on_json_complete`, generation stops as soon as
+/// the first JSON value closes, with `stop_reason: "json_complete"`; so does
+/// `json_schema`.
+///
+/// Once `deadline` passes, generation stops after the current token and the
+/// partial output is returned with `stop_reason: "timeout"`; once `cancelled`
+/// is set, likewise with `stop_reason: "cancelled"`.
+///
+/// Tokens are always taken incrementally, so if the engine fails after
+/// producing some (e.g. out of memory), the output so far is returned with
+/// `stop_reason: "error"` and the failure in `error`. A failure before the
+/// first token is still an error.
+fn run_controlled(
+    client: &mut Client,
+    args: &GenerateArgs,
+    prompt: &str,
+    sampling: &ResolvedSampling,
+    deadline: Option<decode::Deadline>,
+    cancelled: Option<&AtomicBool>,
+    mut on_token: Option<TokenSink>,
+) -> Result<Generation, String> {
+    let is_cancelled = || cancelled.is_some_and(|c| c.load(Ordering::SeqCst));
+    let bans = decode::SubstringFilter::new(&args.ban_substrings);
+    let stops = decode::SubstringFilter::new(&args.stop);
+    let mut json = (args.stop_on_json_complete || args.json_schema.is_some())
+        .then(decode::JsonCompletion::default);
+
+    // Accepted output across attempts, and how much of it was streamed out
+    let mut text = String::new();
+    let mut flushed = 0;
+    let mut tokens_generated = 0;
+    let mut ban_retries = 0;
+    let eos_ignored = Cell::new(false);
+
+    let stopped = |text: String, tokens_generated, reason, stop_sequence, ban_retries| Generation {
+        text,
+        tokens_generated,
+        stopped: true,
+        stop_reason: Some(reason),
+        stop_sequence,
+        ban_retries,
+        eos_ignored: eos_ignored.get(),
+        error: None,
+    };
+
+    loop {
+        let remaining = sampling
+            .max_tokens
+            .map(|max| max.saturating_sub(tokens_generated));
+        if remaining == Some(0) {
+            flush_tokens(&mut on_token, &text, &mut flushed, text.len());
+            return Ok(stopped(
+                text,
+                tokens_generated,
+                StopReason::MaxTokens,
+                None,
+                ban_retries,
+            ));
+        }
+        let interrupted = if is_cancelled() {
+            Some(StopReason::Cancelled)
+        } else {
+            deadline
+                .is_some_and(|d| d.expired())
+                .then_some(StopReason::Timeout)
+        };
+        if let Some(reason) = interrupted {
+            flush_tokens(&mut on_token, &text, &mut flushed, text.len());
+            return Ok(stopped(text, tokens_generated, reason, None, ban_retries));
+        }
+
+        let prompt = format!("{}{}", prompt, text);
+        let mut halt = None;
+        let mut attempt_tokens = 0;
+        let mut consumer_stopped = false;
+
+        let attempt_sampling = ResolvedSampling {
+            seed: sampling
+                .seed
+                .map(|seed| seed.wrapping_add(ban_retries as u64)),
+            ..sampling.clone()
+        };
+        let response = run_generation(
+            client,
+            &prompt,
+            &attempt_sampling,
+            remaining,
+            Some(&mut |token: &str| {
+                let checked = text.len();
+                text.push_str(token);
+                attempt_tokens += 1;
+
+                let stop = stops.as_ref().and_then(|f| f.find_match(&text, checked));
+                let ban = bans.as_ref().and_then(|f| f.find_match(&text, checked));
+                halt = match (stop, ban) {
+                    // A ban after the stop point is cut off anyway
+                    (Some((pos, sequence)), ban) if ban.is_none_or(|(b, _)| pos <= b) => {
+                        Some(Halt::StopSequence(pos, sequence.to_string()))
+                    }
+                    (_, Some((pos, _))) => Some(Halt::Ban(pos)),
+                    _ => json
+                        .as_mut()
+                        .and_then(|j| j.feed(&text[checked..]))
+                        .map(|end| Halt::JsonComplete(checked + end))
+                        .or_else(|| is_cancelled().then_some(Halt::Cancelled))
+                        .or_else(|| {
+                            deadline
+                                .is_some_and(|d| d.expired())
+                                .then_some(Halt::Timeout)
+                        }),
+                };
+                if halt.is_some() {
+                    return false;
+                }
+
+                let safe = [&bans, &stops]
+                    .into_iter()
+                    .flatten()
+                    .map(|f| f.safe_len(&text))
+                    .min()
+                    .unwrap_or(text.len());
+                let keep_going = flush_tokens(&mut on_token, &text, &mut flushed, safe);
+                consumer_stopped |= !keep_going;
+                keep_going
+            }),
+        );
+        let response = match response {
+            Ok(response) => response,
+            Err(e) if tokens_generated + attempt_tokens > 0 => {
+                // Keep what was produced; a match in progress can't complete now
+                tokens_generated += attempt_tokens;
+                flush_tokens(&mut on_token, &text, &mut flushed, text.len());
+                return Ok(Generation {
+                    error: Some(e.to_string()),
+                    ..stopped(text, tokens_generated, StopReason::Error, None, ban_retries)
+                });
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let pos = match halt {
+            None => {
+                tokens_generated += response.tokens_generated;
+                let stop_reason = response.stop_reason.as_deref().map(StopReason::from_engine);
+                // Prompt again with the output so far, as if EOS never came
+                if args.ignore_eos == Some(true)
+                    && stop_reason == Some(StopReason::Eos)
+                    && response.tokens_generated > 0
+                    && remaining.is_some()
+                    && !consumer_stopped
+                {
+                    eos_ignored.set(true);
+                    continue;
+                }
+                flush_tokens(&mut on_token, &text, &mut flushed, text.len());
+                return Ok(Generation {
+                    text,
+                    tokens_generated,
+                    stopped: response.stopped,
+                    stop_reason,
+                    stop_sequence: None,
+                    ban_retries,
+                    eos_ignored: eos_ignored.get(),
+                    error: None,
+                });
+            }
+            Some(Halt::StopSequence(pos, sequence)) => {
+                text.truncate(pos);
+                tokens_generated += attempt_tokens;
+                flush_tokens(&mut on_token, &text, &mut flushed, text.len());
+                return Ok(stopped(
+                    text,
+                    tokens_generated,
+                    StopReason::StopSequence,
+                    Some(sequence),
+                    ban_retries,
+                ));
+            }
+            Some(Halt::JsonComplete(end)) => {
+                text.truncate(end);
+                tokens_generated += attempt_tokens;
+                flush_tokens(&mut on_token, &text, &mut flushed, text.len());
+                return Ok(stopped(
+                    text,
+                    tokens_generated,
+                    StopReason::JsonComplete,
+                    None,
+                    ban_retries,
+                ));
+            }
+            Some(halt @ (Halt::Timeout | Halt::Cancelled)) => {
+                tokens_generated += attempt_tokens;
+                flush_tokens(&mut on_token, &text, &mut flushed, text.len());
+                let reason = match halt {
+                    Halt::Cancelled => StopReason::Cancelled,
+                    _ => StopReason::Timeout,
+                };
+                return Ok(stopped(text, tokens_generated, reason, None, ban_retries));
+            }
+            Some(Halt::Ban(pos)) => pos,
+        };
+
+        // Drop the banned match and the rejected token
+        text.truncate(pos);
+        tokens_generated += attempt_tokens - 1;
+
+        // Re-scan what was kept; the value may have completed before the match
+        if let Some(j) = json.as_mut() {
+            *j = decode::JsonCompletion::default();
+            if let Some(end) = j.feed(&text) {
+                text.truncate(end);
+                flush_tokens(&mut on_token, &text, &mut flushed, text.len());
+                return Ok(stopped(
+                    text,
+                    tokens_generated,
+                    StopReason::JsonComplete,
+                    None,
+                    ban_retries,
+                ));
+            }
+        }
+
+        if ban_retries == MAX_BAN_RETRIES {
+            flush_tokens(&mut on_token, &text, &mut flushed, text.len());
+            return Ok(stopped(
+                text,
+                tokens_generated,
+                StopReason::BanExhausted,
+                None,
+                ban_retries,
+            ));
+        }
+        ban_retries += 1;
+    }
+}
+
+/// Why the decode loop interrupted the engine (byte offsets into the output)
+enum Halt {
+    Ban(usize),
+    StopSequence(usize, String),
+    JsonComplete(usize),
+    Timeout,
+    Cancelled,
+}
+
+/// Hand `text[flushed..upto]` to the streaming consumer, if any.
+/// Returns the consumer's continue flag.
+fn flush_tokens(
+    on_token: &mut Option<TokenSink>,
+    text: &str,
+    flushed: &mut usize,
+    upto: usize,
+) -> bool {
+    let Some(sink) = on_token.as_mut() else {
+        return true;
+    };
+    if upto <= *flushed {
+        return true;
+    }
+    let keep_going = sink(&text[*flushed..upto]);
+    *flushed = upto;
+    keep_going
+}
+
+/// Run one engine generation; `max_tokens` overrides `sampling.max_tokens`
+fn run_generation(
+    client: &mut Client,
+    prompt: &str,
+    sampling: &ResolvedSampling,
+    max_tokens: Option<usize>,
+    on_token: Option<&mut dyn FnMut(&str) -> bool>,
+) -> Result<engine::Response, PluginLlmError> {
+    let request = engine::Request {
+        prompt,
+        max_tokens,
+        sampling: sampling.engine(),
+    };
+    // Called through the trait: `lib_client_uzu::Client` has an inherent
+    // `generate` of its own
+    Engine::generate(client, request, on_token).map_err(PluginLlmError::GenerationFailed)
+}
+
+/// Model metadata without loading the model unless `load` is set.
+///
+/// A loaded model reports live engine info (`source: "engine"`); otherwise
+/// static metadata is read from its files (`source: "files"`).
+fn get_model_info(path: &str, load: bool) -> Result<String, String> {
+    let key = model_key(path);
+    let model = match MODELS.get(&key) {
+        Some(model) => {
+            model.touch();
+            model
+        }
+        None if load => open_model(&key)?,
+        None => {
+            let info = model_files::read_static_info(Path::new(&key))?;
+            let mut result = serde_json::to_value(&info).map_err(|e| e.to_string())?;
+            result["loaded"] = json!(false);
+            result["source"] = json!("files");
+            result["capabilities"] = json!(model_files::read_capabilities(Path::new(&key))?);
+            return Ok(result.to_string());
+        }
+    };
+    let info = Engine::model_info(&*model.lock());
+    let (memory_bytes, _) = memory_estimate(&model, Some(info.size));
 
     let result = json!({
         "name": info.name,
         "size": info.size,
         "loaded": info.loaded,
+        "memory_bytes": memory_bytes,
+        "load_ms": model.load_ms,
+        "resolved_path": model.resolved_path,
+        "source": "engine",
+        // `Client` reports no capabilities, so they come from the files either way
+        "capabilities": model_files::read_capabilities(Path::new(&key))?,
     });
 
     Ok(serde_json::to_string(&result).unwrap_or_default())
 }
+
+/// Estimated memory of every loaded model, plus the total.
+///
+/// Models busy generating aren't waited on; their estimate comes from the
+/// weight files on disk instead of the engine.
+fn memory_usage() -> Result<String, String> {
+    let models = loaded_memory()?;
+    let total: u64 = models.iter().map(|(_, bytes, _)| bytes).sum();
+    let breakdown: Vec<serde_json::Value> = models
+        .into_iter()
+        .map(|(key, memory_bytes, source)| {
+            json!({
+                "model": key,
+                "memory_bytes": memory_bytes,
+                "source": source,
+            })
+        })
+        .collect();
+
+    let result = json!({
+        "total_bytes": total,
+        "models": breakdown,
+    });
+    Ok(serde_json::to_string(&result).unwrap_or_default())
+}
+
+/// Each loaded model with its estimated memory and the estimate's source
+fn loaded_memory() -> Result<Vec<(String, u64, &'static str)>, String> {
+    let models = MODELS.entries()?;
+    Ok(models
+        .into_iter()
+        .map(|(key, model)| {
+            let engine_size = model
+                .try_lock()
+                .map(|client| Engine::model_info(&*client).size);
+            let (memory_bytes, source) = memory_estimate(&model, engine_size);
+            (key, memory_bytes, source)
+        })
+        .collect())
+}
+
+/// Memory footprint of a model: the engine's reported size when it gives one,
+/// otherwise the size of its weight files
+fn memory_estimate(
+    model: &registry::Entry<Client>,
+    engine_size: Option<u64>,
+) -> (u64, &'static str) {
+    match engine_size.filter(|size| *size > 0) {
+        Some(size) => (size, "engine"),
+        None => (
+            model_files::weights_bytes(&model.resolved_path),
+            "file_size",
+        ),
+    }
+}
+
+fn drain_generations(timeout_ms: Option<u64>) -> String {
+    let timeout = Duration::from_millis(timeout_ms.unwrap_or(drain::DEFAULT_DRAIN_TIMEOUT_MS));
+    let status = drain::drain(timeout);
+
+    let result = json!({
+        "draining": true,
+        "drained": status.drained,
+        "in_flight": status.in_flight,
+        "waited_ms": status.waited_ms,
+    });
+
+    serde_json::to_string(&result).unwrap_or_default()
+}
+
+fn undrain_generations() -> String {
+    drain::undrain();
+    let result = json!({ "draining": drain::is_draining() });
+    serde_json::to_string(&result).unwrap_or_default()
+}
+
+/// Unload models unused for longer than the idle TTL
+fn evict_idle_models() {
+    let ttl = config::get().idle_model_ttl_secs;
+    if ttl == 0 {
+        return;
+    }
+    for key in MODELS.evict_idle(Duration::from_secs(ttl)) {
+        model_evicted(&key, "idle");
+    }
+}
+
+/// Release transient GPU allocations once the engine has been idle for the
+/// grace period. Weights stay resident; runs at most once per idle period.
+fn release_idle_gpu_memory() {
+    let grace = config::get().gpu_release_grace_ms;
+    if grace == 0 {
+        return;
+    }
+    let grace = Duration::from_millis(grace);
+    match drain::idle_for() {
+        Some(idle) if idle >= grace => {}
+        Some(_) => return,
+        None => {
+            GPU_RELEASED.store(false, Ordering::SeqCst);
+            return;
+        }
+    }
+    if GPU_RELEASED.load(Ordering::SeqCst) {
+        return;
+    }
+
+    // Don't wait behind a busy map or model; try again next tick
+    let Some(models) = MODELS.try_entries().filter(|m| !m.is_empty()) else {
+        return;
+    };
+    let Some(mut clients) = models
+        .iter()
+        .map(|(path, model)| Some((path, model.try_lock()?)))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return;
+    };
+
+    let reclaimed = clients
+        .iter_mut()
+        .map(|(path, client)| release_transient_memory(path, client))
+        .sum::<u64>();
+
+    GPU_RELEASED.store(true, Ordering::SeqCst);
+    tracing::info!(
+        "Released {} bytes of transient GPU memory across {} model(s)",
+        reclaimed,
+        clients.len()
+    );
+}
+
+/// Release transient GPU allocations of every model not currently in use.
+/// Returns the bytes reclaimed and how many models were released.
+fn release_gpu_memory_now() -> (u64, usize) {
+    let Some(models) = MODELS.try_entries() else {
+        return (0, 0);
+    };
+    let mut reclaimed = 0u64;
+    let mut released = 0;
+    for (path, model) in &models {
+        if let Some(mut client) = model.try_lock() {
+            reclaimed += release_transient_memory(path, &mut client);
+            released += 1;
+        }
+    }
+    (reclaimed, released)
+}
+
+fn release_transient_memory(path: &str, client: &mut Client) -> u64 {
+    Engine::release_transient_memory(client).unwrap_or_else(|e| {
+        tracing::warn!("Failed to release GPU memory for {}: {}", path, e);
+        0
+    })
+}
+
+fn start_session(path: &str, token_budget: Option<usize>) -> Result<String, String> {
+    let session_id = sessions::start(&model_key(path), token_budget)?;
+    serde_json::to_string(&sessions::info(&session_id)?).map_err(|e| e.to_string())
+}
+
+fn inspect_model(path: &str) -> Result<String, String> {
+    let inspection = inspect::inspect(Path::new(&model_key(path)))?;
+    serde_json::to_string(&inspection).map_err(|e| e.to_string())
+}
+
+fn get_template_info(path: &str) -> Result<String, String> {
+    let info = model_files::read_template_info(Path::new(&model_key(path)))?;
+    serde_json::to_string(&info).map_err(|e| e.to_string())
+}
+
+/// Run `f` against a loaded model, loading it first if needed
+fn with_model<T>(
+    path: &str,
+    f: impl FnOnce(&mut Client) -> Result<T, String>,
+) -> Result<T, String> {
+    with_model_reporting_load(path, f).map(|(result, _)| result)
+}
+
+/// Like `with_model`, also returning the load time if this call loaded the model
+fn with_model_reporting_load<T>(
+    path: &str,
+    f: impl FnOnce(&mut Client) -> Result<T, String>,
+) -> Result<(T, Option<u64>), String> {
+    // Only this model's lock is held while `f` runs
+    let (model, loaded) = load_entry(&model_key(path))?;
+    let mut client = model.lock();
+    let result = f(&mut client);
+    // Idle time counts from the end of use, not the start of a long generation
+    model.touch();
+    Ok((result?, loaded.then_some(model.load_ms)))
+}
+
+/// Like `with_model_reporting_load`, but first taking one of the model's
+/// generation slots (`max_concurrent_generations`), queueing for one or
+/// failing with `Service busy` per `max_queued_generations`
+fn with_generation_slot<T>(
+    path: &str,
+    f: impl FnOnce(&mut Client) -> Result<T, String>,
+) -> Result<(T, Option<u64>), String> {
+    let (model, loaded) = load_entry(&model_key(path))?;
+    let config = config::get();
+    let _slot = model.acquire_slot(
+        config.max_concurrent_generations,
+        config.max_queued_generations,
+    )?;
+    let mut client = model.lock();
+    let result = f(&mut client);
+    model.touch();
+    Ok((result?, loaded.then_some(model.load_ms)))
+}
+
+/// Number of tokens `text` encodes to with the model at `path`
+fn count_prompt_tokens(path: &str, text: &str) -> Result<usize, String> {
+    with_model(path, |client| {
+        count_tokens(client, text).map_err(String::from)
+    })
+}
+
+/// Vocabulary of the model at `path`, read from its files
+fn read_vocab(path: &str) -> Result<Option<vocab::Vocab>, String> {
+    let key = model_key(path);
+    model_files::check_model_path(Path::new(&key))?;
+    model_files::read_tokenizer(Path::new(&key))?
+        .map(|tokenizer| vocab::Vocab::parse(&tokenizer))
+        .transpose()
+}
+
+/// Split `text` into tokens as `{"id", "piece"}`.
+///
+/// Ids come from the engine's tokenizer; pieces from the model's
+/// `tokenizer.json`, and are null for models that don't ship one.
+fn tokenize_text(path: &str, text: &str) -> Result<String, String> {
+    check_prompt_size(text)?;
+    let ids = with_model(path, |client| {
+        Engine::tokenize(client, text)
+            .map_err(|e| PluginLlmError::TokenizationFailed(e.to_string()).into())
+    })?;
+    let vocab = read_vocab(path)?;
+    let tokens = ids
+        .iter()
+        .map(|&id| {
+            let piece = vocab.as_ref().map(|v| v.piece(id.into())).transpose()?;
+            Ok(json!({ "id": id, "piece": piece }))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+    let result = json!({ "tokens": tokens, "token_count": ids.len() });
+    Ok(serde_json::to_string(&result).unwrap_or_default())
+}
+
+/// Text of a token id sequence, from the model's `tokenizer.json` (no load)
+fn detokenize(path: &str, ids: &[u64]) -> Result<String, String> {
+    let vocab = read_vocab(path)?.ok_or_else(|| {
+        format!(
+            "Unsupported: detokenize needs the model's tokenizer.json, which {} doesn't ship",
+            model_key(path)
+        )
+    })?;
+    let text = vocab.decode(ids)?;
+    Ok(json!({ "text": text }).to_string())
+}
+
+fn prefix_overlap(path: &str, prompt_a: &str, prompt_b: &str) -> Result<String, String> {
+    check_prompt_size(prompt_a)?;
+    check_prompt_size(prompt_b)?;
+
+    let (tokens_a, tokens_b) = with_model(path, |client| {
+        let tokenize = |text| {
+            Engine::tokenize(client, text).map_err(|e| format!("Tokenization failed: {}", e))
+        };
+        Ok((tokenize(prompt_a)?, tokenize(prompt_b)?))
+    })?;
+
+    let shared = tokens_a
+        .iter()
+        .zip(&tokens_b)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let result = json!({
+        "shared_prefix_tokens": shared,
+        "tokens_a": tokens_a.len(),
+        "tokens_b": tokens_b.len(),
+    });
+
+    Ok(serde_json::to_string(&result).unwrap_or_default())
+}
+
+fn next_token_logits(path: &str, prompt: &str, top_k: usize) -> Result<String, String> {
+    check_prompt_size(prompt)?;
+    if top_k == 0 {
+        return Err(PluginLlmError::InvalidArgs("top_k must be at least 1".to_string()).into());
+    }
+    if prompt.is_empty() {
+        return Err(PluginLlmError::InvalidArgs("prompt must not be empty".to_string()).into());
+    }
+
+    // Report path errors first, without loading a model that can't be used
+    model_files::check_model_path(Path::new(&model_key(path)))?;
+
+    // lib_client_uzu only exposes sampled generation, not raw forward-pass output
+    Err(unsupported("next_token_logits", "logits"))
+}
+
+fn embed(path: &str, inputs: &[String]) -> Result<String, String> {
+    if inputs.is_empty() {
+        return Err("Invalid arguments: input must not be empty".to_string());
+    }
+    for input in inputs {
+        check_prompt_size(input)?;
+    }
+
+    // Client only returns sampled text; hidden states never leave the
+    // engine, so no model has `embeddings` and the only question is the path
+    let key = model_key(path);
+    model_files::check_model_path(Path::new(&key))?;
+    Err(PluginLlmError::UnsupportedCapability(
+        key,
+        "embeddings",
+        "the Uzu engine does not expose hidden-state output".to_string(),
+    )
+    .into())
+}
+
+fn score_continuation(path: &str, prompt: &str, continuation: &str) -> Result<String, String> {
+    check_prompt_size(prompt)?;
+    if continuation.is_empty() {
+        return Err(
+            PluginLlmError::InvalidArgs("continuation must not be empty".to_string()).into(),
+        );
+    }
+
+    // Report path errors first, without loading a model that can't be used
+    model_files::check_model_path(Path::new(&model_key(path)))?;
+
+    // Teacher-forced scoring needs per-token logprobs of a fixed continuation
+    Err(unsupported("score", "forced-continuation logprobs"))
+}
+
+/// `lib_client_uzu::Client` can only be constructed from a path, so models
+/// can't be loaded from in-memory bytes.
+fn load_model_bytes(key: &str, data_base64: &str) -> Result<String, String> {
+    if key.is_empty() {
+        return Err("Invalid arguments: key must not be empty".to_string());
+    }
+    if data_base64.is_empty() {
+        return Err("Invalid arguments: data_base64 must not be empty".to_string());
+    }
+    Err(unsupported(
+        "load_bytes",
+        "loading a model from an in-memory buffer",
+    ))
+}
+
+fn unsupported(method: &str, requirement: &str) -> String {
+    format!(
+        "Unsupported: {} requires {}, which the Uzu engine does not expose",
+        method, requirement
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn model_key_strips_trailing_separators() {
+        assert_eq!(model_key("models/foo/"), "models/foo");
+        assert_eq!(model_key("models/foo///"), "models/foo");
+        assert_eq!(model_key("/abs/models/foo/"), "/abs/models/foo");
+    }
+
+    #[test]
+    fn model_key_collapses_redundant_separators() {
+        assert_eq!(model_key("models//foo"), "models/foo");
+        assert_eq!(model_key("./models/./foo/"), "models/foo");
+        assert_eq!(model_key("models/foo"), model_key("models//foo/"));
+    }
+
+    #[test]
+    fn model_key_keeps_root_and_current_dir() {
+        assert_eq!(model_key("/"), "/");
+        assert_eq!(model_key("./"), ".");
+    }
+
+    #[test]
+    fn cli_generate_args_pulls_flags_out_of_prompt() {
+        let args = cli_generate_args(
+            &[
+                "m",
+                "Tell",
+                "--max-tokens",
+                "128",
+                "me",
+                "--temperature",
+                "0.7",
+            ],
+            &json!({}),
+        )
+        .unwrap();
+        assert_eq!(args.prompt, "Tell me");
+        assert_eq!(args.sampling.max_tokens, Some(128));
+        assert_eq!(args.sampling.temperature, Some(0.7));
+    }
+
+    #[test]
+    fn cli_generate_args_rejects_invalid_numbers() {
+        let bad_tokens = cli_generate_args(&["m", "hi", "--max-tokens", "-1"], &json!({}));
+        assert!(bad_tokens.unwrap_err().starts_with("Invalid arguments"));
+        let bad_temp = cli_generate_args(&["m", "hi"], &json!({ "temperature": "-0.5" }));
+        assert!(bad_temp.unwrap_err().starts_with("Invalid arguments"));
+    }
+
+    /// Scratch model directory for tests running on the mock engine
+    fn mock_model(name: &str) -> String {
+        // Once for all tests, so none wipes another's loaded models
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| MODELS.init());
+        let dir = std::env::temp_dir().join(format!("uzu-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("config.json"), r#"{"model_type": "llama"}"#).unwrap();
+        dir.display().to_string()
+    }
+
+    #[test]
+    fn loading_twice_keeps_one_instance() {
+        let path = mock_model("double-load");
+        let (first, loaded) = load_entry(&path).unwrap();
+        assert!(loaded);
+        let (second, loaded) = load_entry(&path).unwrap();
+        assert!(!loaded);
+        assert!(Arc::ptr_eq(&first, &second));
+
+        let result: serde_json::Value =
+            serde_json::from_str(&preload_model(&path, true, None).unwrap()).unwrap();
+        assert_eq!(result["already_loaded"], true);
+        unload_model(&path).unwrap();
+    }
+
+    #[test]
+    fn unloading_twice_or_unloaded_reports_model_not_loaded() {
+        let path = mock_model("unload");
+        load_model(&path, None).unwrap();
+        unload_model(&path).unwrap();
+        assert!(MODELS.get(&model_key(&path)).is_none());
+
+        let e = unload_model(&path).unwrap_err();
+        assert_eq!(e.code(), "model_not_loaded");
+        let e = unload_model(&mock_model("never-loaded")).unwrap_err();
+        assert_eq!(e.code(), "model_not_loaded");
+    }
+
+    #[test]
+    fn engine_failures_map_to_typed_errors() {
+        let e = load_model(&mock_model(engine::mock::OPEN_FAILURE), None).unwrap_err();
+        assert_eq!(e.code(), "model_load_failed");
+
+        let path = mock_model("generate");
+        let generate = |prompt: &str| {
+            let args: GenerateArgs =
+                serde_json::from_value(json!({ "model_path": path, "prompt": prompt, "seed": 1 }))
+                    .unwrap();
+            generate_text(&args)
+        };
+        let result: serde_json::Value = serde_json::from_str(&generate("hi").unwrap()).unwrap();
+        assert_eq!(result["text"], engine::mock::REPLY);
+
+        let e = generate(engine::mock::GENERATE_FAILURE).unwrap_err();
+        assert!(e.starts_with("Generation failed"), "{}", e);
+        unload_model(&path).unwrap();
+    }
+
+    #[test]
+    fn model_sampling_defaults_sit_under_request_params() {
+        let path = mock_model("sampling");
+        load_model(&path, None).unwrap();
+        let defaults = SamplingParams {
+            temperature: Some(0.25),
+            top_k: Some(7),
+            ..Default::default()
+        };
+        set_model_sampling(&path, defaults.clone()).unwrap();
+
+        let generate = |request: serde_json::Value| {
+            let mut args = json!({ "model_path": path, "prompt": "hi" });
+            args.as_object_mut()
+                .unwrap()
+                .extend(request.as_object().unwrap().clone());
+            let args: GenerateArgs = serde_json::from_value(args).unwrap();
+            let result: serde_json::Value =
+                serde_json::from_str(&generate_text(&args).unwrap()).unwrap();
+            result["effective_params"].clone()
+        };
+        let params = generate(json!({}));
+        assert_eq!(params["temperature"], 0.25);
+        assert_eq!(params["top_k"], 7);
+        let params = generate(json!({ "temperature": 0.5 }));
+        assert_eq!(params["temperature"], 0.5);
+        assert_eq!(params["top_k"], 7);
+
+        reload_model(&path).unwrap();
+        assert_eq!(model_sampling(&model_key(&path)), defaults);
+        let e = set_model_sampling(
+            &path,
+            SamplingParams {
+                top_p: Some(2.0),
+                ..Default::default()
+            },
+        )
+        .unwrap_err();
+        assert_eq!(e.code(), "invalid_args");
+        unload_model(&path).unwrap();
+        let e = set_model_sampling(&path, defaults).unwrap_err();
+        assert_eq!(e.code(), "model_not_loaded");
+    }
+
+    #[test]
+    fn bulk_unload_frees_matching_models_only() {
+        let kept = mock_model("bulk-kept");
+        let gone = mock_model("bulk-gone");
+        load_model(&kept, None).unwrap();
+        load_model(&gone, Some("bulk-alias")).unwrap();
+
+        let unload = |matching| {
+            bulk_unload::unload(&bulk_unload::Filter {
+                older_than: None,
+                matching: Some(matching),
+            })
+            .unwrap()
+        };
+        let result = unload("bulk-ali*");
+        assert_eq!(result["unloaded"], json!([model_key(&gone)]));
+        assert!(MODELS.get(&model_key(&kept)).is_some());
+        assert_eq!(MODELS.resolve_alias("bulk-alias"), None);
+
+        let result = unload("*no-such-model*");
+        assert_eq!(result["unloaded"], json!([]));
+        assert_eq!(result["message"], "no models matched");
+
+        let result = bulk_unload::unload(&bulk_unload::Filter {
+            older_than: Some(Duration::from_secs(3600)),
+            matching: Some("*bulk-kept*"),
+        })
+        .unwrap();
+        assert_eq!(result["message"], "no models matched");
+        unload_model(&kept).unwrap();
+    }
+
+    #[test]
+    fn oversized_payloads_are_refused_with_the_limit() {
+        let args: GenerateArgs = serde_json::from_value(json!({
+            "model_path": mock_model("payload"),
+            "prompt": "hi",
+            "max_tokens": 1_000_000,
+        }))
+        .unwrap();
+        let e = generate_text(&args).unwrap_err();
+        assert_eq!(error::code_of(&e), "payload_too_large");
+        assert_eq!(
+            e,
+            "Payload too large: max_tokens 1000000 exceeds limit of 131072 (max_tokens_limit)"
+        );
+
+        let huge = "x".repeat(config::get().max_prompt_bytes + 1);
+        let e = check_prompt_size(&huge).unwrap_err();
+        assert_eq!(e.code(), "payload_too_large");
+        assert!(e.to_string().contains("(max_prompt_bytes)"), "{}", e);
+    }
+
+    #[test]
+    fn images_need_a_vision_model_and_valid_data() {
+        let text_only = mock_model("images-text");
+        let vision = mock_model("images-vision");
+        std::fs::write(
+            Path::new(&vision).join("config.json"),
+            r#"{"model_type": "llava", "vision_config": {}}"#,
+        )
+        .unwrap();
+        let generate = |path: &str, image: serde_json::Value| {
+            let args: GenerateArgs = serde_json::from_value(json!({
+                "model_path": path,
+                "prompt": "describe this",
+                "images": [image],
+            }))
+            .unwrap();
+            generate_text(&args).unwrap_err()
+        };
+        // A GIF header is enough to pass the signature check
+        let gif = json!({ "data_base64": "R0lGODlhAQABAAAAACw=" });
+
+        let e = generate(&text_only, gif.clone());
+        assert_eq!(error::code_of(&e), "unsupported_capability", "{}", e);
+        let e = generate(&vision, gif);
+        assert!(e.contains("multimodal image input"), "{}", e);
+
+        let e = generate(&vision, json!({ "data_base64": "not base64!" }));
+        assert!(e.starts_with("Invalid arguments: images[0]:"), "{}", e);
+        let e = generate(&vision, json!({ "path": "/nonexistent/cat.png" }));
+        assert_eq!(error::code_of(&e), "invalid_args", "{}", e);
+    }
+
+    #[test]
+    fn stats_count_generations_per_model_on_every_path() {
+        let path = mock_model("stats");
+        let args: GenerateArgs =
+            serde_json::from_value(json!({ "model_path": path, "prompt": "hi", "seed": 1 }))
+                .unwrap();
+        generate_text(&args).unwrap();
+        generate_text_streaming(&args, &mut |_| true).unwrap();
+
+        let stats = stats::snapshot();
+        let model = &stats["models"][path.as_str()];
+        assert_eq!(model["generations"], 2, "{}", stats);
+        assert_eq!(model["tokens_generated"], 8, "{}", stats);
+        assert!(stats["generations"].as_u64().unwrap() >= 2);
+        assert!(stats["models_loaded"].as_u64().unwrap() >= 1);
+        assert!(stats.get("uptime_ms").is_some());
+        unload_model(&path).unwrap();
+    }
+
+    #[test]
+    fn model_stop_defaults_merge_with_request_stops() {
+        let path = mock_model("stops");
+        load_model(&path, None).unwrap();
+        set_model_stops(
+            &path,
+            StopDefaults {
+                stop: vec![" three".to_string()],
+                ignore_eos: false,
+            },
+        )
+        .unwrap();
+
+        let generate = |request: serde_json::Value| {
+            let mut args = json!({ "model_path": path, "prompt": "hi", "seed": 1 });
+            args.as_object_mut()
+                .unwrap()
+                .extend(request.as_object().unwrap().clone());
+            let args: GenerateArgs = serde_json::from_value(args).unwrap();
+            generate_text(&args).map(|r| serde_json::from_str::<serde_json::Value>(&r).unwrap())
+        };
+        let result = generate(json!({})).unwrap();
+        assert_eq!(result["text"], "one two");
+        assert_eq!(result["stop_sequence"], " three");
+        assert!(result.get("eos_ignored").is_none());
+        let result = generate(json!({ "stop": [" two"] })).unwrap();
+        assert_eq!(result["text"], "one");
+        let result = generate(json!({ "stop": [], "replace_stop": true })).unwrap();
+        assert_eq!(result["text"], engine::mock::REPLY);
+
+        let result =
+            generate(json!({ "replace_stop": true, "ignore_eos": true, "max_tokens": 6 })).unwrap();
+        assert_eq!(result["text"], "one two three fourone two");
+        assert_eq!(result["tokens_generated"], 6);
+        assert_eq!(result["stop_reason"], "max_tokens");
+        assert_eq!(result["eos_ignored"], true);
+        let e = generate(json!({ "ignore_eos": true })).unwrap_err();
+        assert!(e.contains("ignore_eos requires max_tokens"), "{}", e);
+
+        // Defaults given at load survive a reload
+        reload_model(&path).unwrap();
+        assert_eq!(generate(json!({})).unwrap()["text"], "one two");
+        unload_model(&path).unwrap();
+    }
+
+    #[test]
+    fn ban_substrings_are_refused_with_greedy_decoding() {
+        let path = mock_model("ban-greedy");
+        for sampling in [json!({ "temperature": 0.0 }), json!({ "top_k": 1 })] {
+            let mut args = json!({ "model_path": path, "prompt": "hi", "ban_substrings": ["two"] });
+            args.as_object_mut()
+                .unwrap()
+                .extend(sampling.as_object().unwrap().clone());
+            let args: GenerateArgs = serde_json::from_value(args).unwrap();
+            let e = generate_text(&args).unwrap_err();
+            assert!(e.contains("greedy decoding"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn unsubscribing_the_last_consumer_cancels_the_job() {
+        let path = mock_model("unsubscribe");
+        load_model(&path, None).unwrap();
+        let model = MODELS.get(&model_key(&path)).unwrap();
+
+        // Held so the job can't produce a token before it is abandoned
+        let busy = model.lock();
+        let args: GenerateArgs =
+            serde_json::from_value(json!({ "model_path": path, "prompt": "hi" })).unwrap();
+        let job_id = polling::generate_async(args).unwrap();
+        polling::unsubscribe(&job_id, None).unwrap();
+        drop(busy);
+
+        let result = loop {
+            match polling::job_result(&job_id) {
+                Err(e) if e.contains("still running") => {
+                    std::thread::sleep(Duration::from_millis(5))
+                }
+                result => break result.unwrap(),
+            }
+        };
+        let result: serde_json::Value = serde_json::from_str(&result).unwrap();
+        assert_eq!(result["stop_reason"], "cancelled", "{}", result);
+        unload_model(&path).unwrap();
+    }
+
+    #[test]
+    fn rendered_prompt_is_the_truncated_prompt() {
+        let path = mock_model("rendered-truncated");
+        std::fs::write(
+            Path::new(&path).join("config.json"),
+            r#"{"model_type": "llama", "max_position_embeddings": 10}"#,
+        )
+        .unwrap();
+        let args: GenerateArgs = serde_json::from_value(json!({
+            "model_path": path,
+            "prompt": "abcdefghijklmnop",
+            "max_tokens": 2,
+            "truncate": true,
+            "include_rendered_prompt": true,
+        }))
+        .unwrap();
+        let result: serde_json::Value =
+            serde_json::from_str(&generate_text(&args).unwrap()).unwrap();
+        // The mock tokenizer has one token per byte
+        assert_eq!(result["rendered_prompt"], "ijklmnop");
+        assert_eq!(result["truncated_prompt_tokens"], 8);
+        unload_model(&path).unwrap();
+    }
+
+    #[test]
+    fn unsupported_methods_classify_bad_arguments_as_invalid() {
+        let path = mock_model("logits-args");
+        let e = next_token_logits(&path, "hi", 0).unwrap_err();
+        assert_eq!(error::code_of(&e), "invalid_args", "{}", e);
+        let e = next_token_logits(&path, "", 5).unwrap_err();
+        assert_eq!(error::code_of(&e), "invalid_args", "{}", e);
+        let e = score_continuation(&path, "hi", "").unwrap_err();
+        assert_eq!(error::code_of(&e), "invalid_args", "{}", e);
+        let e = embed(&path, &["hi".to_string()]).unwrap_err();
+        assert_eq!(error::code_of(&e), "unsupported_capability", "{}", e);
+    }
+
+    #[test]
+    fn append_token_must_stay_a_single_token() {
+        let path = mock_model("append-token");
+        let generate = |token: &str| {
+            let args: GenerateArgs = serde_json::from_value(json!({
+                "model_path": path,
+                "prompt": "hi",
+                "append_token": token,
+                "include_rendered_prompt": true,
+            }))
+            .unwrap();
+            generate_text(&args)
+        };
+        let result: serde_json::Value = serde_json::from_str(&generate("!").unwrap()).unwrap();
+        assert_eq!(result["rendered_prompt"], "hi!");
+        // The mock tokenizer has one token per byte
+        let e = generate("!!").unwrap_err();
+        assert_eq!(error::code_of(&e), "invalid_args", "{}", e);
+        unload_model(&path).unwrap();
+    }
+}
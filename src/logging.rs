@@ -0,0 +1,129 @@
+//! Structured log lines for model and generation events
+//!
+//! Lines go through `tracing`, which the host routes into its own log, with
+//! an `event` field plus typed fields so they can be filtered and parsed.
+//! The `log_level` config gates them on top of the host's own filtering.
+//! Prompts are only logged with `log_prompts`, since they may hold user data.
+
+use crate::config;
+use serde::Deserialize;
+use std::time::Duration;
+
+/// How much the plugin logs about its own events (`log_level` config)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    /// Failed loads and generations only
+    Error,
+    /// Also loads, unloads and finished generations
+    #[default]
+    Info,
+    /// Also generation starts
+    Debug,
+}
+
+fn enabled(level: LogLevel) -> bool {
+    config::get().log_level >= level
+}
+
+pub fn model_loaded(key: &str, load_ms: u64) {
+    if enabled(LogLevel::Info) {
+        tracing::info!(
+            event = "model_loaded",
+            model = key,
+            load_ms,
+            "Loaded model {}",
+            key
+        );
+    }
+}
+
+pub fn model_load_failed(key: &str, error: &str) {
+    if enabled(LogLevel::Error) {
+        tracing::error!(
+            event = "model_load_failed",
+            model = key,
+            error,
+            "Failed to load model {}",
+            key
+        );
+    }
+}
+
+/// A model left the registry (`reason`: unload, reload, idle, max_models)
+pub fn model_unloaded(key: &str, reason: &str) {
+    if enabled(LogLevel::Info) {
+        tracing::info!(
+            event = "model_unloaded",
+            model = key,
+            reason,
+            "Unloaded model {} ({})",
+            key,
+            reason
+        );
+    }
+}
+
+pub fn generation_started(model: &str, request_id: Option<&str>, prompt: &str) {
+    if !enabled(LogLevel::Debug) {
+        return;
+    }
+    let request_id = request_id.unwrap_or_default();
+    if config::get().log_prompts {
+        tracing::debug!(
+            event = "generation_started",
+            model,
+            request_id,
+            prompt_bytes = prompt.len(),
+            prompt,
+            "Generation started on {}",
+            model
+        );
+    } else {
+        tracing::debug!(
+            event = "generation_started",
+            model,
+            request_id,
+            prompt_bytes = prompt.len(),
+            "Generation started on {}",
+            model
+        );
+    }
+}
+
+pub fn generation_finished(
+    model: &str,
+    prompt_tokens: usize,
+    tokens: usize,
+    elapsed: Duration,
+    stop_reason: &str,
+) {
+    if enabled(LogLevel::Info) {
+        tracing::info!(
+            event = "generation_finished",
+            model,
+            prompt_tokens,
+            tokens,
+            elapsed_ms = elapsed.as_millis() as u64,
+            stop_reason,
+            "Generated {} tokens on {}",
+            tokens,
+            model
+        );
+    }
+}
+
+pub fn generation_failed(model: &str, request_id: Option<&str>, error: &str) {
+    if enabled(LogLevel::Error) {
+        tracing::error!(
+            event = "generation_failed",
+            model,
+            request_id = request_id.unwrap_or_default(),
+            code = crate::error::code_of(error),
+            error,
+            "Generation on {} failed",
+            model
+        );
+    }
+}
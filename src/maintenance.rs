@@ -0,0 +1,43 @@
+//! Background maintenance
+//!
+//! A ticker thread started in `init` runs periodic housekeeping until
+//! `shutdown`.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Start the maintenance thread (no-op if already running)
+pub fn start() {
+    if RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+
+    let spawned = std::thread::Builder::new()
+        .name("uzu-maintenance".to_string())
+        .spawn(|| {
+            while RUNNING.load(Ordering::SeqCst) {
+                std::thread::sleep(TICK_INTERVAL);
+                update();
+            }
+        });
+
+    if let Err(e) = spawned {
+        RUNNING.store(false, Ordering::SeqCst);
+        tracing::error!("Failed to start maintenance thread: {}", e);
+    }
+}
+
+/// Stop the maintenance thread after its current tick
+pub fn stop() {
+    RUNNING.store(false, Ordering::SeqCst);
+}
+
+/// One housekeeping tick
+fn update() {
+    crate::evict_idle_models();
+    crate::release_idle_gpu_memory();
+}
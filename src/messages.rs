@@ -0,0 +1,110 @@
+//! Host-initiated commands (`plugin_handle_message`)
+//!
+//! The host pushes JSON envelopes like `{"type": "unload_all"}` outside of
+//! any inference call, e.g. when the system is low on memory. Every reply
+//! echoes the `type` so the host can match it to the action taken.
+
+use crate::engine::Engine;
+use crate::error::PluginLlmError;
+use crate::{
+    config, memory_estimate, model_evicted, release_gpu_memory_now, unload_all_models, MODELS,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// Message envelope sent by the host
+#[derive(Debug, Deserialize)]
+struct Message {
+    #[serde(rename = "type")]
+    kind: String,
+    /// `memory_pressure`: estimated model memory to unload down to
+    #[serde(default)]
+    target_bytes: Option<u64>,
+}
+
+/// Decode a message and dispatch it to its handler
+pub fn handle(message: &str) -> Result<String, String> {
+    let message: Message = serde_json::from_str(message)
+        .map_err(|e| format!("Invalid arguments: bad message envelope: {}", e))?;
+
+    let mut reply = match message.kind.as_str() {
+        "ping" => json!({ "pong": true }),
+        "unload_all" => json!({ "unloaded": unload_all_models()? }),
+        "memory_pressure" => memory_pressure(
+            message
+                .target_bytes
+                .or(config::get().memory_pressure_floor_bytes),
+        )?,
+        other => return Err(format!("Unknown message type: {}", other)),
+    };
+    reply["type"] = json!(message.kind);
+    Ok(serde_json::to_string(&reply).unwrap_or_default())
+}
+
+/// Free transient GPU memory now, then unload idle models least recently
+/// used first: until the estimated total fits `target_bytes`, or without a
+/// target, all but the most recently used one. Fails as `ServiceBusy`
+/// rather than wait on a locked model map.
+fn memory_pressure(target_bytes: Option<u64>) -> Result<Value, String> {
+    let (released_bytes, released_models) = release_gpu_memory_now();
+    let mut reply = json!({
+        "released_bytes": released_bytes,
+        "released_models": released_models,
+    });
+    let Some(target_bytes) = target_bytes else {
+        let (evicted, skipped) = MODELS.shrink_idle(1)?;
+        for key in &evicted {
+            model_evicted(key, "memory_pressure");
+        }
+        reply["evicted"] = json!(evicted);
+        reply["skipped"] = json!(skipped
+            .iter()
+            .map(|key| json!({ "model": key, "reason": "in_flight" }))
+            .collect::<Vec<_>>());
+        return Ok(reply);
+    };
+
+    let mut models = MODELS
+        .try_entries()
+        .ok_or_else(|| PluginLlmError::ServiceBusy("the model map is locked".to_string()))?;
+    models.sort_by_key(|(_, model)| model.last_access_at());
+    let estimates: Vec<(String, u64)> = models
+        .iter()
+        .map(|(key, model)| {
+            let engine_size = model
+                .try_lock()
+                .map(|client| Engine::model_info(&*client).size);
+            (key.clone(), memory_estimate(model, engine_size).0)
+        })
+        .collect();
+    let mut total_bytes: u64 = estimates.iter().map(|(_, bytes)| bytes).sum();
+
+    let mut unloaded = Vec::new();
+    let mut skipped = Vec::new();
+    let mut reclaimed_bytes = 0;
+    for (key, bytes) in estimates {
+        if total_bytes <= target_bytes {
+            break;
+        }
+        // A model with a generation running or queued stays, whatever it holds
+        if !MODELS.evict_if_idle(&key) {
+            skipped.push(json!({ "model": key, "reason": "in_flight" }));
+            continue;
+        }
+        model_evicted(&key, "memory_pressure");
+        total_bytes -= bytes;
+        reclaimed_bytes += bytes;
+        unloaded.push(json!({ "model": key, "bytes": bytes }));
+    }
+
+    reply["evicted"] = json!(unloaded
+        .iter()
+        .map(|model| model["model"].clone())
+        .collect::<Vec<_>>());
+    reply["unloaded"] = json!(unloaded);
+    reply["skipped"] = json!(skipped);
+    reply["reclaimed_bytes"] = json!(reclaimed_bytes);
+    reply["target_bytes"] = json!(target_bytes);
+    reply["total_bytes"] = json!(total_bytes);
+    Ok(reply)
+}
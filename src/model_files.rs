@@ -0,0 +1,482 @@
+//! On-disk model metadata
+//!
+//! Reads the Hugging Face style JSON files shipped in a model directory
+//! without loading the model into the engine.
+
+use crate::error::PluginLlmError;
+use serde::Serialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+const MODEL_CONFIG: &str = "config.json";
+const TOKENIZER_CONFIG: &str = "tokenizer_config.json";
+const GENERATION_CONFIG: &str = "generation_config.json";
+const TOKENIZER: &str = "tokenizer.json";
+
+/// Extensions of single-file models accepted in place of a model directory
+const MODEL_FILE_EXTENSIONS: &[&str] = &["gguf", "safetensors"];
+
+/// `config.json` keys that may hold the context window, in order of preference
+const CONTEXT_LENGTH_KEYS: &[&str] = &[
+    "max_position_embeddings",
+    "context_length",
+    "max_sequence_length",
+    "max_seq_len",
+    "seq_length",
+    "n_ctx",
+];
+
+/// Chat template and special-token metadata for a model
+#[derive(Debug, Default, Serialize)]
+pub struct TemplateInfo {
+    pub has_chat_template: bool,
+    pub bos_token: Option<String>,
+    pub eos_token: Option<String>,
+    /// Stop strings derived from EOS and end-of-turn special tokens
+    pub recommended_stops: Vec<String>,
+}
+
+/// Model metadata read from disk, for `info` on a model that isn't loaded
+#[derive(Debug, Serialize)]
+pub struct StaticInfo {
+    pub name: String,
+    /// Size of the weight files in bytes
+    pub size: u64,
+    pub architecture: Option<String>,
+    /// `quantization_config` / `quantization` from `config.json`, as given
+    pub quantization: Option<Value>,
+    pub context_length: Option<usize>,
+}
+
+/// What a model can be used for, from its metadata files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Capabilities {
+    /// Ships a chat template, so `chat` can render messages for it
+    pub chat: bool,
+    /// Always false: the engine returns sampled text, never hidden states
+    pub embeddings: bool,
+    /// `config.json` declares a vision encoder (images can't be passed in yet)
+    pub vision: bool,
+}
+
+/// `config.json` keys that only multimodal models with a vision encoder have
+const VISION_CONFIG_KEYS: &[&str] = &[
+    "vision_config",
+    "vision_tower",
+    "mm_vision_tower",
+    "image_token_index",
+    "image_token_id",
+];
+
+/// Capability flags of the model at `path`
+pub fn read_capabilities(path: &Path) -> Result<Capabilities, String> {
+    check_model_path(path)?;
+    let config = read_json(&model_dir(path).join(MODEL_CONFIG))?.unwrap_or_default();
+    Ok(Capabilities {
+        chat: read_chat_template(path)?.is_some(),
+        embeddings: false,
+        vision: VISION_CONFIG_KEYS
+            .iter()
+            .any(|key| config.get(key).is_some()),
+    })
+}
+
+/// Chat template source and the special tokens it references
+#[derive(Debug, Clone)]
+pub struct ChatTemplate {
+    pub template: String,
+    pub bos_token: Option<String>,
+    pub eos_token: Option<String>,
+    /// Special tokens from `added_tokens_decoder`, plus BOS/EOS
+    pub special_tokens: Vec<String>,
+}
+
+/// Read the model's chat template, if it ships one
+pub fn read_chat_template(path: &Path) -> Result<Option<ChatTemplate>, String> {
+    if !path.exists() {
+        return Err(format!("Model not found: {}", path.display()));
+    }
+    let Some(config) = read_json(&model_dir(path).join(TOKENIZER_CONFIG))? else {
+        return Ok(None);
+    };
+    let Some(template) = render_template(&config) else {
+        return Ok(None);
+    };
+
+    let bos_token = config.get("bos_token").and_then(token_content);
+    let eos_token = config.get("eos_token").and_then(token_content);
+    let mut special_tokens: Vec<String> = added_tokens(&config)
+        .into_iter()
+        .filter(|(_, _, special)| *special)
+        .map(|(_, content, _)| content)
+        .collect();
+    for token in bos_token.iter().chain(&eos_token) {
+        if !special_tokens.contains(token) {
+            special_tokens.push(token.clone());
+        }
+    }
+
+    Ok(Some(ChatTemplate {
+        template,
+        bos_token,
+        eos_token,
+        special_tokens,
+    }))
+}
+
+/// The model's `config.json`, if it ships one
+pub fn read_config(path: &Path) -> Result<Option<Value>, String> {
+    read_json(&model_dir(path).join(MODEL_CONFIG))
+}
+
+/// The model's `tokenizer_config.json`, if it ships one
+pub fn read_tokenizer_config(path: &Path) -> Result<Option<Value>, String> {
+    read_json(&model_dir(path).join(TOKENIZER_CONFIG))
+}
+
+/// The model's `tokenizer.json`, if it ships one
+pub fn read_tokenizer(path: &Path) -> Result<Option<Value>, String> {
+    read_json(&model_dir(path).join(TOKENIZER))
+}
+
+/// Context window in tokens from the model's `config.json`, if declared
+pub fn read_context_length(path: &Path) -> Result<Option<usize>, String> {
+    let Some(config) = read_json(&model_dir(path).join(MODEL_CONFIG))? else {
+        return Ok(None);
+    };
+    // Multimodal configs nest the language model under `text_config`
+    let text_config = config.get("text_config").unwrap_or(&config);
+    Ok(CONTEXT_LENGTH_KEYS
+        .iter()
+        .find_map(|key| text_config.get(*key).and_then(Value::as_u64))
+        .map(|n| n as usize))
+}
+
+/// Read name, size, architecture, quantization and context length from the
+/// model's files without loading it.
+///
+/// The name is `config.json`'s `_name_or_path`, else the file or directory name.
+pub fn read_static_info(path: &Path) -> Result<StaticInfo, String> {
+    check_model_path(path)?;
+    let config = read_json(&model_dir(path).join(MODEL_CONFIG))?.unwrap_or_default();
+    let text_config = config.get("text_config").unwrap_or(&config);
+
+    let name = config
+        .get("_name_or_path")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .or_else(|| path.file_name().map(|n| n.to_string_lossy().into_owned()))
+        .unwrap_or_else(|| path.display().to_string());
+    let architecture = config
+        .get("architectures")
+        .and_then(|a| a.get(0))
+        .or_else(|| text_config.get("model_type"))
+        .and_then(Value::as_str)
+        .map(str::to_string);
+    let quantization = config
+        .get("quantization_config")
+        .or_else(|| config.get("quantization"))
+        .cloned();
+
+    Ok(StaticInfo {
+        name,
+        size: weights_bytes(path),
+        architecture,
+        quantization,
+        context_length: read_context_length(path)?,
+    })
+}
+
+/// Read template metadata for the model at `path`.
+///
+/// Models without a `tokenizer_config.json` (e.g. single-file models) yield
+/// empty metadata rather than an error.
+pub fn read_template_info(path: &Path) -> Result<TemplateInfo, String> {
+    if !path.exists() {
+        return Err(format!("Model not found: {}", path.display()));
+    }
+
+    let Some(config) = read_json(&model_dir(path).join(TOKENIZER_CONFIG))? else {
+        return Ok(TemplateInfo::default());
+    };
+    let generation = read_json(&model_dir(path).join(GENERATION_CONFIG))?;
+
+    let chat_template = chat_template(&config);
+    let bos_token = config.get("bos_token").and_then(token_content);
+    let eos_token = config.get("eos_token").and_then(token_content);
+
+    let added_tokens = added_tokens(&config);
+
+    let mut recommended_stops = Vec::new();
+    let mut push_stop = |stop: &str| {
+        if !stop.is_empty() && !recommended_stops.iter().any(|s| s == stop) {
+            recommended_stops.push(stop.to_string());
+        }
+    };
+
+    if let Some(eos) = &eos_token {
+        push_stop(eos);
+    }
+
+    // generation_config.json may list several EOS ids
+    let eos_ids: Vec<u64> = match generation.as_ref().and_then(|g| g.get("eos_token_id")) {
+        Some(Value::Number(n)) => n.as_u64().into_iter().collect(),
+        Some(Value::Array(ids)) => ids.iter().filter_map(Value::as_u64).collect(),
+        _ => Vec::new(),
+    };
+    for (id, content, _) in &added_tokens {
+        if eos_ids.contains(id) {
+            push_stop(content);
+        }
+    }
+
+    // End-of-turn markers used by the chat template
+    if let Some(template) = &chat_template {
+        for (_, content, special) in &added_tokens {
+            if *special && template.contains(content.as_str()) && is_end_marker(content) {
+                push_stop(content);
+            }
+        }
+    }
+
+    Ok(TemplateInfo {
+        has_chat_template: chat_template.is_some(),
+        bos_token,
+        eos_token,
+        recommended_stops,
+    })
+}
+
+/// Check that `path` names a model before handing it to the engine: a
+/// directory, or a single model file (`.gguf`, `.safetensors`).
+pub fn check_model_path(path: &Path) -> Result<(), PluginLlmError> {
+    let display = || path.display().to_string();
+    let metadata = path.metadata().map_err(|_| {
+        // A bare name was most likely meant as an alias
+        if path.components().count() == 1 && path.extension().is_none() {
+            PluginLlmError::UnknownModel(display())
+        } else {
+            PluginLlmError::ModelNotFound(display())
+        }
+    })?;
+
+    if metadata.is_dir() {
+        return Ok(());
+    }
+    if !metadata.is_file() {
+        return Err(PluginLlmError::InvalidModelPath(
+            display(),
+            "is neither a file nor a directory".to_string(),
+        ));
+    }
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    if MODEL_FILE_EXTENSIONS
+        .iter()
+        .any(|ext| ext.eq_ignore_ascii_case(extension))
+    {
+        return Ok(());
+    }
+    Err(PluginLlmError::InvalidModelPath(
+        display(),
+        "is a file, expected a model directory or a .gguf/.safetensors file".to_string(),
+    ))
+}
+
+/// Total size of the model's weight files, a proxy for the memory it occupies
+/// once loaded (quantized weights are already smaller on disk).
+///
+/// For a directory this sums every file except the JSON/text metadata.
+pub fn weights_bytes(path: &Path) -> u64 {
+    if path.is_file() {
+        return path.metadata().map(|m| m.len()).unwrap_or(0);
+    }
+    let Ok(entries) = std::fs::read_dir(path) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .filter(|entry| {
+            let path = entry.path();
+            path.is_file()
+                && !matches!(
+                    path.extension().and_then(|e| e.to_str()),
+                    Some("json" | "txt" | "md" | "jinja")
+                )
+        })
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Directory holding the model's metadata files
+fn model_dir(path: &Path) -> PathBuf {
+    if path.is_dir() {
+        path.to_path_buf()
+    } else {
+        path.parent().map(Path::to_path_buf).unwrap_or_default()
+    }
+}
+
+/// Read a JSON file, returning `None` when it doesn't exist
+fn read_json(path: &Path) -> Result<Option<Value>, String> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_str(&content)
+        .map(Some)
+        .map_err(|e| format!("Invalid {}: {}", path.display(), e))
+}
+
+/// `chat_template` is either a string or a list of named templates
+fn chat_template(config: &Value) -> Option<String> {
+    match config.get("chat_template")? {
+        Value::String(template) => Some(template.clone()),
+        Value::Array(templates) => {
+            let templates: Vec<&str> = templates
+                .iter()
+                .filter_map(|t| t.get("template").and_then(Value::as_str))
+                .collect();
+            (!templates.is_empty()).then(|| templates.join("\n"))
+        }
+        _ => None,
+    }
+}
+
+/// The template to render: the one named `default` when several are listed
+fn render_template(config: &Value) -> Option<String> {
+    match config.get("chat_template")? {
+        Value::String(template) => Some(template.clone()),
+        Value::Array(templates) => templates
+            .iter()
+            .find(|t| t.get("name").and_then(Value::as_str) == Some("default"))
+            .or_else(|| templates.first())
+            .and_then(|t| t.get("template"))
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        _ => None,
+    }
+}
+
+/// `added_tokens_decoder` entries as (id, content, special)
+fn added_tokens(config: &Value) -> Vec<(u64, String, bool)> {
+    config
+        .get("added_tokens_decoder")
+        .and_then(Value::as_object)
+        .map(|decoder| {
+            decoder
+                .iter()
+                .filter_map(|(id, token)| {
+                    let id = id.parse().ok()?;
+                    let content = token.get("content")?.as_str()?.to_string();
+                    let special = token
+                        .get("special")
+                        .and_then(Value::as_bool)
+                        .unwrap_or(false);
+                    Some((id, content, special))
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Special tokens are either plain strings or `{"content": ...}` objects
+fn token_content(token: &Value) -> Option<String> {
+    match token {
+        Value::String(content) => Some(content.clone()),
+        Value::Object(_) => token.get("content")?.as_str().map(str::to_string),
+        _ => None,
+    }
+}
+
+fn is_end_marker(token: &str) -> bool {
+    let lower = token.to_ascii_lowercase();
+    lower == "</s>" || lower.contains("end") || lower.contains("eot")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh scratch directory under the system temp dir
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("uzu-model-files-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn check_model_path_reports_missing_path() {
+        let missing = scratch_dir("missing").join("no-such-model");
+        let err = check_model_path(&missing).unwrap_err();
+        assert_eq!(err.code(), "model_not_found");
+        assert_eq!(
+            err.to_string(),
+            format!("Model not found: {} does not exist", missing.display())
+        );
+    }
+
+    #[test]
+    fn check_model_path_rejects_file_instead_of_directory() {
+        let dir = scratch_dir("file");
+        let config = dir.join("config.json");
+        std::fs::write(&config, "{}").unwrap();
+
+        let err = check_model_path(&config).unwrap_err();
+        assert_eq!(err.code(), "invalid_model_path");
+        assert!(err.to_string().contains(&config.display().to_string()));
+        assert!(err.to_string().contains("is a file"));
+
+        assert_eq!(check_model_path(&dir), Ok(()));
+        let weights = dir.join("model.gguf");
+        std::fs::write(&weights, "").unwrap();
+        assert_eq!(check_model_path(&weights), Ok(()));
+    }
+
+    #[test]
+    fn read_static_info_uses_config_json() {
+        let dir = scratch_dir("static-info");
+        std::fs::write(
+            dir.join("config.json"),
+            r#"{"architectures": ["LlamaForCausalLM"], "max_position_embeddings": 8192,
+                "quantization": {"bits": 4, "group_size": 64}}"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("model.safetensors"), [0u8; 16]).unwrap();
+
+        let info = read_static_info(&dir).unwrap();
+        assert_eq!(info.name, dir.file_name().unwrap().to_string_lossy());
+        assert_eq!(info.size, 16);
+        assert_eq!(info.architecture.as_deref(), Some("LlamaForCausalLM"));
+        assert_eq!(info.quantization.unwrap()["bits"], 4);
+        assert_eq!(info.context_length, Some(8192));
+    }
+
+    #[test]
+    fn read_capabilities_checks_template_and_vision_config() {
+        let dir = scratch_dir("capabilities");
+        std::fs::write(dir.join("config.json"), r#"{"vision_config": {}}"#).unwrap();
+        std::fs::write(dir.join("model.safetensors"), [0u8; 16]).unwrap();
+        let capabilities = read_capabilities(&dir).unwrap();
+        assert!(!capabilities.chat);
+        assert!(capabilities.vision);
+
+        std::fs::write(dir.join("config.json"), "{}").unwrap();
+        std::fs::write(
+            dir.join("tokenizer_config.json"),
+            r#"{"chat_template": "{{ messages }}"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            read_capabilities(&dir).unwrap(),
+            Capabilities {
+                chat: true,
+                embeddings: false,
+                vision: false
+            }
+        );
+    }
+}
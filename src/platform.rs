@@ -0,0 +1,41 @@
+//! Hardware support check
+//!
+//! The Uzu engine runs on Metal, which only exists on Apple Silicon Macs.
+//! Elsewhere `Client::new` fails deep inside the engine with an opaque Metal
+//! error, so the plugin checks up front and refuses with a clear message.
+
+use crate::error::PluginLlmError;
+use once_cell::sync::Lazy;
+
+/// Present on every macOS install that has Metal
+const METAL_FRAMEWORK: &str = "/System/Library/Frameworks/Metal.framework";
+
+/// Checked once per process; `Err` holds why this machine is unsupported
+static SUPPORT: Lazy<Result<(), String>> = Lazy::new(probe);
+
+fn probe() -> Result<(), String> {
+    // Unit tests run on the mock engine, which needs neither
+    if cfg!(test) {
+        return Ok(());
+    }
+    if !cfg!(all(target_os = "macos", target_arch = "aarch64")) {
+        return Err(format!(
+            "this build targets {}-{}",
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        ));
+    }
+    if !std::path::Path::new(METAL_FRAMEWORK).exists() {
+        return Err("Metal is not available".to_string());
+    }
+    Ok(())
+}
+
+/// `Ok` on Apple Silicon with Metal, otherwise `UnsupportedPlatform`
+pub fn check() -> Result<(), PluginLlmError> {
+    SUPPORT.clone().map_err(PluginLlmError::UnsupportedPlatform)
+}
+
+pub fn is_supported() -> bool {
+    SUPPORT.is_ok()
+}
@@ -0,0 +1,448 @@
+//! Pull-based streaming for hosts that poll instead of accepting callbacks
+//!
+//! `generate_async` runs generation on a background thread, buffering tokens
+//! in bounded per-consumer ring buffers that `poll` drains. Additional
+//! consumers can `subscribe` to the same request so one generation fans out
+//! to several readers; once every consumer has unsubscribed, the generation
+//! is cancelled.
+//!
+//! The same requests double as jobs: `job_status` reports a request's state
+//! and progress without draining any consumer, and `job_result` returns its
+//! final result. Unloading the model a job runs on cancels it and fails it
+//! with `Model not loaded`.
+
+use crate::inference::EventSink;
+use crate::{cancel, default_model_path, drain, generate_text_streaming, model_key, GenerateArgs};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Tokens buffered per consumer before its oldest are dropped
+const STREAM_BUFFER_CAPACITY: usize = 4096;
+
+/// How long a finished request stays pollable
+const FINISHED_STREAM_TTL: Duration = Duration::from_secs(300);
+
+/// How often an attached callback consumer checks for new tokens
+const ATTACH_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Consumer created by `generate_async`, used when `poll` gets no `consumer_id`
+pub const PRIMARY_CONSUMER: &str = "primary";
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_CONSUMER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Active and recently finished requests (request_id -> stream)
+static STREAMS: Mutex<Option<HashMap<String, Arc<Mutex<PolledStream>>>>> = Mutex::new(None);
+
+#[derive(Default)]
+struct PolledStream {
+    /// Readers of this request (consumer_id -> pending tokens)
+    consumers: HashMap<String, Consumer>,
+    /// Final generation result, set once the background thread is done
+    result: Option<Result<Value, String>>,
+    finished_at: Option<Instant>,
+    /// Model key the generation runs on
+    model: String,
+    /// `request_id` the generation is cancellable by (the caller's own, if given)
+    cancel_id: String,
+    /// Output chunks streamed so far; a chunk may hold several tokens when
+    /// text is held back for stop and ban matching
+    chunks: usize,
+    /// Why the job was failed from outside (its model was unloaded)
+    failure: Option<String>,
+}
+
+#[derive(Default)]
+struct Consumer {
+    buffer: VecDeque<String>,
+    /// Tokens dropped because the buffer was full
+    dropped: usize,
+}
+
+impl PolledStream {
+    /// Buffer `token` for every consumer; `false` once nobody is listening
+    fn push(&mut self, token: &str) -> bool {
+        self.chunks += 1;
+        for consumer in self.consumers.values_mut() {
+            if consumer.buffer.len() == STREAM_BUFFER_CAPACITY {
+                consumer.buffer.pop_front();
+                consumer.dropped += 1;
+            }
+            consumer.buffer.push_back(token.to_string());
+        }
+        !self.consumers.is_empty()
+    }
+}
+
+/// Start a generation in the background and return its request id
+///
+/// Without its own `request_id`, the generation can be cancelled by the id
+/// returned here.
+pub fn generate_async(mut args: GenerateArgs) -> Result<String, String> {
+    let in_flight = drain::InFlightGuard::acquire()?;
+
+    let request_id = format!("req-{}", NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed));
+    // Registered before the thread starts so an immediate cancel isn't missed
+    let cancel_id = args.request_id.get_or_insert(request_id.clone()).clone();
+    let cancellable = cancel::register(&cancel_id)?;
+    let mut stream = PolledStream {
+        cancel_id,
+        model: match args.model_path.as_str() {
+            "" => default_model_path()
+                .map(|path| model_key(&path))
+                .unwrap_or_default(),
+            path => model_key(path),
+        },
+        ..Default::default()
+    };
+    stream
+        .consumers
+        .insert(PRIMARY_CONSUMER.to_string(), Consumer::default());
+    let stream = Arc::new(Mutex::new(stream));
+
+    with_streams(|streams| {
+        streams.insert(request_id.clone(), stream.clone());
+        Ok(())
+    })?;
+
+    std::thread::spawn(move || {
+        let _in_flight = in_flight;
+        let _cancellable = cancellable;
+
+        let result = generate_text_streaming(&args, &mut |token| {
+            stream.lock().map(|mut s| s.push(token)).unwrap_or(false)
+        })
+        .and_then(|output| serde_json::from_str(&output).map_err(|e| e.to_string()));
+
+        if let Ok(mut stream) = stream.lock() {
+            let result = match stream.failure.take() {
+                Some(failure) => Err(failure),
+                None => result,
+            };
+            stream.result = Some(result);
+            stream.finished_at = Some(Instant::now());
+        }
+    });
+
+    Ok(request_id)
+}
+
+/// Return tokens produced since this consumer's last poll plus a `done` flag
+pub fn poll(request_id: &str, consumer_id: Option<&str>) -> Result<String, String> {
+    let update = take_pending(request_id, consumer_id.unwrap_or(PRIMARY_CONSUMER))?;
+    Ok(serde_json::to_string(&update).unwrap_or_default())
+}
+
+/// Register another consumer of `request_id` and return its consumer id.
+///
+/// New consumers only see tokens produced after they subscribe.
+pub fn subscribe(request_id: &str) -> Result<String, String> {
+    let consumer_id = format!("sub-{}", NEXT_CONSUMER_ID.fetch_add(1, Ordering::Relaxed));
+    with_stream(request_id, |stream| {
+        stream
+            .consumers
+            .insert(consumer_id.clone(), Consumer::default());
+        Ok(())
+    })?;
+    Ok(consumer_id)
+}
+
+/// Detach a consumer; the generation is cancelled when none remain
+pub fn unsubscribe(request_id: &str, consumer_id: Option<&str>) -> Result<String, String> {
+    let consumer_id = consumer_id.unwrap_or(PRIMARY_CONSUMER);
+    let remaining = with_stream(request_id, |stream| {
+        stream
+            .consumers
+            .remove(consumer_id)
+            .ok_or_else(|| unknown_consumer(request_id, consumer_id))?;
+        // Stop now rather than at the next token, which may be a while off
+        if stream.consumers.is_empty() && stream.result.is_none() {
+            cancel::cancel(&stream.cancel_id);
+        }
+        Ok(stream.consumers.len())
+    })?;
+
+    let result = json!({
+        "request_id": request_id,
+        "consumer_id": consumer_id,
+        "consumers_remaining": remaining,
+    });
+    Ok(serde_json::to_string(&result).unwrap_or_default())
+}
+
+/// Subscribe to `request_id` and forward its tokens to a callback.
+///
+/// Emits `{"event":"token","token"}` per token (plus `{"event":"dropped","count"}`
+/// when the buffer overflowed) and returns the final poll update. Returning
+/// `false` from `on_event` detaches this consumer.
+pub fn attach(request_id: &str, on_event: EventSink) -> Result<String, String> {
+    let consumer_id = subscribe(request_id)?;
+
+    loop {
+        let mut update = take_pending(request_id, &consumer_id)?;
+
+        let dropped = update["dropped"].as_u64().unwrap_or(0);
+        let mut events: Vec<Value> = update["tokens"]
+            .as_array()
+            .map(|tokens| {
+                tokens
+                    .iter()
+                    .map(|token| json!({ "event": "token", "token": token }))
+                    .collect()
+            })
+            .unwrap_or_default();
+        if dropped > 0 {
+            events.insert(0, json!({ "event": "dropped", "count": dropped }));
+        }
+
+        for event in &events {
+            if !on_event(&event.to_string()) {
+                return unsubscribe(request_id, Some(&consumer_id));
+            }
+        }
+
+        if update["done"].as_bool().unwrap_or(false) {
+            update["event"] = json!("done");
+            return Ok(serde_json::to_string(&update).unwrap_or_default());
+        }
+        if events.is_empty() {
+            std::thread::sleep(ATTACH_POLL_INTERVAL);
+        }
+    }
+}
+
+/// State and progress of a request: `running`, `done`, `error` or `cancelled`
+pub fn job_status(job_id: &str) -> Result<String, String> {
+    with_stream(job_id, |stream| {
+        let mut status = json!({
+            "job_id": job_id,
+            "state": job_state(stream),
+            "model_path": stream.model,
+            "chunks": stream.chunks,
+        });
+        if let Some(Err(e)) = &stream.result {
+            status["error"] = json!(e);
+        }
+        Ok(serde_json::to_string(&status).unwrap_or_default())
+    })
+}
+
+/// Final result of a finished request (partial for a cancelled one)
+pub fn job_result(job_id: &str) -> Result<String, String> {
+    with_stream(job_id, |stream| match &stream.result {
+        Some(Ok(output)) => Ok(output.to_string()),
+        Some(Err(e)) => Err(e.clone()),
+        None => Err(format!(
+            "Invalid arguments: job {} is still running ({} chunks so far)",
+            job_id, stream.chunks
+        )),
+    })
+}
+
+fn job_state(stream: &PolledStream) -> &'static str {
+    match &stream.result {
+        None => "running",
+        Some(Err(_)) => "error",
+        Some(Ok(output)) if output["stop_reason"] == "cancelled" => "cancelled",
+        Some(Ok(_)) => "done",
+    }
+}
+
+/// Cancel the running jobs on `model`, failing them with `why`
+pub fn fail_jobs(model: &str, why: &str) {
+    let Ok(running) = with_streams(|streams| Ok(streams.values().cloned().collect::<Vec<_>>()))
+    else {
+        return;
+    };
+    for stream in running {
+        let Ok(mut stream) = stream.lock() else {
+            continue;
+        };
+        if stream.model == model && stream.result.is_none() {
+            stream.failure = Some(format!("Model not loaded: {} {}", model, why));
+            cancel::cancel(&stream.cancel_id);
+        }
+    }
+}
+
+/// Cancel a job by its job id, which differs from the id it is
+/// cancellable by when the caller gave its own `request_id`
+pub fn cancel_job(job_id: &str) -> Value {
+    match with_stream(job_id, |stream| Ok(stream.cancel_id.clone())) {
+        Ok(cancel_id) => {
+            let mut status = cancel::cancel(&cancel_id);
+            status["request_id"] = json!(job_id);
+            status
+        }
+        Err(_) => cancel::cancel(job_id),
+    }
+}
+
+/// Drain a consumer's buffer, forgetting it once the final result is
+/// delivered. The request itself stays for `job_status` and `job_result`
+/// until `FINISHED_STREAM_TTL` expires it.
+fn take_pending(request_id: &str, consumer_id: &str) -> Result<Value, String> {
+    with_streams(|streams| {
+        let stream = streams
+            .get(request_id)
+            .cloned()
+            .ok_or_else(|| format!("Unknown or expired request_id: {}", request_id))?;
+        let mut stream = stream
+            .lock()
+            .map_err(|e| format!("Failed to lock stream: {}", e))?;
+
+        let consumer = stream
+            .consumers
+            .get_mut(consumer_id)
+            .ok_or_else(|| unknown_consumer(request_id, consumer_id))?;
+        let tokens: Vec<String> = consumer.buffer.drain(..).collect();
+        let dropped = std::mem::take(&mut consumer.dropped);
+
+        let mut update = json!({
+            "request_id": request_id,
+            "consumer_id": consumer_id,
+            "tokens": tokens,
+            "dropped": dropped,
+            "done": stream.result.is_some(),
+        });
+
+        match &stream.result {
+            Some(Ok(output)) => update["result"] = output.clone(),
+            Some(Err(e)) => update["error"] = json!(e),
+            None => {}
+        }
+
+        // Everything has been delivered to this consumer; forget it
+        if stream.result.is_some() {
+            stream.consumers.remove(consumer_id);
+        }
+
+        Ok(update)
+    })
+}
+
+fn with_streams<T>(
+    f: impl FnOnce(&mut HashMap<String, Arc<Mutex<PolledStream>>>) -> Result<T, String>,
+) -> Result<T, String> {
+    let mut streams = STREAMS
+        .lock()
+        .map_err(|e| format!("Failed to lock streams: {}", e))?;
+    let streams_map = streams.get_or_insert_with(HashMap::new);
+    purge_expired(streams_map);
+    f(streams_map)
+}
+
+fn with_stream<T>(
+    request_id: &str,
+    f: impl FnOnce(&mut PolledStream) -> Result<T, String>,
+) -> Result<T, String> {
+    let stream = with_streams(|streams| {
+        streams
+            .get(request_id)
+            .cloned()
+            .ok_or_else(|| format!("Unknown or expired request_id: {}", request_id))
+    })?;
+    let mut stream = stream
+        .lock()
+        .map_err(|e| format!("Failed to lock stream: {}", e))?;
+    f(&mut stream)
+}
+
+fn unknown_consumer(request_id: &str, consumer_id: &str) -> String {
+    format!(
+        "Unknown consumer {} for request_id {}",
+        consumer_id, request_id
+    )
+}
+
+fn purge_expired(streams: &mut HashMap<String, Arc<Mutex<PolledStream>>>) {
+    streams.retain(|_, stream| {
+        stream
+            .lock()
+            .map(|s| {
+                s.finished_at
+                    .is_none_or(|t| t.elapsed() < FINISHED_STREAM_TTL)
+            })
+            .unwrap_or(false)
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jobs_report_state_and_fail_when_their_model_goes() {
+        let stream = Arc::new(Mutex::new(PolledStream {
+            model: "models/jobs-test".to_string(),
+            cancel_id: "jobs-test".to_string(),
+            ..Default::default()
+        }));
+        with_streams(|streams| {
+            streams.insert("jobs-test".to_string(), stream.clone());
+            Ok(())
+        })
+        .unwrap();
+        let status = |id| -> Value { serde_json::from_str(&job_status(id).unwrap()).unwrap() };
+
+        stream.lock().unwrap().push("a");
+        assert_eq!(status("jobs-test")["state"], "running");
+        assert_eq!(status("jobs-test")["chunks"], 1);
+        assert!(job_result("jobs-test")
+            .unwrap_err()
+            .contains("still running"));
+
+        fail_jobs("models/other", "was unloaded");
+        assert!(stream.lock().unwrap().failure.is_none());
+        fail_jobs("models/jobs-test", "was unloaded");
+        let failure = stream.lock().unwrap().failure.take().unwrap();
+        assert_eq!(failure, "Model not loaded: models/jobs-test was unloaded");
+
+        stream.lock().unwrap().result = Some(Err(failure));
+        assert_eq!(status("jobs-test")["state"], "error");
+        stream.lock().unwrap().result = Some(Ok(json!({ "stop_reason": "cancelled" })));
+        assert_eq!(status("jobs-test")["state"], "cancelled");
+        assert_eq!(
+            job_result("jobs-test").unwrap(),
+            r#"{"stop_reason":"cancelled"}"#
+        );
+    }
+
+    #[test]
+    fn finished_jobs_stay_queryable_after_the_last_poll() {
+        let mut stream = PolledStream {
+            model: "models/polled-test".to_string(),
+            cancel_id: "polled-test".to_string(),
+            result: Some(Ok(json!({ "text": "a", "stop_reason": "eos" }))),
+            finished_at: Some(Instant::now()),
+            ..Default::default()
+        };
+        stream
+            .consumers
+            .insert(PRIMARY_CONSUMER.to_string(), Consumer::default());
+        stream.push("a");
+        with_streams(|streams| {
+            streams.insert("polled-test".to_string(), Arc::new(Mutex::new(stream)));
+            Ok(())
+        })
+        .unwrap();
+
+        let update: Value = serde_json::from_str(&poll("polled-test", None).unwrap()).unwrap();
+        assert_eq!(update["done"], true);
+        assert_eq!(update["tokens"], json!(["a"]));
+
+        assert_eq!(
+            job_result("polled-test").unwrap(),
+            r#"{"stop_reason":"eos","text":"a"}"#
+        );
+        assert!(job_status("polled-test")
+            .unwrap()
+            .contains(r#""state":"done""#));
+        assert!(poll("polled-test", None)
+            .unwrap_err()
+            .contains("Unknown consumer"));
+    }
+}
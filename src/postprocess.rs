@@ -0,0 +1,280 @@
+//! Post-processing of generated text
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+/// Words longer than this are treated as implausible
+const MAX_PLAUSIBLE_WORD_CHARS: usize = 24;
+
+/// Best-effort gibberish check used by `retry_on_gibberish`.
+///
+/// Output is treated as gibberish when either:
+/// - more than 5% of its characters are control or U+FFFD replacement characters, or
+/// - it has at least 4 words and more than half of them are implausible: longer than
+///   24 characters, or ASCII-alphabetic words of 5+ letters with no vowel.
+///
+/// Empty output is never gibberish.
+pub fn is_gibberish(text: &str) -> bool {
+    let total_chars = text.chars().count();
+    if total_chars == 0 {
+        return false;
+    }
+
+    let garbage_chars = text
+        .chars()
+        .filter(|c| *c == char::REPLACEMENT_CHARACTER || (c.is_control() && !c.is_whitespace()))
+        .count();
+    if garbage_chars * 20 > total_chars {
+        return true;
+    }
+
+    let words: Vec<&str> = text.split_whitespace().collect();
+    if words.len() < 4 {
+        return false;
+    }
+
+    let implausible = words.iter().filter(|w| is_implausible_word(w)).count();
+    implausible * 2 > words.len()
+}
+
+fn is_implausible_word(word: &str) -> bool {
+    if word.chars().count() > MAX_PLAUSIBLE_WORD_CHARS {
+        return true;
+    }
+
+    let letters = word.trim_matches(|c: char| c.is_ascii_punctuation());
+    letters.len() >= 5
+        && letters.chars().all(|c| c.is_ascii_alphabetic())
+        && !letters
+            .chars()
+            .any(|c| matches!(c.to_ascii_lowercase(), 'a' | 'e' | 'i' | 'o' | 'u' | 'y'))
+}
+
+/// Lowercase hex SHA-256 of the UTF-8 output text, used by `include_output_hash`
+pub fn output_hash(text: &str) -> String {
+    Sha256::digest(text.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Letters examined by `dominant_script`, keeping detection cheap on long output
+const MAX_SCRIPT_SAMPLE_CHARS: usize = 4096;
+
+/// Script names accepted by `expect_script`
+pub const SCRIPTS: &[&str] = &[
+    "Latin",
+    "Cyrillic",
+    "Greek",
+    "Arabic",
+    "Hebrew",
+    "Devanagari",
+    "Thai",
+    "Hangul",
+    "Kana",
+    "Han",
+];
+
+/// Canonical name of a supported script, matched case-insensitively
+pub fn script_name(name: &str) -> Option<&'static str> {
+    SCRIPTS
+        .iter()
+        .copied()
+        .find(|s| s.eq_ignore_ascii_case(name))
+}
+
+/// Script of most letters among the first 4096 alphabetic characters.
+///
+/// Returns `None` when the output has no letters from a known script.
+pub fn dominant_script(text: &str) -> Option<&'static str> {
+    let mut counts = [0usize; SCRIPTS.len()];
+    for c in text
+        .chars()
+        .filter(|c| c.is_alphabetic())
+        .take(MAX_SCRIPT_SAMPLE_CHARS)
+    {
+        if let Some(index) = script_of(c) {
+            counts[index] += 1;
+        }
+    }
+    let (index, count) = counts
+        .iter()
+        .enumerate()
+        .max_by_key(|(index, count)| (**count, std::cmp::Reverse(*index)))?;
+    (*count > 0).then(|| SCRIPTS[index])
+}
+
+/// Index into `SCRIPTS` by Unicode block
+fn script_of(c: char) -> Option<usize> {
+    let index = match c as u32 {
+        0x0041..=0x024F | 0x1E00..=0x1EFF => 0,
+        0x0400..=0x052F => 1,
+        0x0370..=0x03FF | 0x1F00..=0x1FFF => 2,
+        0x0600..=0x06FF | 0x0750..=0x077F => 3,
+        0x0590..=0x05FF => 4,
+        0x0900..=0x097F => 5,
+        0x0E00..=0x0E7F => 6,
+        0x1100..=0x11FF | 0x3130..=0x318F | 0xAC00..=0xD7AF => 7,
+        0x3040..=0x30FF => 8,
+        0x3400..=0x4DBF | 0x4E00..=0x9FFF => 9,
+        _ => return None,
+    };
+    Some(index)
+}
+
+/// A prose or fenced-code segment of generated markdown
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum MarkdownBlock {
+    Text {
+        content: String,
+    },
+    Code {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        lang: Option<String>,
+        content: String,
+    },
+}
+
+/// Split output into text and fenced code blocks (``` or ~~~).
+///
+/// An unclosed fence is kept as text, fence line included. Whitespace-only
+/// text between blocks is dropped.
+pub fn markdown_blocks(text: &str) -> Vec<MarkdownBlock> {
+    let mut blocks = Vec::new();
+    let mut prose = String::new();
+    let mut lines = text.split_inclusive('\n');
+
+    while let Some(line) = lines.next() {
+        let Some((fence, lang)) = opening_fence(line) else {
+            prose.push_str(line);
+            continue;
+        };
+
+        let mut code = String::new();
+        let mut closed = false;
+        for code_line in lines.by_ref() {
+            if is_closing_fence(code_line, &fence) {
+                closed = true;
+                break;
+            }
+            code.push_str(code_line);
+        }
+
+        if !closed {
+            prose.push_str(line);
+            prose.push_str(&code);
+            break;
+        }
+
+        push_text(&mut blocks, &mut prose);
+        blocks.push(MarkdownBlock::Code {
+            lang,
+            content: code.strip_suffix('\n').unwrap_or(&code).to_string(),
+        });
+    }
+
+    push_text(&mut blocks, &mut prose);
+    blocks
+}
+
+fn push_text(blocks: &mut Vec<MarkdownBlock>, prose: &mut String) {
+    if !prose.trim().is_empty() {
+        blocks.push(MarkdownBlock::Text {
+            content: prose.trim_matches('\n').to_string(),
+        });
+    }
+    prose.clear();
+}
+
+/// Fence marker and language of an opening code fence line
+fn opening_fence(line: &str) -> Option<(String, Option<String>)> {
+    let trimmed = line.trim_end();
+    let body = strip_fence_indent(trimmed)?;
+    let marker = body.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let fence_len = body.chars().take_while(|c| *c == marker).count();
+    if fence_len < 3 {
+        return None;
+    }
+
+    let info = body[fence_len..].trim();
+    if marker == '`' && info.contains('`') {
+        return None;
+    }
+    let lang = info.split_whitespace().next().map(str::to_string);
+    Some((body[..fence_len].to_string(), lang))
+}
+
+fn is_closing_fence(line: &str, fence: &str) -> bool {
+    let Some(body) = strip_fence_indent(line.trim_end()) else {
+        return false;
+    };
+    let marker = fence.chars().next().unwrap_or('`');
+    body.len() >= fence.len() && body.chars().all(|c| c == marker)
+}
+
+/// Fences may be indented by up to 3 spaces
+fn strip_fence_indent(line: &str) -> Option<&str> {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    (indent <= 3).then(|| &line[indent..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(content: &str) -> MarkdownBlock {
+        MarkdownBlock::Text {
+            content: content.to_string(),
+        }
+    }
+
+    #[test]
+    fn output_hash_is_sha256_hex() {
+        assert_eq!(
+            output_hash(""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn dominant_script_counts_letters_only() {
+        assert_eq!(dominant_script("Привет, world! 123"), Some("Cyrillic"));
+        assert_eq!(dominant_script("Hello, мир"), Some("Latin"));
+        assert_eq!(dominant_script("123 !?"), None);
+    }
+
+    #[test]
+    fn markdown_blocks_splits_prose_and_code() {
+        let blocks = markdown_blocks("Intro:\n```rust\nfn main() {}\n```\nDone.");
+        assert_eq!(
+            blocks,
+            vec![
+                text("Intro:"),
+                MarkdownBlock::Code {
+                    lang: Some("rust".to_string()),
+                    content: "fn main() {}".to_string(),
+                },
+                text("Done."),
+            ]
+        );
+    }
+
+    #[test]
+    fn markdown_blocks_keeps_unclosed_fence_as_text() {
+        let blocks = markdown_blocks("Intro\n```python\nprint(1)\n");
+        assert_eq!(blocks, vec![text("Intro\n```python\nprint(1)")]);
+    }
+
+    #[test]
+    fn markdown_blocks_requires_matching_closing_fence() {
+        let blocks = markdown_blocks("~~~~\n```\nstill code\n~~~~");
+        assert_eq!(
+            blocks,
+            vec![MarkdownBlock::Code {
+                lang: None,
+                content: "```\nstill code".to_string(),
+            }]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Per-model request rate limiting (`rate_limit` config)
+//!
+//! Each model key has a token bucket holding up to `burst` requests, refilled
+//! at `requests_per_minute`. A generation request takes one token; with the
+//! bucket empty it is refused as `RateLimited` with the wait until the next
+//! token. `n` samples are one request. Off unless configured.
+
+use crate::error::PluginLlmError;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Allowed request rate per model
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    pub requests_per_minute: u32,
+    /// Requests allowed back to back after a quiet period
+    /// (default: `requests_per_minute`)
+    #[serde(default)]
+    pub burst: Option<u32>,
+}
+
+impl RateLimit {
+    /// Reason the limit is unusable, if any
+    pub fn validate(&self) -> Result<(), String> {
+        if self.requests_per_minute == 0 {
+            return Err("requests_per_minute must be at least 1".to_string());
+        }
+        if self.burst == Some(0) {
+            return Err("burst must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    fn capacity(&self) -> f64 {
+        f64::from(self.burst.unwrap_or(self.requests_per_minute))
+    }
+
+    fn tokens_per_ms(&self) -> f64 {
+        f64::from(self.requests_per_minute) / 60_000.0
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// model key -> bucket
+static BUCKETS: Lazy<Mutex<HashMap<String, Bucket>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Take one request from `model`'s bucket, or fail with the wait in ms
+pub fn acquire(model: &str, limit: &RateLimit) -> Result<(), PluginLlmError> {
+    let mut buckets = BUCKETS.lock().unwrap_or_else(|e| e.into_inner());
+    take(&mut buckets, model, limit, Instant::now())
+}
+
+/// Forget every bucket (limits may have changed)
+pub fn reset() {
+    BUCKETS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+fn take(
+    buckets: &mut HashMap<String, Bucket>,
+    model: &str,
+    limit: &RateLimit,
+    now: Instant,
+) -> Result<(), PluginLlmError> {
+    let bucket = buckets.entry(model.to_string()).or_insert(Bucket {
+        tokens: limit.capacity(),
+        updated: now,
+    });
+    let elapsed_ms = now.saturating_duration_since(bucket.updated).as_secs_f64() * 1000.0;
+    bucket.tokens = (bucket.tokens + elapsed_ms * limit.tokens_per_ms()).min(limit.capacity());
+    bucket.updated = now;
+
+    if bucket.tokens >= 1.0 {
+        bucket.tokens -= 1.0;
+        return Ok(());
+    }
+    Err(PluginLlmError::RateLimited {
+        model: model.to_string(),
+        requests_per_minute: limit.requests_per_minute,
+        retry_after_ms: ((1.0 - bucket.tokens) / limit.tokens_per_ms()).ceil() as u64,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn bucket_allows_a_burst_then_refills_at_the_rate() {
+        let limit = RateLimit {
+            requests_per_minute: 60,
+            burst: Some(2),
+        };
+        let mut buckets = HashMap::new();
+        let start = Instant::now();
+
+        assert!(take(&mut buckets, "a", &limit, start).is_ok());
+        assert!(take(&mut buckets, "a", &limit, start).is_ok());
+        let e = take(&mut buckets, "a", &limit, start).unwrap_err();
+        assert_eq!(e.code(), "rate_limited");
+        assert_eq!(
+            e,
+            PluginLlmError::RateLimited {
+                model: "a".to_string(),
+                requests_per_minute: 60,
+                retry_after_ms: 1000,
+            }
+        );
+        // Other models have buckets of their own
+        assert!(take(&mut buckets, "b", &limit, start).is_ok());
+
+        let later = start + Duration::from_millis(400);
+        let e = take(&mut buckets, "a", &limit, later).unwrap_err();
+        assert!(e.to_string().ends_with("(retry_after_ms: 600)"), "{}", e);
+        assert!(take(&mut buckets, "a", &limit, start + Duration::from_secs(1)).is_ok());
+    }
+}
@@ -0,0 +1,845 @@
+//! Loaded-model registry with per-model locking and LRU eviction
+//!
+//! The map lock is only held long enough to look up or insert an entry;
+//! each model has its own lock, so generating on one model never blocks
+//! another. At most `max_models` stay resident; loading past the limit
+//! evicts the least recently used.
+//!
+//! Loads of one key are single-flight: while a load runs, other callers for
+//! that key wait for it and share its entry or error instead of opening a
+//! second copy. Nothing is cached once it finishes, so a failed load can be
+//! retried by the next caller.
+//!
+//! Aliases map user-chosen names to model keys. They survive eviction (the
+//! model is reloaded from its path on next use) and go away on unload.
+//!
+//! Generations are admitted per model: at most a configured number run at
+//! once, a bounded number wait for a slot, and the rest are turned away as
+//! `ServiceBusy` instead of piling up behind the model lock.
+//!
+//! A panic while a lock is held poisons it. Rather than failing every later
+//! call, locks are recovered with a warning: the map is only mutated by
+//! single inserts and removes, and the engine builds fresh decode state per
+//! request, so nothing is left half-updated.
+
+use crate::error::PluginLlmError;
+use crate::sampling::SamplingParams;
+use crate::stop_defaults::StopDefaults;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, TryLockError};
+use std::time::{Duration, Instant, SystemTime};
+
+/// A loaded model and how it was loaded
+pub struct Entry<T> {
+    model: Mutex<T>,
+    /// Wall-clock time the loader took
+    pub load_ms: u64,
+    /// Canonical on-disk path backing the model key
+    pub resolved_path: PathBuf,
+    /// When the load finished
+    pub loaded_at: SystemTime,
+    last_access: Mutex<Instant>,
+    /// Sampling defaults requests to this model layer over
+    sampling: Mutex<SamplingParams>,
+    /// Stop sequences and EOS handling requests to this model layer over
+    stops: Mutex<StopDefaults>,
+    generations: AtomicU64,
+    slots: Mutex<Slots>,
+    slot_freed: Condvar,
+}
+
+/// Generations running on and waiting for one model
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Slots {
+    pub running: usize,
+    pub queued: usize,
+}
+
+/// A running generation's slot, given back on drop
+pub struct SlotGuard<'a, T> {
+    entry: &'a Entry<T>,
+}
+
+impl<T> Drop for SlotGuard<'_, T> {
+    fn drop(&mut self) {
+        lock_recovering(&self.entry.slots, "model slots").running -= 1;
+        self.entry.slot_freed.notify_one();
+    }
+}
+
+impl<T> Entry<T> {
+    /// Lock this model for exclusive use
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        lock_recovering(&self.model, "model")
+    }
+
+    /// Lock this model only if nobody is using it
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        try_lock_recovering(&self.model, "model")
+    }
+
+    /// Mark this model as just used
+    pub fn touch(&self) {
+        *lock_recovering(&self.last_access, "model access time") = Instant::now();
+    }
+
+    fn last_access(&self) -> Instant {
+        *lock_recovering(&self.last_access, "model access time")
+    }
+
+    /// Wall-clock time of the last use
+    pub fn last_access_at(&self) -> SystemTime {
+        SystemTime::now() - self.last_access().elapsed()
+    }
+
+    /// Count a generation served by this model
+    pub fn record_generation(&self) {
+        self.generations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Sampling parameters requests to this model fall back to
+    pub fn sampling_defaults(&self) -> SamplingParams {
+        lock_recovering(&self.sampling, "model sampling").clone()
+    }
+
+    pub fn set_sampling_defaults(&self, defaults: SamplingParams) {
+        *lock_recovering(&self.sampling, "model sampling") = defaults;
+    }
+
+    /// Stop sequences and `ignore_eos` requests to this model fall back to
+    pub fn stop_defaults(&self) -> StopDefaults {
+        lock_recovering(&self.stops, "model stops").clone()
+    }
+
+    pub fn set_stop_defaults(&self, defaults: StopDefaults) {
+        *lock_recovering(&self.stops, "model stops") = defaults;
+    }
+
+    /// Generations served since the model was loaded
+    pub fn generations(&self) -> u64 {
+        self.generations.load(Ordering::Relaxed)
+    }
+
+    /// Take one of `limit` generation slots, waiting as one of at most
+    /// `max_queued` if none is free. Fails with `ServiceBusy` when the
+    /// queue is full too.
+    pub fn acquire_slot(
+        &self,
+        limit: usize,
+        max_queued: usize,
+    ) -> Result<SlotGuard<'_, T>, PluginLlmError> {
+        let mut slots = lock_recovering(&self.slots, "model slots");
+        // Queued requests go first, so newcomers can't overtake them
+        if slots.running >= limit || slots.queued > 0 {
+            if slots.queued >= max_queued {
+                return Err(PluginLlmError::ServiceBusy(format!(
+                    "{} generations running and {} queued (limits {} and {})",
+                    slots.running, slots.queued, limit, max_queued
+                )));
+            }
+            slots.queued += 1;
+            while slots.running >= limit {
+                slots = self
+                    .slot_freed
+                    .wait(slots)
+                    .unwrap_or_else(|e| e.into_inner());
+            }
+            slots.queued -= 1;
+        }
+        slots.running += 1;
+        Ok(SlotGuard { entry: self })
+    }
+
+    /// Generations currently running and queued
+    pub fn slots(&self) -> Slots {
+        *lock_recovering(&self.slots, "model slots")
+    }
+
+    /// No generation is running on or waiting for this model, and its lock
+    /// is free
+    fn is_idle(&self) -> bool {
+        let slots = self.slots();
+        slots.running == 0 && slots.queued == 0 && self.try_lock().is_some()
+    }
+}
+
+/// Point-in-time copy of the loaded models (key, entry)
+pub type Snapshot<T> = Vec<(String, Arc<Entry<T>>)>;
+
+/// Outcome of a load, handed to every caller that waited on it
+type LoadResult<T> = Result<Arc<Entry<T>>, PluginLlmError>;
+
+/// A load in progress; `result` is set once it finishes
+struct PendingLoad<T> {
+    result: Mutex<Option<LoadResult<T>>>,
+    done: Condvar,
+}
+
+/// Held by the caller running a load, publishing its outcome when dropped
+/// (a panicking `open` included) so waiters never hang
+struct LoadGuard<'a, T> {
+    loading: &'a Mutex<BTreeMap<String, Arc<PendingLoad<T>>>>,
+    key: &'a str,
+    pending: Arc<PendingLoad<T>>,
+}
+
+impl<T> LoadGuard<'_, T> {
+    fn finish(self, result: LoadResult<T>) -> LoadResult<T> {
+        *lock_recovering(&self.pending.result, "pending load") = Some(result.clone());
+        result
+    }
+}
+
+impl<T> Drop for LoadGuard<'_, T> {
+    fn drop(&mut self) {
+        lock_recovering(self.loading, "loading").remove(self.key);
+        lock_recovering(&self.pending.result, "pending load").get_or_insert_with(|| {
+            Err(PluginLlmError::ModelLoadFailed(format!(
+                "loading {} panicked",
+                self.key
+            )))
+        });
+        self.pending.done.notify_all();
+    }
+}
+
+/// Models keyed by normalized path; `None` until `init`
+pub struct Registry<T> {
+    models: Mutex<Option<HashMap<String, Arc<Entry<T>>>>>,
+    max_models: AtomicUsize,
+    /// alias -> model key
+    aliases: Mutex<BTreeMap<String, String>>,
+    /// key -> load in progress
+    loading: Mutex<BTreeMap<String, Arc<PendingLoad<T>>>>,
+    /// Told about models a load evicts, after the map lock is released
+    on_evict: Option<fn(&str)>,
+}
+
+impl<T> Registry<T> {
+    pub const fn new(max_models: usize) -> Self {
+        Self {
+            models: Mutex::new(None),
+            max_models: AtomicUsize::new(max_models),
+            aliases: Mutex::new(BTreeMap::new()),
+            loading: Mutex::new(BTreeMap::new()),
+            on_evict: None,
+        }
+    }
+
+    /// Call `hook` with each model a load evicts past the limit. Other
+    /// evictions return their keys to the caller instead.
+    pub const fn on_evict(mut self, hook: fn(&str)) -> Self {
+        self.on_evict = Some(hook);
+        self
+    }
+
+    pub fn max_models(&self) -> usize {
+        self.max_models.load(Ordering::SeqCst)
+    }
+
+    /// Change the resident-model limit, evicting LRU models now if over it.
+    /// Returns the evicted keys.
+    pub fn set_max_models(&self, max_models: usize) -> Result<Vec<String>, PluginLlmError> {
+        if max_models == 0 {
+            return Err(PluginLlmError::InvalidArgs(
+                "max models must be at least 1".to_string(),
+            ));
+        }
+        self.max_models.store(max_models, Ordering::SeqCst);
+        self.with_map(|models| Ok(evict_lru(models, max_models, None)))
+    }
+
+    /// Start with an empty registry
+    pub fn init(&self) {
+        *lock_recovering(&self.models, "models") = Some(HashMap::new());
+    }
+
+    /// Drop every model and refuse further loads until `init`
+    pub fn clear(&self) {
+        *lock_recovering(&self.models, "models") = None;
+        lock_recovering(&self.aliases, "aliases").clear();
+    }
+
+    fn with_map<R>(
+        &self,
+        f: impl FnOnce(&mut HashMap<String, Arc<Entry<T>>>) -> Result<R, PluginLlmError>,
+    ) -> Result<R, PluginLlmError> {
+        let mut models = lock_recovering(&self.models, "models");
+        f(models.as_mut().ok_or(PluginLlmError::NotInitialized)?)
+    }
+
+    /// Entry for `key`, loading it with `open` if needed, and mark it used.
+    ///
+    /// `open` runs without the map lock held. Callers arriving while `key`
+    /// is being loaded wait and get that load's result; `open` isn't called.
+    pub fn load(
+        &self,
+        key: &str,
+        open: impl FnOnce() -> Result<T, PluginLlmError>,
+    ) -> Result<Arc<Entry<T>>, PluginLlmError> {
+        if let Some(entry) = self.with_map(|models| Ok(models.get(key).cloned()))? {
+            entry.touch();
+            return Ok(entry);
+        }
+
+        let pending = {
+            let mut loading = lock_recovering(&self.loading, "loading");
+            match loading.get(key) {
+                Some(pending) => Err(pending.clone()),
+                None => {
+                    let pending = Arc::new(PendingLoad {
+                        result: Mutex::new(None),
+                        done: Condvar::new(),
+                    });
+                    loading.insert(key.to_string(), pending.clone());
+                    Ok(pending)
+                }
+            }
+        };
+        let guard = match pending {
+            Ok(pending) => LoadGuard {
+                loading: &self.loading,
+                key,
+                pending,
+            },
+            Err(pending) => {
+                let mut result = lock_recovering(&pending.result, "pending load");
+                while result.is_none() {
+                    result = pending.done.wait(result).unwrap_or_else(|e| e.into_inner());
+                }
+                let result = result.clone().expect("set before notifying");
+                if let Ok(entry) = &result {
+                    entry.touch();
+                }
+                return result;
+            }
+        };
+
+        // A load that finished between the lookup above and registering
+        // this one already inserted its entry
+        let result = match self.with_map(|models| Ok(models.get(key).cloned())) {
+            Ok(Some(entry)) => {
+                entry.touch();
+                Ok(entry)
+            }
+            Ok(None) => self.open_entry(key, open),
+            Err(e) => Err(e),
+        };
+        guard.finish(result)
+    }
+
+    fn open_entry(
+        &self,
+        key: &str,
+        open: impl FnOnce() -> Result<T, PluginLlmError>,
+    ) -> LoadResult<T> {
+        let start = Instant::now();
+        let model = open()?;
+        let entry = Arc::new(Entry {
+            model: Mutex::new(model),
+            load_ms: start.elapsed().as_millis() as u64,
+            resolved_path: std::fs::canonicalize(key).unwrap_or_else(|_| PathBuf::from(key)),
+            loaded_at: SystemTime::now(),
+            last_access: Mutex::new(Instant::now()),
+            sampling: Mutex::new(SamplingParams::default()),
+            stops: Mutex::new(StopDefaults::default()),
+            generations: AtomicU64::new(0),
+            slots: Mutex::new(Slots::default()),
+            slot_freed: Condvar::new(),
+        });
+
+        let max_models = self.max_models();
+        let (entry, evicted) = self.with_map(|models| {
+            let entry = models.entry(key.to_string()).or_insert(entry).clone();
+            Ok((entry, evict_lru(models, max_models, Some(key))))
+        })?;
+        for key in &evicted {
+            match self.on_evict {
+                Some(hook) => hook(key),
+                None => tracing::info!("Evicted least recently used model: {}", key),
+            }
+        }
+        Ok(entry)
+    }
+
+    /// Entry for `key` if it is loaded; doesn't load it or count as use
+    pub fn get(&self, key: &str) -> Option<Arc<Entry<T>>> {
+        self.with_map(|models| Ok(models.get(key).cloned()))
+            .ok()
+            .flatten()
+    }
+
+    /// Forget a model; it is freed once in-flight users release it
+    pub fn remove(&self, key: &str) -> Result<(), PluginLlmError> {
+        self.with_map(|models| {
+            models
+                .remove(key)
+                .map(|_| ())
+                .ok_or_else(|| PluginLlmError::ModelNotLoaded(key.to_string()))
+        })?;
+        lock_recovering(&self.aliases, "aliases").retain(|_, target| target != key);
+        Ok(())
+    }
+
+    /// Forget every model and return their keys
+    pub fn remove_all(&self) -> Result<Vec<String>, PluginLlmError> {
+        let keys = self.with_map(|models| Ok(models.drain().map(|(key, _)| key).collect()))?;
+        lock_recovering(&self.aliases, "aliases").clear();
+        Ok(keys)
+    }
+
+    /// Point `alias` at `key`. An alias can't be re-pointed at another model
+    /// without unloading that model first.
+    pub fn set_alias(&self, alias: &str, key: &str) -> Result<(), PluginLlmError> {
+        let mut aliases = lock_recovering(&self.aliases, "aliases");
+        match aliases.get(alias) {
+            Some(target) if target != key => Err(PluginLlmError::InvalidArgs(format!(
+                "alias {} already refers to {}",
+                alias, target
+            ))),
+            _ => {
+                aliases.insert(alias.to_string(), key.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    /// Model key that `alias` refers to
+    pub fn resolve_alias(&self, alias: &str) -> Option<String> {
+        lock_recovering(&self.aliases, "aliases")
+            .get(alias)
+            .cloned()
+    }
+
+    /// Aliases referring to `key`, sorted
+    pub fn aliases_of(&self, key: &str) -> Vec<String> {
+        lock_recovering(&self.aliases, "aliases")
+            .iter()
+            .filter(|(_, target)| *target == key)
+            .map(|(alias, _)| alias.clone())
+            .collect()
+    }
+
+    /// Evict least recently used models that are idle as `evict_if_idle`
+    /// requires until at most `keep` remain. Returns the evicted keys and
+    /// the busy ones kept in their place; fails with `ServiceBusy` rather
+    /// than wait on the map.
+    pub fn shrink_idle(&self, keep: usize) -> Result<(Vec<String>, Vec<String>), PluginLlmError> {
+        let mut models = try_lock_recovering(&self.models, "models")
+            .ok_or_else(|| PluginLlmError::ServiceBusy("the model map is locked".to_string()))?;
+        let models = models.as_mut().ok_or(PluginLlmError::NotInitialized)?;
+
+        let mut by_age: Vec<(String, Instant)> = models
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.last_access()))
+            .collect();
+        by_age.sort_by_key(|(_, last_access)| *last_access);
+
+        let mut evicted = Vec::new();
+        let mut skipped = Vec::new();
+        for (key, _) in by_age {
+            if models.len() <= keep {
+                break;
+            }
+            if models[&key].is_idle() {
+                models.remove(&key);
+                evicted.push(key);
+            } else {
+                skipped.push(key);
+            }
+        }
+        Ok((evicted, skipped))
+    }
+
+    /// Evict `key` unless a generation is running on or waiting for it, or
+    /// its lock is held. Returns whether it went; never waits on the map.
+    pub fn evict_if_idle(&self, key: &str) -> bool {
+        let Some(mut models) = try_lock_recovering(&self.models, "models") else {
+            return false;
+        };
+        let Some(models) = models.as_mut() else {
+            return false;
+        };
+        let idle = models.get(key).is_some_and(|entry| entry.is_idle());
+        if idle {
+            models.remove(key);
+        }
+        idle
+    }
+
+    /// Like `remove`, but only if `key` is idle as `evict_if_idle` requires
+    pub fn remove_if_idle(&self, key: &str) -> bool {
+        let removed = self.evict_if_idle(key);
+        if removed {
+            lock_recovering(&self.aliases, "aliases").retain(|_, target| target != key);
+        }
+        removed
+    }
+
+    /// Evict models idle as `evict_if_idle` requires that were last used more
+    /// than `ttl` ago. Returns the evicted keys; never waits on the map.
+    pub fn evict_idle(&self, ttl: Duration) -> Vec<String> {
+        let Some(mut models) = try_lock_recovering(&self.models, "models") else {
+            return Vec::new();
+        };
+        let Some(models) = models.as_mut() else {
+            return Vec::new();
+        };
+
+        let expired: Vec<String> = models
+            .iter()
+            .filter(|(_, entry)| entry.last_access().elapsed() > ttl && entry.is_idle())
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &expired {
+            models.remove(key);
+        }
+        expired
+    }
+
+    /// Keys of all loaded models
+    pub fn keys(&self) -> Vec<String> {
+        self.with_map(|models| Ok(models.keys().cloned().collect()))
+            .unwrap_or_default()
+    }
+
+    /// Snapshot of all entries
+    pub fn entries(&self) -> Result<Snapshot<T>, PluginLlmError> {
+        self.with_map(|models| {
+            Ok(models
+                .iter()
+                .map(|(key, entry)| (key.clone(), entry.clone()))
+                .collect())
+        })
+    }
+
+    /// Snapshot of all entries, or `None` if the map is busy
+    pub fn try_entries(&self) -> Option<Snapshot<T>> {
+        let models = try_lock_recovering(&self.models, "models")?;
+        Some(
+            models
+                .as_ref()?
+                .iter()
+                .map(|(key, entry)| (key.clone(), entry.clone()))
+                .collect(),
+        )
+    }
+}
+
+/// Lock `mutex`, taking over the guard if a panicking holder poisoned it
+fn lock_recovering<'a, U>(mutex: &'a Mutex<U>, what: &str) -> MutexGuard<'a, U> {
+    mutex.lock().unwrap_or_else(|poisoned| {
+        tracing::warn!("Recovering poisoned {} lock", what);
+        mutex.clear_poison();
+        poisoned.into_inner()
+    })
+}
+
+/// Like `lock_recovering`, but `None` if the lock is held
+fn try_lock_recovering<'a, U>(mutex: &'a Mutex<U>, what: &str) -> Option<MutexGuard<'a, U>> {
+    match mutex.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::WouldBlock) => None,
+        Err(TryLockError::Poisoned(poisoned)) => {
+            tracing::warn!("Recovering poisoned {} lock", what);
+            mutex.clear_poison();
+            Some(poisoned.into_inner())
+        }
+    }
+}
+
+/// Remove least recently used entries until at most `max_models` remain,
+/// never evicting `keep`. In-flight users keep an evicted model alive until
+/// they finish.
+fn evict_lru<T>(
+    models: &mut HashMap<String, Arc<Entry<T>>>,
+    max_models: usize,
+    keep: Option<&str>,
+) -> Vec<String> {
+    let mut evicted = Vec::new();
+    while models.len() > max_models {
+        let Some(lru) = models
+            .iter()
+            .filter(|(key, _)| Some(key.as_str()) != keep)
+            .min_by_key(|(_, entry)| entry.last_access())
+            .map(|(key, _)| key.clone())
+        else {
+            break;
+        };
+        models.remove(&lru);
+        evicted.push(lru);
+    }
+    evicted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn different_models_run_concurrently() {
+        let registry = Registry::<()>::new(2);
+        registry.init();
+        let a = registry.load("model-a", || Ok(())).unwrap();
+        let b = registry.load("model-b", || Ok(())).unwrap();
+
+        let busy = |entry: &Entry<()>| {
+            let _model = entry.lock();
+            let start = Instant::now();
+            std::thread::sleep(Duration::from_millis(200));
+            (start, Instant::now())
+        };
+
+        let ((start_a, end_a), (start_b, end_b)) = std::thread::scope(|s| {
+            let thread_a = s.spawn(|| busy(&a));
+            let thread_b = s.spawn(|| busy(&b));
+            (thread_a.join().unwrap(), thread_b.join().unwrap())
+        });
+
+        assert!(
+            start_a < end_b && start_b < end_a,
+            "generations did not overlap"
+        );
+    }
+
+    #[test]
+    fn loading_past_limit_evicts_least_recently_used() {
+        let registry = Registry::<()>::new(2);
+        registry.init();
+        registry.load("a", || Ok(())).unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        registry.load("b", || Ok(())).unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        registry.load("a", || Ok(())).unwrap();
+        registry.load("c", || Ok(())).unwrap();
+
+        let mut keys = registry.keys();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "c"]);
+
+        assert_eq!(registry.set_max_models(1).unwrap(), vec!["a"]);
+        assert_eq!(registry.keys(), vec!["c"]);
+    }
+
+    #[test]
+    fn shrink_idle_skips_models_in_use() {
+        let registry = Registry::<()>::new(3);
+        registry.init();
+        let a = registry.load("a", || Ok(())).unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        registry.load("b", || Ok(())).unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        registry.load("c", || Ok(())).unwrap();
+
+        let _busy = a.lock();
+        assert_eq!(
+            registry.shrink_idle(1).unwrap(),
+            (
+                vec!["b".to_string(), "c".to_string()],
+                vec!["a".to_string()]
+            )
+        );
+        assert_eq!(registry.keys(), vec!["a"]);
+
+        // A queued generation keeps a model even while its lock is free
+        let d = registry.load("d", || Ok(())).unwrap();
+        std::thread::sleep(Duration::from_millis(2));
+        registry.load("e", || Ok(())).unwrap();
+        lock_recovering(&d.slots, "model slots").queued += 1;
+        let (evicted, skipped) = registry.shrink_idle(1).unwrap();
+        assert_eq!(evicted, vec!["e"]);
+        assert_eq!(skipped, vec!["a", "d"]);
+
+        let _map = registry.models.lock().unwrap();
+        assert!(matches!(
+            registry.shrink_idle(1),
+            Err(PluginLlmError::ServiceBusy(_))
+        ));
+    }
+
+    #[test]
+    fn evict_if_idle_skips_models_with_generations() {
+        let registry = Registry::<()>::new(3);
+        registry.init();
+        let a = registry.load("a", || Ok(())).unwrap();
+        let b = registry.load("b", || Ok(())).unwrap();
+        registry.load("c", || Ok(())).unwrap();
+
+        let _slot = a.acquire_slot(1, 0).unwrap();
+        let _busy = b.lock();
+        assert!(!registry.evict_if_idle("a"));
+        assert!(!registry.evict_if_idle("b"));
+        assert!(registry.evict_if_idle("c"));
+        assert!(!registry.evict_if_idle("c"));
+        let mut keys = registry.keys();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn poisoned_locks_are_recovered() {
+        let registry = Registry::<()>::new(2);
+        registry.init();
+        let a = registry.load("a", || Ok(())).unwrap();
+
+        std::thread::scope(|s| {
+            let poison_map =
+                s.spawn(|| registry.with_map(|_| -> Result<(), PluginLlmError> { panic!("boom") }));
+            assert!(poison_map.join().is_err());
+            let poison_model = s.spawn(|| {
+                let _model = a.lock();
+                panic!("boom");
+            });
+            assert!(poison_model.join().is_err());
+        });
+        assert!(registry.models.is_poisoned());
+
+        registry.load("b", || Ok(())).unwrap();
+        let mut keys = registry.keys();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "b"]);
+        assert!(a.try_lock().is_some());
+        assert!(!registry.models.is_poisoned());
+    }
+
+    #[test]
+    fn aliases_outlive_eviction_but_not_unload() {
+        let registry = Registry::<()>::new(1);
+        registry.init();
+        registry.load("models/a", || Ok(())).unwrap();
+        registry.set_alias("small", "models/a").unwrap();
+        assert!(registry.set_alias("small", "models/b").is_err());
+
+        registry.load("models/b", || Ok(())).unwrap();
+        assert_eq!(registry.keys(), vec!["models/b"]);
+        assert_eq!(registry.resolve_alias("small").as_deref(), Some("models/a"));
+
+        registry.load("models/a", || Ok(())).unwrap();
+        registry.remove("models/a").unwrap();
+        assert_eq!(registry.resolve_alias("small"), None);
+    }
+
+    #[test]
+    fn evict_idle_keeps_recent_and_busy_models() {
+        let registry = Registry::<()>::new(5);
+        registry.init();
+        let a = registry.load("a", || Ok(())).unwrap();
+        registry.load("b", || Ok(())).unwrap();
+        let d = registry.load("d", || Ok(())).unwrap();
+        let e = registry.load("e", || Ok(())).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        registry.load("c", || Ok(())).unwrap();
+
+        // Holding the lock, a running slot and a queued one each keep a model
+        let _busy = a.lock();
+        let _slot = d.acquire_slot(1, 0).unwrap();
+        lock_recovering(&e.slots, "model slots").queued += 1;
+        assert_eq!(registry.evict_idle(Duration::from_millis(10)), vec!["b"]);
+        let mut keys = registry.keys();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "c", "d", "e"]);
+    }
+
+    #[test]
+    fn loading_a_loaded_model_keeps_its_generation_count() {
+        let registry = Registry::<()>::new(2);
+        registry.init();
+        let a = registry.load("a", || Ok(())).unwrap();
+        a.record_generation();
+        a.record_generation();
+
+        let again = registry.load("a", || panic!("already loaded")).unwrap();
+        assert_eq!(again.generations(), 2);
+    }
+
+    #[test]
+    fn generations_past_the_queue_bound_are_turned_away() {
+        let registry = Registry::<()>::new(1);
+        registry.init();
+        let model = registry.load("a", || Ok(())).unwrap();
+
+        let running = model.acquire_slot(1, 0).unwrap();
+        let busy = model.acquire_slot(1, 0).err().unwrap();
+        assert_eq!(busy.code(), "service_busy");
+
+        std::thread::scope(|s| {
+            let queued = s.spawn(|| model.acquire_slot(1, 1).map(|_| ()));
+            while model.slots().queued == 0 {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            assert!(model.acquire_slot(1, 1).is_err());
+            drop(running);
+            assert!(queued.join().unwrap().is_ok());
+        });
+        assert_eq!(model.slots().running, 0);
+    }
+
+    #[test]
+    fn concurrent_loads_of_one_key_open_it_once() {
+        let registry = Registry::<usize>::new(2);
+        registry.init();
+        let opens = AtomicUsize::new(0);
+        let open = || {
+            std::thread::sleep(Duration::from_millis(20));
+            Ok(opens.fetch_add(1, Ordering::SeqCst))
+        };
+
+        let entries: Vec<Arc<Entry<usize>>> = std::thread::scope(|s| {
+            let loads: Vec<_> = (0..32)
+                .map(|_| s.spawn(|| registry.load("a", open).unwrap()))
+                .collect();
+            loads.into_iter().map(|load| load.join().unwrap()).collect()
+        });
+        assert_eq!(opens.load(Ordering::SeqCst), 1);
+        assert!(entries.iter().all(|entry| Arc::ptr_eq(entry, &entries[0])));
+        assert_eq!(registry.keys(), vec!["a"]);
+    }
+
+    #[test]
+    fn a_failed_load_is_shared_with_waiters_but_not_cached() {
+        let registry = Registry::<()>::new(2);
+        registry.init();
+        let failed = std::thread::scope(|s| {
+            let loads: Vec<_> = (0..8)
+                .map(|_| {
+                    s.spawn(|| {
+                        registry.load("a", || {
+                            std::thread::sleep(Duration::from_millis(20));
+                            Err(PluginLlmError::ModelLoadFailed("no weights".to_string()))
+                        })
+                    })
+                })
+                .collect();
+            loads
+                .into_iter()
+                .map(|load| load.join().unwrap())
+                .filter(|result| result.is_err())
+                .count()
+        });
+        assert_eq!(failed, 8);
+        assert!(registry.keys().is_empty());
+
+        assert!(registry.load("a", || Ok(())).is_ok());
+        assert_eq!(registry.keys(), vec!["a"]);
+
+        // A panicking load doesn't leave the key stuck loading
+        let panicked =
+            std::thread::scope(|s| s.spawn(|| registry.load("b", || panic!("boom"))).join());
+        assert!(panicked.is_err());
+        assert!(registry.load("b", || Ok(())).is_ok());
+    }
+
+    #[test]
+    fn loads_report_the_models_they_evict() {
+        static EVICTED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        let registry = Registry::<()>::new(1).on_evict(|key| {
+            EVICTED.lock().unwrap().push(key.to_string());
+        });
+        registry.init();
+        registry.load("a", || Ok(())).unwrap();
+        registry.load("b", || Ok(())).unwrap();
+        assert_eq!(*EVICTED.lock().unwrap(), vec!["a"]);
+    }
+}
@@ -0,0 +1,131 @@
+//! Loaded models persisted across plugin restarts (`restore_models` config)
+//!
+//! `shutdown` writes the loaded model paths and their aliases to a small
+//! state file in the plugin's data dir; the next `init` re-registers them.
+//! `lazy` only restores aliases, so each model loads on first use; `eager`
+//! also loads every model on a background thread so init isn't held up.
+//! Paths that no longer exist are skipped with a warning.
+
+use crate::{load_model, MODELS};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// State file under the plugin's data dir
+const STATE_FILE: &str = "loaded_models.json";
+
+/// What `init` does with the models loaded before the last shutdown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RestoreMode {
+    /// Neither saved nor restored
+    #[default]
+    Off,
+    /// Aliases restored; models load on first use
+    Lazy,
+    /// Aliases restored and models loaded in the background
+    Eager,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SavedModel {
+    path: String,
+    #[serde(default)]
+    aliases: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct State {
+    /// Least recently used first, so restoring past the model limit keeps
+    /// the most recently used
+    models: Vec<SavedModel>,
+}
+
+/// State file path while restoring is on (set by `restore`)
+static STATE_PATH: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// Re-register the models saved by the last `save`, and remember where to
+/// save them on shutdown
+pub fn restore(data_dir: &Path, mode: RestoreMode) {
+    let path = (mode != RestoreMode::Off).then(|| data_dir.join(STATE_FILE));
+    *STATE_PATH.lock().unwrap_or_else(|e| e.into_inner()) = path.clone();
+    let Some(path) = path else {
+        return;
+    };
+
+    let state: State = match std::fs::read(&path) {
+        Ok(bytes) => match serde_json::from_slice(&bytes) {
+            Ok(state) => state,
+            Err(e) => {
+                tracing::warn!("Ignoring unreadable {}: {}", path.display(), e);
+                return;
+            }
+        },
+        Err(_) => return,
+    };
+
+    let mut to_load = Vec::new();
+    for model in state.models {
+        if !Path::new(&model.path).exists() {
+            tracing::warn!("Not restoring {}: the path no longer exists", model.path);
+            continue;
+        }
+        for alias in &model.aliases {
+            if let Err(e) = MODELS.set_alias(alias, &model.path) {
+                tracing::warn!("Not restoring alias {}: {}", alias, e);
+            }
+        }
+        to_load.push(model.path);
+    }
+    if mode != RestoreMode::Eager || to_load.is_empty() {
+        return;
+    }
+
+    let spawned = std::thread::Builder::new()
+        .name("uzu-restore".to_string())
+        .spawn(move || {
+            for path in to_load {
+                if let Err(e) = load_model(&path, None) {
+                    tracing::warn!("Failed to restore {}: {}", path, e);
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        tracing::error!("Failed to start model restore thread: {}", e);
+    }
+}
+
+/// Write the loaded models to the state file, if restoring is on
+pub fn save() {
+    let Some(path) = STATE_PATH.lock().unwrap_or_else(|e| e.into_inner()).take() else {
+        return;
+    };
+    let mut entries = match MODELS.entries() {
+        Ok(entries) => entries,
+        Err(e) => {
+            tracing::warn!("Not saving loaded models: {}", e);
+            return;
+        }
+    };
+    entries.sort_by_key(|(_, model)| model.last_access_at());
+    let state = State {
+        models: entries
+            .into_iter()
+            .map(|(key, _)| SavedModel {
+                aliases: MODELS.aliases_of(&key),
+                path: key,
+            })
+            .collect(),
+    };
+
+    // Written aside and renamed, so a crash mid-write keeps the old state
+    let partial = path.with_extension("json.partial");
+    let written = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&partial, serde_json::to_vec(&state).unwrap_or_default()))
+        .and_then(|_| std::fs::rename(&partial, &path));
+    if let Err(e) = written {
+        tracing::warn!("Failed to save loaded models to {}: {}", path.display(), e);
+    }
+}
@@ -0,0 +1,316 @@
+//! Sampling parameters and their resolution into effective settings
+//!
+//! Generation and `resolve_sampling` share `resolve`, so the preview always
+//! matches what a real request would use.
+
+use serde::{Deserialize, Serialize};
+
+/// Temperatures above this are clamped
+pub const MAX_TEMPERATURE: f32 = 2.0;
+
+/// Temperature multiplier for the `retry_on_gibberish` retry
+const GIBBERISH_RETRY_FACTOR: f32 = 0.5;
+
+/// Retry temperature when the first attempt used the engine default
+const GIBBERISH_RETRY_TEMPERATURE: f32 = 0.3;
+
+/// Seed sent with greedy decoding, whose output doesn't depend on it
+const GREEDY_SEED: u64 = 0;
+
+/// Recent tokens the repetition penalties consider by default
+pub const DEFAULT_PENALTY_LAST_N: usize = 64;
+
+/// Repetition penalty that leaves logits unchanged (the default)
+pub const DEFAULT_REPETITION_PENALTY: f32 = 1.0;
+
+/// Largest accepted repetition penalty
+const MAX_REPETITION_PENALTY: f32 = 2.0;
+
+/// Frequency penalty that leaves logits unchanged (the default)
+pub const DEFAULT_FREQUENCY_PENALTY: f32 = 0.0;
+
+/// Frequency penalties must lie within +/- this bound
+const MAX_FREQUENCY_PENALTY: f32 = 2.0;
+
+/// Sampling parameters as supplied by the caller
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct SamplingParams {
+    #[serde(default)]
+    pub max_tokens: Option<usize>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    /// Nucleus sampling: sample from the smallest set with this probability mass
+    #[serde(default)]
+    pub top_p: Option<f32>,
+    /// Sample only from the `top_k` most likely tokens
+    #[serde(default)]
+    pub top_k: Option<usize>,
+    /// RNG seed for reproducible sampling (chosen per request when omitted)
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Retry once at a lower temperature if the output looks like gibberish
+    #[serde(default)]
+    pub retry_on_gibberish: bool,
+    /// Cap max_tokens at this multiple of the prompt's token count
+    #[serde(default)]
+    pub max_tokens_ratio: Option<f32>,
+    /// Recent tokens the repetition/frequency penalties consider (0 = whole context)
+    #[serde(default)]
+    pub penalty_last_n: Option<usize>,
+    /// Divides the logits of recently seen tokens (1.0 = off)
+    #[serde(default)]
+    pub repetition_penalty: Option<f32>,
+    /// Subtracted from a token's logit per previous occurrence (0.0 = off)
+    #[serde(default)]
+    pub frequency_penalty: Option<f32>,
+}
+
+impl SamplingParams {
+    /// These parameters, with any left unset taken from `defaults`
+    pub fn or(&self, defaults: &SamplingParams) -> SamplingParams {
+        SamplingParams {
+            max_tokens: self.max_tokens.or(defaults.max_tokens),
+            temperature: self.temperature.or(defaults.temperature),
+            top_p: self.top_p.or(defaults.top_p),
+            top_k: self.top_k.or(defaults.top_k),
+            seed: self.seed.or(defaults.seed),
+            retry_on_gibberish: self.retry_on_gibberish || defaults.retry_on_gibberish,
+            max_tokens_ratio: self.max_tokens_ratio.or(defaults.max_tokens_ratio),
+            penalty_last_n: self.penalty_last_n.or(defaults.penalty_last_n),
+            repetition_penalty: self.repetition_penalty.or(defaults.repetition_penalty),
+            frequency_penalty: self.frequency_penalty.or(defaults.frequency_penalty),
+        }
+    }
+}
+
+/// Effective sampling settings for one generation
+#[derive(Debug, Clone, Serialize)]
+pub struct ResolvedSampling {
+    /// `None` leaves the engine default in place
+    pub max_tokens: Option<usize>,
+    /// `None` leaves the engine default in place
+    pub temperature: Option<f32>,
+    /// `None` leaves the engine default in place
+    pub top_p: Option<f32>,
+    /// `None` leaves the engine default in place
+    pub top_k: Option<usize>,
+    /// `None` until generation picks a seed
+    pub seed: Option<u64>,
+    /// Temperature 0 selects greedy decoding
+    pub greedy: bool,
+    /// Temperature of the single `retry_on_gibberish` retry (`None` = no retry)
+    pub retry_temperature: Option<f32>,
+    /// Penalty window in tokens (`None` = whole context). The penalties are
+    /// validated but never applied, so they are left out of the report.
+    #[serde(skip)]
+    pub penalty_last_n: Option<usize>,
+    #[serde(skip)]
+    pub repetition_penalty: f32,
+    #[serde(skip)]
+    pub frequency_penalty: f32,
+    /// Adjustments made while resolving
+    pub notes: Vec<String>,
+}
+
+/// Sampling settings handed to the engine for one generation
+#[derive(Debug, Clone, PartialEq)]
+pub struct EngineSampling {
+    pub temperature: Option<f32>,
+    pub top_p: Option<f32>,
+    pub top_k: Option<usize>,
+    pub seed: Option<u64>,
+}
+
+impl ResolvedSampling {
+    /// What the engine is asked for.
+    ///
+    /// Greedy decoding isn't sent as temperature 0, which the sampler may
+    /// divide by: it becomes top-k 1 (argmax) with a fixed seed, so the
+    /// output is the same on every run whatever seed the request had.
+    pub fn engine(&self) -> EngineSampling {
+        if self.greedy {
+            return EngineSampling {
+                temperature: None,
+                top_p: None,
+                top_k: Some(1),
+                seed: Some(GREEDY_SEED),
+            };
+        }
+        EngineSampling {
+            temperature: self.temperature,
+            top_p: self.top_p,
+            top_k: self.top_k,
+            seed: self.seed,
+        }
+    }
+}
+
+/// Validate and resolve caller parameters into effective settings
+pub fn resolve(params: &SamplingParams) -> Result<ResolvedSampling, String> {
+    let mut notes = Vec::new();
+
+    if params.max_tokens == Some(0) {
+        return Err("Invalid arguments: max_tokens must be at least 1".to_string());
+    }
+
+    if let Some(top_p) = params.top_p {
+        if !(0.0..=1.0).contains(&top_p) {
+            return Err(format!(
+                "Invalid arguments: top_p must be within 0.0..=1.0, got {}",
+                top_p
+            ));
+        }
+    }
+    if params.top_k == Some(0) {
+        return Err("Invalid arguments: top_k must be at least 1".to_string());
+    }
+
+    let repetition_penalty = params
+        .repetition_penalty
+        .unwrap_or(DEFAULT_REPETITION_PENALTY);
+    if !(repetition_penalty > 0.0 && repetition_penalty <= MAX_REPETITION_PENALTY) {
+        return Err(format!(
+            "Invalid arguments: repetition_penalty must be in (0, {}], got {}",
+            MAX_REPETITION_PENALTY, repetition_penalty
+        ));
+    }
+    let frequency_penalty = params
+        .frequency_penalty
+        .unwrap_or(DEFAULT_FREQUENCY_PENALTY);
+    if !(-MAX_FREQUENCY_PENALTY..=MAX_FREQUENCY_PENALTY).contains(&frequency_penalty) {
+        return Err(format!(
+            "Invalid arguments: frequency_penalty must be within {}..={}, got {}",
+            -MAX_FREQUENCY_PENALTY, MAX_FREQUENCY_PENALTY, frequency_penalty
+        ));
+    }
+
+    if let Some(ratio) = params.max_tokens_ratio {
+        if !ratio.is_finite() || ratio <= 0.0 {
+            return Err(format!(
+                "Invalid arguments: max_tokens_ratio must be > 0, got {}",
+                ratio
+            ));
+        }
+        notes.push(format!(
+            "max_tokens capped at {} x prompt tokens after tokenization",
+            ratio
+        ));
+    }
+
+    let temperature = match params.temperature {
+        Some(t) if t.is_nan() || t < 0.0 => {
+            return Err(format!(
+                "Invalid arguments: temperature must be >= 0, got {}",
+                t
+            ))
+        }
+        Some(t) if t > MAX_TEMPERATURE => {
+            notes.push(format!("temperature {} clamped to {}", t, MAX_TEMPERATURE));
+            Some(MAX_TEMPERATURE)
+        }
+        t => t,
+    };
+
+    let greedy = temperature == Some(0.0);
+    if greedy {
+        notes.push("temperature 0 selects greedy decoding (top_k 1, seed ignored)".to_string());
+    }
+
+    let retry_temperature = match (params.retry_on_gibberish, greedy) {
+        (false, _) => None,
+        (true, true) => {
+            notes.push("retry_on_gibberish has no effect with greedy decoding".to_string());
+            None
+        }
+        (true, false) => Some(
+            temperature
+                .map(|t| t * GIBBERISH_RETRY_FACTOR)
+                .unwrap_or(GIBBERISH_RETRY_TEMPERATURE),
+        ),
+    };
+
+    let penalty_last_n = match params.penalty_last_n {
+        Some(0) => None,
+        Some(n) => Some(n),
+        None => Some(DEFAULT_PENALTY_LAST_N),
+    };
+
+    Ok(ResolvedSampling {
+        max_tokens: params.max_tokens,
+        temperature,
+        top_p: params.top_p,
+        top_k: params.top_k,
+        seed: params.seed,
+        greedy,
+        retry_temperature,
+        penalty_last_n,
+        repetition_penalty,
+        frequency_penalty,
+        notes,
+    })
+}
+
+/// Seed for a request that didn't supply one
+pub fn random_seed() -> u64 {
+    use std::hash::{BuildHasher, Hasher};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    // RandomState is randomly keyed per process; mixing in a counter and
+    // the clock keeps seeds distinct across calls
+    let mut hasher = std::collections::hash_map::RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    if let Ok(elapsed) = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        hasher.write_u128(elapsed.as_nanos());
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temperature_zero_decodes_identically_whatever_the_seed() {
+        let greedy = |seed| {
+            let mut resolved = resolve(&SamplingParams {
+                temperature: Some(0.0),
+                top_p: Some(0.9),
+                ..Default::default()
+            })
+            .unwrap();
+            resolved.seed = Some(seed);
+            resolved.engine()
+        };
+        let first = greedy(random_seed());
+        assert_eq!(first, greedy(random_seed()));
+        assert_eq!(first.top_k, Some(1));
+        assert_eq!(first.temperature, None);
+
+        let sampled = resolve(&SamplingParams {
+            temperature: Some(0.7),
+            seed: Some(3),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(sampled.engine().temperature, Some(0.7));
+        assert_eq!(sampled.engine().seed, Some(3));
+    }
+
+    #[test]
+    fn negative_and_nan_temperatures_are_rejected() {
+        for t in [-0.1, f32::NAN] {
+            let e = resolve(&SamplingParams {
+                temperature: Some(t),
+                ..Default::default()
+            })
+            .unwrap_err();
+            assert!(
+                e.starts_with("Invalid arguments: temperature must be >= 0"),
+                "{}",
+                e
+            );
+        }
+    }
+}
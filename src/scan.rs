@@ -0,0 +1,161 @@
+//! Discovery of models on disk (`scan`)
+//!
+//! Walks a models directory without loading anything. A directory holding
+//! `config.json` next to weight files is one model and isn't descended into;
+//! a loose `.gguf` file is a model of its own. Unreadable entries are
+//! reported in `errors` and the walk goes on.
+
+use crate::{model_files, MODELS};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Directory levels searched below the root (`org/model` needs two)
+const MAX_DEPTH: usize = 4;
+
+/// Weight file extensions that make a directory with `config.json` a model
+const WEIGHT_EXTENSIONS: &[&str] = &["safetensors", "gguf", "bin"];
+
+/// Models found under `root`, as `{"root", "models", "errors"}`
+pub fn scan(root: &Path) -> Result<String, String> {
+    if !root.is_dir() {
+        return Err(format!(
+            "Invalid arguments: {} is not a directory",
+            root.display()
+        ));
+    }
+    let loaded: HashSet<PathBuf> = MODELS
+        .entries()
+        .map(|entries| {
+            entries
+                .iter()
+                .map(|(_, model)| model.resolved_path.clone())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut found = Vec::new();
+    let mut errors = Vec::new();
+    walk(root, 0, &mut found, &mut errors);
+    found.sort();
+
+    let models: Vec<Value> = found
+        .iter()
+        .map(|path| {
+            let resolved = std::fs::canonicalize(path).unwrap_or_else(|_| path.clone());
+            let mut model = match model_files::read_static_info(path) {
+                Ok(info) => json!({
+                    "name": info.name,
+                    "size": info.size,
+                    "architecture": info.architecture,
+                    "quantization": info.quantization,
+                    "context_length": info.context_length,
+                }),
+                Err(e) => json!({ "error": e }),
+            };
+            model["path"] = json!(path.display().to_string());
+            model["loaded"] = json!(loaded.contains(&resolved));
+            model
+        })
+        .collect();
+
+    let result = json!({
+        "root": root.display().to_string(),
+        "models": models,
+        "errors": errors,
+    });
+    Ok(serde_json::to_string(&result).unwrap_or_default())
+}
+
+fn walk(dir: &Path, depth: usize, found: &mut Vec<PathBuf>, errors: &mut Vec<Value>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            errors.push(json!({ "path": dir.display().to_string(), "error": e.to_string() }));
+            return;
+        }
+    };
+
+    let mut subdirs = Vec::new();
+    let mut has_config = false;
+    let mut weights = Vec::new();
+    for entry in entries {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                errors.push(json!({ "path": dir.display().to_string(), "error": e.to_string() }));
+                continue;
+            }
+        };
+        let name = entry.file_name().to_string_lossy().into_owned();
+        // Hidden entries and unfinished `hf:` downloads aren't models
+        if name.starts_with('.') || name.ends_with(".partial") {
+            continue;
+        }
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(kind) if kind.is_dir() || (kind.is_symlink() && path.is_dir()) => subdirs.push(path),
+            Ok(_) if name == "config.json" => has_config = true,
+            Ok(_) => {
+                let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+                if WEIGHT_EXTENSIONS.contains(&extension) {
+                    weights.push(path);
+                }
+            }
+            Err(e) => {
+                errors.push(json!({ "path": path.display().to_string(), "error": e.to_string() }))
+            }
+        }
+    }
+
+    if has_config && !weights.is_empty() {
+        found.push(dir.to_path_buf());
+        return;
+    }
+    found.extend(
+        weights
+            .into_iter()
+            .filter(|p| p.extension().is_some_and(|e| e == "gguf")),
+    );
+    if depth < MAX_DEPTH {
+        for subdir in subdirs {
+            walk(&subdir, depth + 1, found, errors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_finds_model_dirs_and_loose_gguf_files() {
+        let root = std::env::temp_dir().join(format!("uzu-scan-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let model = root.join("org/model");
+        std::fs::create_dir_all(&model).unwrap();
+        std::fs::write(model.join("config.json"), r#"{"model_type": "llama"}"#).unwrap();
+        std::fs::write(model.join("model.safetensors"), "").unwrap();
+        std::fs::create_dir_all(root.join("org/model.partial")).unwrap();
+        std::fs::create_dir_all(root.join("empty")).unwrap();
+        std::fs::write(root.join("small.gguf"), "gguf").unwrap();
+
+        let result: Value = serde_json::from_str(&scan(&root).unwrap()).unwrap();
+        let paths: Vec<&str> = result["models"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|m| m["path"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            paths,
+            vec![
+                model.display().to_string(),
+                root.join("small.gguf").display().to_string()
+            ]
+        );
+        assert_eq!(result["models"][0]["architecture"], "llama");
+        assert_eq!(result["models"][0]["loaded"], false);
+        assert_eq!(result["errors"], json!([]));
+    }
+}
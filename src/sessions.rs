@@ -0,0 +1,201 @@
+//! Generation sessions
+//!
+//! A session groups generations against one model and enforces an optional
+//! token budget across all of its turns.
+//!
+//! A turn reserves everything left of the budget before it generates and
+//! gives back what it didn't use when charged, so concurrent turns can't
+//! each spend the same remainder.
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Active sessions (session_id -> Session)
+static SESSIONS: Mutex<Option<HashMap<String, Session>>> = Mutex::new(None);
+
+struct Session {
+    model_key: String,
+    /// Total tokens the session may generate (`None` = unlimited)
+    token_budget: Option<usize>,
+    tokens_used: usize,
+    /// Tokens held by turns still generating
+    tokens_reserved: usize,
+    turns: usize,
+}
+
+impl Session {
+    fn remaining(&self) -> Option<usize> {
+        self.token_budget.map(|budget| {
+            budget
+                .saturating_sub(self.tokens_used)
+                .saturating_sub(self.tokens_reserved)
+        })
+    }
+}
+
+/// Budget held for one turn, released on drop unless charged
+pub struct Reservation {
+    session_id: String,
+    /// Tokens the turn may generate (`None` = unlimited)
+    limit: Option<usize>,
+    charged: bool,
+}
+
+impl Reservation {
+    /// Tokens the turn may generate (`None` = unlimited)
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Record the finished turn, returning the unused reservation
+    pub fn charge(mut self, tokens: usize) -> Result<(), String> {
+        self.charged = true;
+        let reserved = self.limit.unwrap_or(0);
+        with_sessions(|sessions| {
+            if let Some(session) = sessions.get_mut(&self.session_id) {
+                session.tokens_reserved -= reserved;
+                session.tokens_used += tokens;
+                session.turns += 1;
+            }
+            Ok(())
+        })
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if self.charged {
+            return;
+        }
+        let reserved = self.limit.unwrap_or(0);
+        let _ = with_sessions(|sessions| {
+            if let Some(session) = sessions.get_mut(&self.session_id) {
+                session.tokens_reserved -= reserved;
+            }
+            Ok(())
+        });
+    }
+}
+
+fn with_sessions<T>(
+    f: impl FnOnce(&mut HashMap<String, Session>) -> Result<T, String>,
+) -> Result<T, String> {
+    let mut sessions = SESSIONS
+        .lock()
+        .map_err(|e| format!("Failed to lock sessions: {}", e))?;
+    f(sessions.get_or_insert_with(HashMap::new))
+}
+
+/// Create a session bound to `model_key`
+pub fn start(model_key: &str, token_budget: Option<usize>) -> Result<String, String> {
+    let session_id = format!(
+        "session-{}",
+        NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed)
+    );
+    with_sessions(|sessions| {
+        sessions.insert(
+            session_id.clone(),
+            Session {
+                model_key: model_key.to_string(),
+                token_budget,
+                tokens_used: 0,
+                tokens_reserved: 0,
+                turns: 0,
+            },
+        );
+        Ok(session_id)
+    })
+}
+
+/// Session state as JSON
+pub fn info(session_id: &str) -> Result<Value, String> {
+    with_sessions(|sessions| {
+        let session = sessions
+            .get(session_id)
+            .ok_or_else(|| format!("Unknown session: {}", session_id))?;
+        Ok(json!({
+            "session_id": session_id,
+            "model_path": session.model_key,
+            "turns": session.turns,
+            "tokens_used": session.tokens_used,
+            "token_budget": session.token_budget,
+            "tokens_remaining": session.remaining(),
+        }))
+    })
+}
+
+/// Remove a session
+pub fn end(session_id: &str) -> Result<(), String> {
+    with_sessions(|sessions| {
+        sessions
+            .remove(session_id)
+            .map(|_| ())
+            .ok_or_else(|| format!("Unknown session: {}", session_id))
+    })
+}
+
+/// Reserve what is left of `session_id`'s budget for a new turn.
+///
+/// Fails if the session doesn't exist, belongs to another model, or has
+/// nothing left (used, or reserved by turns still generating).
+pub fn reserve(session_id: &str, model_key: &str) -> Result<Reservation, String> {
+    with_sessions(|sessions| {
+        let session = sessions
+            .get_mut(session_id)
+            .ok_or_else(|| format!("Unknown session: {}", session_id))?;
+        if session.model_key != model_key {
+            return Err(format!(
+                "Session {} belongs to model {}",
+                session_id, session.model_key
+            ));
+        }
+        let limit = session.remaining();
+        match limit {
+            Some(0) if session.tokens_reserved > 0 => {
+                return Err(format!(
+                    "Budget exhausted: session {} has its remaining {} tokens reserved by a turn in progress",
+                    session_id, session.tokens_reserved
+                ))
+            }
+            Some(0) => {
+                return Err(format!(
+                    "Budget exhausted: session {} used all {} tokens",
+                    session_id, session.tokens_used
+                ))
+            }
+            Some(tokens) => session.tokens_reserved += tokens,
+            None => {}
+        }
+        Ok(Reservation {
+            session_id: session_id.to_string(),
+            limit,
+            charged: false,
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn concurrent_turns_cannot_spend_the_same_budget() {
+        let id = start("models/budget", Some(10)).unwrap();
+        let remaining = || info(&id).unwrap()["tokens_remaining"].clone();
+
+        let first = reserve(&id, "models/budget").unwrap();
+        assert_eq!(first.limit(), Some(10));
+        let e = reserve(&id, "models/budget").err().unwrap();
+        assert!(e.starts_with("Budget exhausted:"), "{}", e);
+
+        first.charge(4).unwrap();
+        assert_eq!(remaining(), 6);
+        drop(reserve(&id, "models/budget").unwrap());
+        assert_eq!(remaining(), 6);
+        assert!(reserve(&id, "models/other").is_err());
+    }
+}
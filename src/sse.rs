@@ -0,0 +1,31 @@
+//! Server-sent events framing for CLI `generate --format sse`
+
+/// Payload of the event ending a stream
+pub const DONE: &str = "[DONE]";
+
+/// One event carrying `data`. Each line of a multi-line payload gets its own
+/// `data:` field, which readers join back with newlines.
+pub fn event(data: &str) -> String {
+    let mut framed = String::with_capacity(data.len() + 8);
+    for line in data.split('\n') {
+        framed.push_str("data: ");
+        framed.push_str(line.strip_suffix('\r').unwrap_or(line));
+        framed.push('\n');
+    }
+    framed.push('\n');
+    framed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn newlines_in_tokens_never_break_an_event() {
+        let token = json!({ "token": "one\ntwo\r\n" }).to_string();
+        assert_eq!(event(&token), "data: {\"token\":\"one\\ntwo\\r\\n\"}\n\n");
+        assert_eq!(event("a\nb"), "data: a\ndata: b\n\n");
+        assert_eq!(event(DONE), "data: [DONE]\n\n");
+    }
+}
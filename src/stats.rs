@@ -0,0 +1,138 @@
+//! Plugin-wide usage counters exposed by the `stats` method
+//!
+//! Rejections are counted by error code at the CLI and inference entry
+//! points, so every rejection site is covered by the same classification.
+//! Generations are counted once each, where `generate_once` finishes one,
+//! which every generate, stream, batch, async and chat path goes through.
+
+use crate::{loaded_memory, tokens_per_second, uptime_ms};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Error codes that mean a request was refused rather than failed while running
+const REJECTION_CODES: &[&str] = &[
+    "usage",
+    "unknown_command",
+    "unknown_method",
+    "invalid_args",
+    "model_not_found",
+    "invalid_model_path",
+    "payload_too_large",
+    "context_overflow",
+    "draining",
+    "service_busy",
+    "rate_limited",
+    "memory_budget_exceeded",
+    "budget_exhausted",
+    "session_invalidated",
+    "unsupported",
+    "unsupported_platform",
+    "unsupported_capability",
+];
+
+/// Generation counters, plugin-wide and per model
+#[derive(Default)]
+struct Counters {
+    generations: AtomicU64,
+    tokens_generated: AtomicU64,
+    /// Time spent generating, model loads and queueing excluded
+    generation_ms: AtomicU64,
+}
+
+impl Counters {
+    const fn new() -> Self {
+        Self {
+            generations: AtomicU64::new(0),
+            tokens_generated: AtomicU64::new(0),
+            generation_ms: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, tokens: usize, elapsed: Duration) {
+        self.generations.fetch_add(1, Ordering::Relaxed);
+        self.tokens_generated
+            .fetch_add(tokens as u64, Ordering::Relaxed);
+        self.generation_ms
+            .fetch_add(elapsed.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Value {
+        let tokens = self.tokens_generated.load(Ordering::Relaxed);
+        let ms = self.generation_ms.load(Ordering::Relaxed);
+        json!({
+            "generations": self.generations.load(Ordering::Relaxed),
+            "tokens_generated": tokens,
+            "generation_ms": ms,
+            "avg_tokens_per_second": tokens_per_second(tokens as usize, Duration::from_millis(ms)),
+        })
+    }
+
+    fn reset(&self) {
+        self.generations.store(0, Ordering::Relaxed);
+        self.tokens_generated.store(0, Ordering::Relaxed);
+        self.generation_ms.store(0, Ordering::Relaxed);
+    }
+}
+
+static TOTALS: Counters = Counters::new();
+
+/// Counters by model key; kept after the model is unloaded
+static PER_MODEL: Mutex<BTreeMap<String, Counters>> = Mutex::new(BTreeMap::new());
+
+/// Rejected requests by reason (error code -> count)
+static REJECTIONS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+
+/// Record a completed generation of `tokens` on `model` taking `elapsed`
+pub fn record_generation(model: &str, tokens: usize, elapsed: Duration) {
+    TOTALS.record(tokens, elapsed);
+    let mut per_model = PER_MODEL.lock().unwrap_or_else(|e| e.into_inner());
+    per_model
+        .entry(model.to_string())
+        .or_default()
+        .record(tokens, elapsed);
+}
+
+/// Count a failed request if its error code marks it as a rejection
+pub fn record_error(code: &'static str) {
+    if !REJECTION_CODES.contains(&code) {
+        return;
+    }
+    if let Ok(mut rejections) = REJECTIONS.lock() {
+        *rejections.entry(code).or_insert(0) += 1;
+    }
+}
+
+/// Current counters as JSON, with the loaded models and uptime
+pub fn snapshot() -> Value {
+    let rejections = REJECTIONS.lock().map(|r| r.clone()).unwrap_or_default();
+    let per_model: BTreeMap<String, Value> = PER_MODEL
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|(model, counters)| (model.clone(), counters.snapshot()))
+        .collect();
+    // Before `init` the registry is unusable; report nothing loaded
+    let (models_loaded, memory_bytes) = loaded_memory()
+        .map(|models| (models.len(), models.iter().map(|(_, bytes, _)| bytes).sum()))
+        .unwrap_or((0, 0u64));
+
+    let mut result = TOTALS.snapshot();
+    result["models_loaded"] = json!(models_loaded);
+    result["memory_bytes"] = json!(memory_bytes);
+    result["uptime_ms"] = json!(uptime_ms());
+    result["models"] = json!(per_model);
+    result["rejections"] = json!(rejections);
+    result
+}
+
+/// Zero every counter
+pub fn reset() {
+    TOTALS.reset();
+    PER_MODEL.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    if let Ok(mut rejections) = REJECTIONS.lock() {
+        rejections.clear();
+    }
+}
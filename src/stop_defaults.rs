@@ -0,0 +1,88 @@
+//! Per-model stop sequences and EOS handling (`model_stops` config, `load`
+//! `stops`)
+//!
+//! A model's default `stop` sequences apply to every request to it; the
+//! request's own `stop` is added to them unless it sets `replace_stop`. Its
+//! `ignore_eos` applies unless the request sets one.
+//!
+//! The engine can't suppress EOS, so ignoring it means continuing: when a
+//! reply ends at EOS short of `max_tokens`, the decode loop prompts the
+//! engine again with the output so far. That needs a `max_tokens` to end.
+
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StopDefaults {
+    /// Stop sequences applied to every request
+    pub stop: Vec<String>,
+    /// Keep generating past EOS until `max_tokens` (base models that never
+    /// end a reply on their own, or end it too early)
+    pub ignore_eos: bool,
+}
+
+impl StopDefaults {
+    /// Reason the defaults are unusable, if any
+    pub fn validate(&self) -> Result<(), String> {
+        if self.stop.iter().any(String::is_empty) {
+            return Err("stop sequences must not be empty".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A request's stop sequences and `ignore_eos` over the model's defaults
+pub fn resolve(
+    defaults: &StopDefaults,
+    stop: &[String],
+    replace_stop: bool,
+    ignore_eos: Option<bool>,
+) -> (Vec<String>, bool) {
+    let mut merged = if replace_stop {
+        Vec::new()
+    } else {
+        defaults.stop.clone()
+    };
+    for sequence in stop {
+        if !merged.contains(sequence) {
+            merged.push(sequence.clone());
+        }
+    }
+    (merged, ignore_eos.unwrap_or(defaults.ignore_eos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_stops_merge_with_model_defaults_unless_replaced() {
+        let defaults = StopDefaults {
+            stop: vec!["\n\n".to_string(), "###".to_string()],
+            ignore_eos: true,
+        };
+        let request = ["###".to_string(), "END".to_string()];
+
+        assert_eq!(
+            resolve(&defaults, &request, false, None),
+            (
+                vec!["\n\n".to_string(), "###".to_string(), "END".to_string()],
+                true
+            )
+        );
+        assert_eq!(
+            resolve(&defaults, &request, true, Some(false)),
+            (request.to_vec(), false)
+        );
+        assert_eq!(
+            resolve(&StopDefaults::default(), &[], false, None),
+            (vec![], false)
+        );
+        assert!(StopDefaults {
+            stop: vec![String::new()],
+            ignore_eos: false
+        }
+        .validate()
+        .is_err());
+    }
+}
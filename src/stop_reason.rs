@@ -0,0 +1,86 @@
+//! Why a generation stopped (`stop_reason`)
+//!
+//! Plugin-side decode controls and the engine both end generations. Their
+//! reasons are normalized into `StopReason`, serialized as a stable
+//! lowercase string, so callers can match on it whichever path produced it.
+
+use serde::{Serialize, Serializer};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    /// `max_tokens` were generated
+    MaxTokens,
+    /// A `stop` sequence matched
+    StopSequence,
+    /// The model ended its reply
+    Eos,
+    /// `timeout_ms` expired
+    Timeout,
+    /// `cancel`, or the stream's consumer stopped it
+    Cancelled,
+    /// The engine failed after producing output
+    Error,
+    /// `stop_on_json_complete`: the first JSON value closed
+    JsonComplete,
+    /// `ban_substrings` kept matching after every retry
+    BanExhausted,
+    /// An engine reason with no variant of its own, as reported
+    Other(String),
+}
+
+impl StopReason {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::MaxTokens => "max_tokens",
+            Self::StopSequence => "stop_sequence",
+            Self::Eos => "eos",
+            Self::Timeout => "timeout",
+            Self::Cancelled => "cancelled",
+            Self::Error => "error",
+            Self::JsonComplete => "json_complete",
+            Self::BanExhausted => "ban_exhausted",
+            Self::Other(reason) => reason,
+        }
+    }
+
+    /// Normalize a reason reported by the engine
+    pub fn from_engine(reason: &str) -> Self {
+        match reason.trim().to_ascii_lowercase().as_str() {
+            "max_tokens" | "length" | "max_length" | "token_limit" => Self::MaxTokens,
+            "eos" | "stop" | "end_of_sequence" | "end_of_text" | "eos_token" => Self::Eos,
+            "stop_sequence" | "stop_string" => Self::StopSequence,
+            "timeout" => Self::Timeout,
+            "cancelled" | "canceled" | "interrupted" | "aborted" => Self::Cancelled,
+            "error" => Self::Error,
+            _ => Self::Other(reason.to_string()),
+        }
+    }
+}
+
+impl Serialize for StopReason {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn engine_reasons_normalize_to_stable_strings() {
+        assert_eq!(StopReason::from_engine("length"), StopReason::MaxTokens);
+        assert_eq!(StopReason::from_engine("EOS"), StopReason::Eos);
+        assert_eq!(StopReason::from_engine("canceled"), StopReason::Cancelled);
+        assert_eq!(
+            StopReason::from_engine("KvCacheFull"),
+            StopReason::Other("KvCacheFull".to_string())
+        );
+        assert_eq!(json!(StopReason::JsonComplete), json!("json_complete"));
+        assert_eq!(
+            json!(StopReason::from_engine("KvCacheFull")),
+            json!("KvCacheFull")
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! Token id -> text mapping from a model's `tokenizer.json`
+//!
+//! The engine tokenizes but has no decode call, so `tokenize` pieces and
+//! `detokenize` read the vocabulary the engine's tokenizer is built from and
+//! undo its decoder: GPT-2 style byte-level pieces, or SentencePiece style
+//! `▁` spaces with `<0xNN>` byte fallback. Added tokens decode as written.
+
+use serde_json::Value;
+
+/// SentencePiece's space marker
+const METASPACE: char = '\u{2581}';
+
+/// How vocabulary pieces map back to text
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Decoder {
+    /// Each char stands for one byte (GPT-2 `bytes_to_unicode`)
+    ByteLevel,
+    /// `▁` is a space, `<0xNN>` a raw byte; the first leading space is dropped
+    Metaspace,
+    /// Pieces are plain text
+    Plain,
+}
+
+pub struct Vocab {
+    /// Piece per id (`None` for ids the file doesn't define)
+    pieces: Vec<Option<String>>,
+    /// Ids of added tokens, which bypass the decoder
+    added: Vec<bool>,
+    decoder: Decoder,
+}
+
+impl Vocab {
+    /// Build from a parsed `tokenizer.json`
+    pub fn parse(tokenizer: &Value) -> Result<Self, String> {
+        let mut entries: Vec<(usize, String, bool)> = Vec::new();
+        match tokenizer.pointer("/model/vocab") {
+            // BPE / WordPiece: piece -> id
+            Some(Value::Object(vocab)) => {
+                for (piece, id) in vocab {
+                    if let Some(id) = id.as_u64() {
+                        entries.push((id as usize, piece.clone(), false));
+                    }
+                }
+            }
+            // Unigram: [piece, score] at index id
+            Some(Value::Array(vocab)) => {
+                for (id, entry) in vocab.iter().enumerate() {
+                    if let Some(piece) = entry.get(0).and_then(Value::as_str) {
+                        entries.push((id, piece.to_string(), false));
+                    }
+                }
+            }
+            _ => return Err("tokenizer.json has no model.vocab".to_string()),
+        }
+        for token in tokenizer["added_tokens"].as_array().into_iter().flatten() {
+            if let (Some(id), Some(content)) = (token["id"].as_u64(), token["content"].as_str()) {
+                entries.push((id as usize, content.to_string(), true));
+            }
+        }
+
+        let len = entries.iter().map(|(id, ..)| id + 1).max().unwrap_or(0);
+        let mut pieces = vec![None; len];
+        let mut added = vec![false; len];
+        for (id, piece, is_added) in entries {
+            pieces[id] = Some(piece);
+            added[id] = is_added;
+        }
+        Ok(Self {
+            pieces,
+            added,
+            decoder: decoder_of(tokenizer),
+        })
+    }
+
+    /// Number of ids, including gaps the file leaves undefined
+    pub fn id_count(&self) -> usize {
+        self.pieces.len()
+    }
+
+    /// Text of one token on its own (leading spaces kept; partial UTF-8 lossy)
+    pub fn piece(&self, id: u64) -> Result<String, String> {
+        let mut bytes = Vec::new();
+        self.push_bytes(id, &mut bytes)?;
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    /// Text of a token sequence
+    pub fn decode(&self, ids: &[u64]) -> Result<String, String> {
+        let mut bytes = Vec::new();
+        for &id in ids {
+            self.push_bytes(id, &mut bytes)?;
+        }
+        let text = String::from_utf8_lossy(&bytes).into_owned();
+        Ok(match self.decoder {
+            Decoder::Metaspace => text.strip_prefix(' ').map(str::to_string).unwrap_or(text),
+            _ => text,
+        })
+    }
+
+    fn push_bytes(&self, id: u64, out: &mut Vec<u8>) -> Result<(), String> {
+        let index = id as usize;
+        let Some(Some(piece)) = self.pieces.get(index) else {
+            return Err(format!(
+                "Invalid arguments: token id {} is not in the vocabulary ({} ids)",
+                id,
+                self.id_count()
+            ));
+        };
+        if self.added[index] {
+            out.extend_from_slice(piece.as_bytes());
+            return Ok(());
+        }
+        match self.decoder {
+            Decoder::ByteLevel => {
+                for c in piece.chars() {
+                    match unicode_to_byte(c) {
+                        Some(b) => out.push(b),
+                        None => out.extend_from_slice(c.to_string().as_bytes()),
+                    }
+                }
+            }
+            Decoder::Metaspace => match byte_fallback(piece) {
+                Some(b) => out.push(b),
+                None => out.extend_from_slice(piece.replace(METASPACE, " ").as_bytes()),
+            },
+            Decoder::Plain => out.extend_from_slice(piece.as_bytes()),
+        }
+        Ok(())
+    }
+}
+
+/// Pick the decoder from `decoder`, falling back to `pre_tokenizer`
+fn decoder_of(tokenizer: &Value) -> Decoder {
+    let mut types = Vec::new();
+    collect_steps(&tokenizer["decoder"], &mut types);
+    if types.is_empty() {
+        collect_steps(&tokenizer["pre_tokenizer"], &mut types);
+    }
+    if types.iter().any(|(t, _)| t == "ByteLevel") {
+        Decoder::ByteLevel
+    } else if types.iter().any(|(t, step)| {
+        t == "Metaspace" || t == "ByteFallback" || (t == "Replace" && step.contains(METASPACE))
+    }) {
+        Decoder::Metaspace
+    } else {
+        Decoder::Plain
+    }
+}
+
+/// `(type, step as JSON)` of a decoder, flattening `Sequence`s
+fn collect_steps(step: &Value, out: &mut Vec<(String, String)>) {
+    let Some(kind) = step["type"].as_str() else {
+        return;
+    };
+    let nested = step
+        .get("decoders")
+        .or_else(|| step.get("pretokenizers"))
+        .and_then(Value::as_array);
+    match nested {
+        Some(steps) => steps.iter().for_each(|s| collect_steps(s, out)),
+        None => out.push((kind.to_string(), step.to_string())),
+    }
+}
+
+/// Byte of a SentencePiece `<0xNN>` piece
+fn byte_fallback(piece: &str) -> Option<u8> {
+    let hex = piece.strip_prefix("<0x")?.strip_suffix('>')?;
+    (hex.len() == 2).then(|| u8::from_str_radix(hex, 16).ok())?
+}
+
+/// Inverse of GPT-2's `bytes_to_unicode`: printable bytes stand for
+/// themselves, the rest were shifted to U+0100 onwards in byte order
+fn unicode_to_byte(c: char) -> Option<u8> {
+    let printable = |b: u8| matches!(b, b'!'..=b'~' | 0xA1..=0xAC | 0xAE..=0xFF);
+    let code = c as u32;
+    if code < 256 && printable(code as u8) {
+        return Some(code as u8);
+    }
+    let shifted = code.checked_sub(256)? as usize;
+    (0..=255u8).filter(|b| !printable(*b)).nth(shifted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn byte_level_pieces_decode_to_text() {
+        let vocab = Vocab::parse(&json!({
+            "model": { "vocab": { "Hello": 0, "\u{0120}w\u{00c3}\u{00b6}rld": 1, "\u{010a}": 2 } },
+            "added_tokens": [{ "id": 3, "content": "<|end|>" }],
+            "decoder": { "type": "ByteLevel" }
+        }))
+        .unwrap();
+        assert_eq!(vocab.decode(&[0, 1, 2, 3]).unwrap(), "Hello wörld\n<|end|>");
+        assert_eq!(vocab.piece(1).unwrap(), " wörld");
+    }
+
+    #[test]
+    fn metaspace_pieces_decode_with_byte_fallback() {
+        let vocab = Vocab::parse(&json!({
+            "model": { "vocab": [["<unk>", 0.0], ["\u{2581}Hi", -1.0], ["<0x0A>", -2.0], ["\u{2581}there", -3.0]] },
+            "decoder": { "type": "Sequence", "decoders": [
+                { "type": "Replace", "pattern": { "String": "\u{2581}" }, "content": " " },
+                { "type": "ByteFallback" }
+            ] }
+        }))
+        .unwrap();
+        assert_eq!(vocab.decode(&[1, 2, 3]).unwrap(), "Hi\n there");
+        assert_eq!(vocab.piece(1).unwrap(), " Hi");
+    }
+
+    #[test]
+    fn out_of_vocabulary_ids_are_rejected() {
+        let vocab = Vocab::parse(&json!({ "model": { "vocab": { "a": 0, "c": 2 } } })).unwrap();
+        assert_eq!(vocab.id_count(), 3);
+        assert!(vocab.decode(&[1]).unwrap_err().contains("token id 1"));
+        assert!(vocab
+            .decode(&[7])
+            .unwrap_err()
+            .starts_with("Invalid arguments"));
+    }
+}